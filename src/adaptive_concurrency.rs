@@ -0,0 +1,159 @@
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+const DEFAULT_INITIAL_LIMIT: usize = 20;
+const DEFAULT_MIN_LIMIT: usize = 1;
+const DEFAULT_MAX_LIMIT: usize = 1000;
+const DEFAULT_AIMD_LATENCY_THRESHOLD_MS: u64 = 1000;
+const DEFAULT_AIMD_BACKOFF_RATIO: f64 = 0.9;
+const DEFAULT_GRADIENT_SMOOTHING: f64 = 0.2;
+/// Samples the gradient algorithm's long-term latency average spans.
+const GRADIENT_LONG_WINDOW: f64 = 600.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConcurrencyAlgorithm {
+    /// Additive increase while responses are fast, multiplicative decrease
+    /// when one is slower than `latency_threshold` or fails.
+    Aimd {
+        latency_threshold: Duration,
+        backoff_ratio: f64,
+    },
+    /// Scales the limit by the ratio of long-term to current latency, so it
+    /// shrinks as queueing delay builds up at the workers.
+    Gradient { smoothing: f64 },
+}
+
+impl ConcurrencyAlgorithm {
+    pub fn aimd() -> Self {
+        ConcurrencyAlgorithm::Aimd {
+            latency_threshold: Duration::from_millis(DEFAULT_AIMD_LATENCY_THRESHOLD_MS),
+            backoff_ratio: DEFAULT_AIMD_BACKOFF_RATIO,
+        }
+    }
+
+    pub fn gradient() -> Self {
+        ConcurrencyAlgorithm::Gradient {
+            smoothing: DEFAULT_GRADIENT_SMOOTHING,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdaptiveConcurrencyConfig {
+    pub algorithm: ConcurrencyAlgorithm,
+    pub initial_limit: usize,
+    pub min_limit: usize,
+    pub max_limit: usize,
+}
+
+impl Default for AdaptiveConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            algorithm: ConcurrencyAlgorithm::gradient(),
+            initial_limit: DEFAULT_INITIAL_LIMIT,
+            min_limit: DEFAULT_MIN_LIMIT,
+            max_limit: DEFAULT_MAX_LIMIT,
+        }
+    }
+}
+
+/// Limits requests in flight across the worker pool, adjusting the limit
+/// from observed latency.
+pub(crate) struct AdaptiveLimiter {
+    config: AdaptiveConcurrencyConfig,
+    in_flight: AtomicUsize,
+    state: Mutex<LimiterState>,
+}
+
+struct LimiterState {
+    limit: f64,
+    long_rtt_ms: Option<f64>,
+}
+
+/// Holds one of the limiter's slots until dropped.
+pub(crate) struct LimiterPermit<'a> {
+    limiter: &'a AdaptiveLimiter,
+}
+
+impl Drop for LimiterPermit<'_> {
+    fn drop(&mut self) {
+        self.limiter.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl AdaptiveLimiter {
+    pub(crate) fn new(config: AdaptiveConcurrencyConfig) -> Self {
+        let limit = config
+            .initial_limit
+            .clamp(config.min_limit, config.max_limit) as f64;
+        Self {
+            config,
+            in_flight: AtomicUsize::new(0),
+            state: Mutex::new(LimiterState {
+                limit,
+                long_rtt_ms: None,
+            }),
+        }
+    }
+
+    pub(crate) fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit as usize
+    }
+
+    /// Takes a slot, or returns `None` if the pool is at its current limit
+    /// and the request should be shed.
+    pub(crate) fn try_acquire(&self) -> Option<LimiterPermit<'_>> {
+        let limit = self.limit();
+        self.in_flight
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |in_flight| {
+                (in_flight < limit).then_some(in_flight + 1)
+            })
+            .ok()?;
+        Some(LimiterPermit { limiter: self })
+    }
+
+    /// Adjusts the limit from one call's latency and outcome.
+    pub(crate) fn record(&self, rtt: Duration, failed: bool) {
+        let mut state = self.state.lock().unwrap();
+        let limit = state.limit;
+        let new_limit = match &self.config.algorithm {
+            ConcurrencyAlgorithm::Aimd {
+                latency_threshold,
+                backoff_ratio,
+            } => {
+                if failed || rtt > *latency_threshold {
+                    limit * backoff_ratio
+                } else if self.in_flight.load(Ordering::SeqCst) as f64 >= limit / 2.0 {
+                    // Only grow while the limit is actually being used
+                    limit + 1.0
+                } else {
+                    limit
+                }
+            }
+            ConcurrencyAlgorithm::Gradient { smoothing } => {
+                let rtt_ms = rtt.as_secs_f64() * 1000.0;
+                let long_rtt_ms = match state.long_rtt_ms {
+                    Some(long) => long + (rtt_ms - long) * (2.0 / (GRADIENT_LONG_WINDOW + 1.0)),
+                    None => rtt_ms,
+                };
+                state.long_rtt_ms = Some(long_rtt_ms);
+
+                let gradient = if failed {
+                    0.5
+                } else {
+                    (long_rtt_ms / rtt_ms.max(f64::EPSILON)).clamp(0.5, 1.0)
+                };
+                // Allow some queueing so the limit can still probe upwards
+                let queue_size = limit.sqrt();
+                let target = limit * gradient + queue_size;
+                limit * (1.0 - smoothing) + target * smoothing
+            }
+        };
+        state.limit = new_limit.clamp(self.config.min_limit as f64, self.config.max_limit as f64);
+    }
+}
@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 
+use tracing::debug;
+
 use crate::Worker;
 
 pub trait BalancingAlgorithm: Send + Sync {
@@ -16,7 +18,7 @@ pub enum AlgorithmType {
     LeastConnections,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct RoundRobinAlgorithm {
     current_index: usize,
 }
@@ -31,7 +33,7 @@ impl BalancingAlgorithm for RoundRobinAlgorithm {
     fn choose<'a>(&mut self, workers: &'a [Worker]) -> &'a Worker {
         let worker = &workers[self.current_index % workers.len()];
         self.current_index = (self.current_index + 1) % workers.len();
-        println!("Chosen worker: {}", worker.host);
+        debug!(worker = %worker.host, "Chosen worker");
         worker
     }
     fn get_type(&self) -> AlgorithmType {
@@ -64,7 +66,7 @@ impl BalancingAlgorithm for LeastConnectionsAlgorithm {
             if let Some(counter) = self.connection_map.get_mut(&chosen_worker.host) {
                 *counter += 1;
             }
-            println!("Chosen worker: {}", chosen_worker.host); // TODO: move over to logging
+            debug!(worker = %chosen_worker.host, "Chosen worker");
             return chosen_worker;
         }
         panic!("There are no workers setup!")
@@ -75,10 +77,7 @@ impl BalancingAlgorithm for LeastConnectionsAlgorithm {
             if *counter > 0 {
                 *counter -= 1;
             }
-            println!(
-                "Released worker: {}, current connections: {}",
-                worker.host, *counter
-            );
+            debug!(worker = %worker.host, connections = *counter, "Released worker");
         }
     }
 
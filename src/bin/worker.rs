@@ -8,10 +8,16 @@ use hyper::{
 };
 use hyper_util::rt::{TokioExecutor, TokioIo};
 use hyper_util::server::conn::auto::Builder;
+use load_balancer::LogConfig;
 use tokio::{net::TcpListener, task};
+use tracing::{info, warn};
 
 #[tokio::main]
 async fn main() {
+    LogConfig::from_env()
+        .init()
+        .expect("failed to initialize logging");
+
     let port = env::args()
         .nth(1)
         .and_then(|port| port.parse().ok())
@@ -19,7 +25,7 @@ async fn main() {
         .unwrap_or(3000);
 
     let addr = SocketAddr::from(([127, 0, 0, 1], port));
-    println!("worker listening on http://{}", addr);
+    info!(%addr, "Worker listening");
 
     let listener = TcpListener::bind(addr)
         .await
@@ -34,7 +40,7 @@ async fn main() {
             let builder = Builder::new(TokioExecutor::new());
 
             if let Err(err) = builder.serve_connection(io, service).await {
-                eprintln!("worker connection error: {err}");
+                warn!(error = %err, "Worker connection error");
             }
         });
     }
@@ -53,13 +59,41 @@ async fn worker_service(
             .map(|pq| pq.as_str())
             .unwrap_or("/")
     );
-    println!("{}", message);
+    info!(
+        method = %req.method(),
+        path = %req.uri().path(),
+        request_id = req
+            .headers()
+            .get("x-request-id")
+            .and_then(|id| id.to_str().ok()),
+        "Received request"
+    );
 
     match (req.method(), req.uri().path()) {
         (&Method::GET, "/health") => Ok(Response::builder()
             .status(StatusCode::OK)
             .body(Full::new(Bytes::from("Health Status - OK\r\n")))
             .expect("response builder")),
+        // Echoes the request headers, and answers with hop-by-hop headers of
+        // its own like an older HTTP/1.1 server might
+        (&Method::GET, "/headers") => {
+            let mut body = String::new();
+            for (name, value) in req.headers() {
+                body.push_str(&format!(
+                    "{}: {}\n",
+                    name,
+                    value.to_str().unwrap_or("<binary>")
+                ));
+            }
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("connection", "keep-alive, x-worker-hop")
+                .header("keep-alive", "timeout=5")
+                .header("x-worker-hop", "1")
+                .header("proxy-authenticate", "Basic realm=\"worker\"")
+                .body(Full::new(Bytes::from(body)))
+                .expect("response builder"))
+        }
         (&Method::GET, "/heavy_work") => {
             tokio::time::sleep(Duration::from_secs(10)).await;
 
@@ -0,0 +1,244 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::Worker;
+
+const DEFAULT_FAILURE_RATE_THRESHOLD: f64 = 0.5;
+const DEFAULT_SLOW_CALL_RATE_THRESHOLD: f64 = 0.8;
+const DEFAULT_SLOW_CALL_DURATION_SECS: u64 = 5;
+const DEFAULT_MINIMUM_CALLS: usize = 10;
+const DEFAULT_WINDOW_SIZE: usize = 20;
+const DEFAULT_OPEN_DURATION_SECS: u64 = 30;
+const DEFAULT_HALF_OPEN_MAX_CALLS: usize = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CircuitBreakerConfig {
+    /// Fraction of failed calls in the window that opens the circuit.
+    pub failure_rate_threshold: f64,
+    /// Fraction of slow calls in the window that opens the circuit.
+    pub slow_call_rate_threshold: f64,
+    /// Calls taking at least this long count as slow.
+    pub slow_call_duration: Duration,
+    /// Calls needed in the window before the rates are evaluated.
+    pub minimum_calls: usize,
+    /// Number of most recent calls the rates are computed over.
+    pub window_size: usize,
+    /// How long the circuit stays open before letting probe requests through.
+    pub open_duration: Duration,
+    /// Probe requests allowed while half-open; all must succeed to close.
+    pub half_open_max_calls: usize,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_rate_threshold: DEFAULT_FAILURE_RATE_THRESHOLD,
+            slow_call_rate_threshold: DEFAULT_SLOW_CALL_RATE_THRESHOLD,
+            slow_call_duration: Duration::from_secs(DEFAULT_SLOW_CALL_DURATION_SECS),
+            minimum_calls: DEFAULT_MINIMUM_CALLS,
+            window_size: DEFAULT_WINDOW_SIZE,
+            open_duration: Duration::from_secs(DEFAULT_OPEN_DURATION_SECS),
+            half_open_max_calls: DEFAULT_HALF_OPEN_MAX_CALLS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CallOutcome {
+    failed: bool,
+    slow: bool,
+}
+
+struct CircuitBreaker {
+    state: CircuitState,
+    window: VecDeque<CallOutcome>,
+    opened_at: Instant,
+    half_open_started: usize,
+    half_open_succeeded: usize,
+    /// Bumped on every transition, so probes from an earlier half-open
+    /// period don't release slots in a later one.
+    generation: u64,
+}
+
+impl CircuitBreaker {
+    fn new() -> Self {
+        Self {
+            state: CircuitState::Closed,
+            window: VecDeque::new(),
+            opened_at: Instant::now(),
+            half_open_started: 0,
+            half_open_succeeded: 0,
+            generation: 0,
+        }
+    }
+
+    fn transition(&mut self, state: CircuitState) -> Option<CircuitState> {
+        if self.state == state {
+            return None;
+        }
+        self.state = state;
+        self.window.clear();
+        self.half_open_started = 0;
+        self.half_open_succeeded = 0;
+        self.generation += 1;
+        if state == CircuitState::Open {
+            self.opened_at = Instant::now();
+        }
+        Some(state)
+    }
+}
+
+/// A worker whose circuit can't take another call right now.
+pub struct HalfOpenFull;
+
+/// One circuit breaker per worker, keyed by host.
+pub struct CircuitBreakers {
+    config: CircuitBreakerConfig,
+    breakers: HashMap<String, Arc<Mutex<CircuitBreaker>>>,
+}
+
+impl CircuitBreakers {
+    pub fn new(workers: &[Worker], config: CircuitBreakerConfig) -> Self {
+        let breakers = workers
+            .iter()
+            .map(|worker| {
+                (
+                    worker.host.clone(),
+                    Arc::new(Mutex::new(CircuitBreaker::new())),
+                )
+            })
+            .collect();
+        Self { config, breakers }
+    }
+
+    /// Whether `host` may be chosen for a request. An open circuit moves to
+    /// half-open here once its open duration has passed.
+    pub fn is_available(&self, host: &str) -> (bool, Option<CircuitState>) {
+        let Some(breaker) = self.breakers.get(host) else {
+            return (true, None);
+        };
+        let mut breaker = breaker.lock().unwrap();
+        let mut transition = None;
+        if breaker.state == CircuitState::Open
+            && breaker.opened_at.elapsed() >= self.config.open_duration
+        {
+            transition = breaker.transition(CircuitState::HalfOpen);
+        }
+        let available = match breaker.state {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            CircuitState::HalfOpen => breaker.half_open_started < self.config.half_open_max_calls,
+        };
+        (available, transition)
+    }
+
+    /// Takes one of `host`'s half-open probe slots, checking and counting
+    /// under the same lock so concurrent requests can't overshoot the limit.
+    /// The returned guard gives the slot back if the probe is dropped before
+    /// its outcome is recorded; `None` means the call isn't a probe.
+    pub fn on_call_started(&self, host: &str) -> Result<Option<ProbeGuard>, HalfOpenFull> {
+        let Some(shared) = self.breakers.get(host) else {
+            return Ok(None);
+        };
+        let mut breaker = shared.lock().unwrap();
+        match breaker.state {
+            CircuitState::Closed => Ok(None),
+            CircuitState::HalfOpen
+                if breaker.half_open_started < self.config.half_open_max_calls =>
+            {
+                breaker.half_open_started += 1;
+                Ok(Some(ProbeGuard {
+                    breaker: shared.clone(),
+                    generation: breaker.generation,
+                    recorded: false,
+                }))
+            }
+            // Reopened, or every probe slot taken, since the worker was chosen
+            CircuitState::HalfOpen | CircuitState::Open => Err(HalfOpenFull),
+        }
+    }
+
+    /// Records the outcome of a call, returning the new state if it changed.
+    pub fn record(&self, host: &str, failed: bool, duration: Duration) -> Option<CircuitState> {
+        let breaker = self.breakers.get(host)?;
+        let mut breaker = breaker.lock().unwrap();
+        let outcome = CallOutcome {
+            failed,
+            slow: duration >= self.config.slow_call_duration,
+        };
+
+        match breaker.state {
+            CircuitState::Closed => {
+                breaker.window.push_back(outcome);
+                while breaker.window.len() > self.config.window_size {
+                    breaker.window.pop_front();
+                }
+                if breaker.window.len() < self.config.minimum_calls {
+                    return None;
+                }
+                let calls = breaker.window.len() as f64;
+                let failures = breaker.window.iter().filter(|o| o.failed).count() as f64;
+                let slow_calls = breaker.window.iter().filter(|o| o.slow).count() as f64;
+                if failures / calls >= self.config.failure_rate_threshold
+                    || slow_calls / calls >= self.config.slow_call_rate_threshold
+                {
+                    return breaker.transition(CircuitState::Open);
+                }
+                None
+            }
+            CircuitState::HalfOpen => {
+                if outcome.failed || outcome.slow {
+                    return breaker.transition(CircuitState::Open);
+                }
+                breaker.half_open_succeeded += 1;
+                if breaker.half_open_succeeded >= self.config.half_open_max_calls {
+                    return breaker.transition(CircuitState::Closed);
+                }
+                None
+            }
+            // Calls that were in flight when the circuit opened
+            CircuitState::Open => None,
+        }
+    }
+
+    pub fn state(&self, host: &str) -> Option<CircuitState> {
+        Some(self.breakers.get(host)?.lock().unwrap().state)
+    }
+}
+
+/// A half-open probe in flight, released unless its outcome is recorded.
+pub struct ProbeGuard {
+    breaker: Arc<Mutex<CircuitBreaker>>,
+    generation: u64,
+    recorded: bool,
+}
+
+impl ProbeGuard {
+    /// Keeps the slot taken, once [`CircuitBreakers::record`] has counted
+    /// the probe's outcome.
+    pub fn recorded(mut self) {
+        self.recorded = true;
+    }
+}
+
+impl Drop for ProbeGuard {
+    fn drop(&mut self) {
+        if self.recorded {
+            return;
+        }
+        let mut breaker = self.breaker.lock().unwrap();
+        if breaker.generation == self.generation && breaker.half_open_started > 0 {
+            breaker.half_open_started -= 1;
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::time::Duration;
+
+use crate::{
+    adaptive_concurrency::AdaptiveConcurrencyConfig,
+    circuit_breaker::CircuitBreakerConfig,
+    forwarding::ForwardingConfig,
+    grpc::GrpcConfig,
+    hedging::HedgingConfig,
+    load_shedding::LoadSheddingConfig,
+    rate_limit::RateLimitConfig,
+    request_id::RequestIdConfig,
+    request_queue::QueueConfig,
+    retries::RetryConfig,
+    timeouts::{RouteTimeouts, TimeoutConfig},
+    upstream::UpstreamPoolConfig,
+};
+
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 5;
+
+#[derive(Debug, Clone)]
+pub struct LoadBalancerConfig {
+    /// Applies to every connection opened to a worker.
+    pub connect_timeout: Option<Duration>,
+    /// Connection pooling to the workers, for both HTTP/1.1 and HTTP/2.
+    pub upstream_pool: UpstreamPoolConfig,
+    /// Default timeouts for requests proxied to the worker pool.
+    pub timeouts: TimeoutConfig,
+    /// Overrides for requests whose path starts with the route's prefix.
+    /// The first matching route wins.
+    pub route_timeouts: Vec<RouteTimeouts>,
+    pub retries: RetryConfig,
+    /// Retries and health checks for gRPC traffic.
+    pub grpc: GrpcConfig,
+    /// `X-Forwarded-*`, `Forwarded` and `Via` headers added to requests.
+    pub forwarding: ForwardingConfig,
+    /// How requests are tagged with an ID shared with workers and clients.
+    pub request_id: RequestIdConfig,
+    /// Per-worker circuit breakers; `None` disables them.
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Hedging for latency-sensitive GET routes; `None` disables it.
+    pub hedging: Option<HedgingConfig>,
+    /// Token bucket limits applied before a request is proxied.
+    pub rate_limit: RateLimitConfig,
+    /// Where requests wait when every worker is at its `max_connections`;
+    /// `None` rejects them with 503 straight away.
+    pub queue: Option<QueueConfig>,
+    /// Pool-wide in-flight limit adapted from observed latency; requests
+    /// over the limit are shed with 503. `None` disables it.
+    pub adaptive_concurrency: Option<AdaptiveConcurrencyConfig>,
+    /// Sheds low-priority requests with 503 when the balancer is
+    /// overloaded. `None` disables it.
+    pub load_shedding: Option<LoadSheddingConfig>,
+}
+
+impl Default for LoadBalancerConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Some(Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS)),
+            upstream_pool: UpstreamPoolConfig::default(),
+            timeouts: TimeoutConfig::default(),
+            route_timeouts: Vec::new(),
+            retries: RetryConfig::default(),
+            grpc: GrpcConfig::default(),
+            forwarding: ForwardingConfig::default(),
+            request_id: RequestIdConfig::default(),
+            circuit_breaker: None,
+            hedging: None,
+            rate_limit: RateLimitConfig::default(),
+            queue: None,
+            adaptive_concurrency: None,
+            load_shedding: None,
+        }
+    }
+}
+
+impl LoadBalancerConfig {
+    pub(crate) fn timeouts_for(&self, path: &str) -> TimeoutConfig {
+        self.route_timeouts
+            .iter()
+            .find(|route| path.starts_with(&route.path_prefix))
+            .map(|route| route.timeouts.or(&self.timeouts))
+            .unwrap_or_else(|| self.timeouts.clone())
+    }
+}
@@ -0,0 +1,134 @@
+//! The headers that tell workers about the client behind the balancer:
+//! `X-Forwarded-For`, `X-Forwarded-Proto`, `X-Forwarded-Host`, RFC 7239
+//! `Forwarded` and `Via`.
+
+use std::net::IpAddr;
+
+use hyper::{
+    HeaderMap, Version,
+    header::{FORWARDED, HOST, HeaderName, HeaderValue, VIA},
+    http::request::Parts,
+};
+use ipnet::IpNet;
+
+use crate::ConnectionInfo;
+
+const DEFAULT_VIA_PSEUDONYM: &str = "load-balancer";
+
+static X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+static X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+static X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+
+#[derive(Debug, Clone)]
+pub struct ForwardingConfig {
+    /// Clients whose own forwarding headers are kept and appended to. From
+    /// anyone else they are replaced, since they could be forged.
+    pub trusted_proxies: Vec<IpNet>,
+    /// How the balancer names itself in `Via`; `None` leaves `Via` alone.
+    pub via_pseudonym: Option<String>,
+}
+
+impl Default for ForwardingConfig {
+    fn default() -> Self {
+        Self {
+            trusted_proxies: Vec::new(),
+            via_pseudonym: Some(DEFAULT_VIA_PSEUDONYM.to_string()),
+        }
+    }
+}
+
+impl ForwardingConfig {
+    pub(crate) fn is_trusted(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.trusted_proxies.iter().any(|net| net.contains(&ip))
+    }
+
+    /// Adds this hop to the request's forwarding headers.
+    pub(crate) fn apply(&self, parts: &mut Parts) {
+        let info = parts.extensions.get::<ConnectionInfo>();
+        let client_ip = info.map(|info| info.peer_addr.ip().to_canonical());
+        let proto = if info.is_some_and(|info| info.tls) {
+            "https"
+        } else {
+            "http"
+        };
+        let host = parts
+            .headers
+            .get(HOST)
+            .and_then(|host| host.to_str().ok())
+            .map(str::to_string)
+            .or_else(|| parts.uri.authority().map(|authority| authority.to_string()));
+        let headers = &mut parts.headers;
+
+        if !client_ip.is_some_and(|ip| self.is_trusted(ip)) {
+            for name in [&X_FORWARDED_FOR, &X_FORWARDED_PROTO, &X_FORWARDED_HOST] {
+                headers.remove(name);
+            }
+            headers.remove(FORWARDED);
+        }
+
+        if let Some(ip) = client_ip {
+            append(headers, &X_FORWARDED_FOR, &ip.to_string());
+        }
+        if !headers.contains_key(&X_FORWARDED_PROTO) {
+            headers.insert(&X_FORWARDED_PROTO, HeaderValue::from_static(proto));
+        }
+        if !headers.contains_key(&X_FORWARDED_HOST)
+            && let Some(host) = host.as_deref().and_then(|host| host.parse().ok())
+        {
+            headers.insert(&X_FORWARDED_HOST, host);
+        }
+
+        let mut element = Vec::new();
+        if let Some(ip) = client_ip {
+            element.push(match ip {
+                IpAddr::V4(ip) => format!("for={}", ip),
+                IpAddr::V6(ip) => format!("for=\"[{}]\"", ip),
+            });
+        }
+        if let Some(host) = &host {
+            element.push(format!("host={}", quote_if_needed(host)));
+        }
+        element.push(format!("proto={}", proto));
+        append(headers, &FORWARDED, &element.join(";"));
+
+        if let Some(pseudonym) = &self.via_pseudonym {
+            let version = match parts.version {
+                Version::HTTP_09 => "0.9",
+                Version::HTTP_10 => "1.0",
+                Version::HTTP_2 => "2",
+                Version::HTTP_3 => "3",
+                _ => "1.1",
+            };
+            append(headers, &VIA, &format!("{} {}", version, pseudonym));
+        }
+    }
+}
+
+/// Appends `value` to the comma-separated list in `name`, joining repeated
+/// headers into one.
+fn append(headers: &mut HeaderMap, name: &HeaderName, value: &str) {
+    let mut values: Vec<String> = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .map(str::to_string)
+        .collect();
+    values.push(value.to_string());
+    if let Ok(value) = HeaderValue::from_str(&values.join(", ")) {
+        headers.insert(name, value);
+    }
+}
+
+/// Quotes a `Forwarded` parameter value unless it is a plain token.
+fn quote_if_needed(value: &str) -> String {
+    let is_token = !value.is_empty()
+        && value
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte));
+    if is_token {
+        value.to_string()
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
@@ -0,0 +1,387 @@
+//! gRPC awareness on top of plain HTTP/2 proxying: status codes from
+//! headers and trailers, retries on retryable codes, and active health
+//! checks using the gRPC health checking protocol (`grpc.health.v1`).
+
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::{
+        Arc, Weak,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    HeaderMap, Method, Request, Uri,
+    body::{Body, Bytes, Frame, SizeHint},
+    header::{CONTENT_TYPE, HeaderValue, TE},
+};
+use tracing::{info, warn};
+
+use crate::{
+    Worker,
+    upstream::{UpstreamBody, UpstreamClients},
+};
+
+const DEFAULT_HEALTH_CHECK_INTERVAL_SECS: u64 = 5;
+const DEFAULT_HEALTH_CHECK_TIMEOUT_SECS: u64 = 1;
+const HEALTH_CHECK_PATH: &str = "/grpc.health.v1.Health/Check";
+/// `HealthCheckResponse.ServingStatus.SERVING`
+const SERVING: u64 = 1;
+
+/// gRPC status codes, as sent in the `grpc-status` trailer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GrpcCode {
+    Ok,
+    Cancelled,
+    Unknown,
+    InvalidArgument,
+    DeadlineExceeded,
+    NotFound,
+    AlreadyExists,
+    PermissionDenied,
+    ResourceExhausted,
+    FailedPrecondition,
+    Aborted,
+    OutOfRange,
+    Unimplemented,
+    Internal,
+    Unavailable,
+    DataLoss,
+    Unauthenticated,
+}
+
+impl GrpcCode {
+    const ALL: [GrpcCode; 17] = [
+        GrpcCode::Ok,
+        GrpcCode::Cancelled,
+        GrpcCode::Unknown,
+        GrpcCode::InvalidArgument,
+        GrpcCode::DeadlineExceeded,
+        GrpcCode::NotFound,
+        GrpcCode::AlreadyExists,
+        GrpcCode::PermissionDenied,
+        GrpcCode::ResourceExhausted,
+        GrpcCode::FailedPrecondition,
+        GrpcCode::Aborted,
+        GrpcCode::OutOfRange,
+        GrpcCode::Unimplemented,
+        GrpcCode::Internal,
+        GrpcCode::Unavailable,
+        GrpcCode::DataLoss,
+        GrpcCode::Unauthenticated,
+    ];
+
+    pub fn from_code(code: u32) -> Option<Self> {
+        Self::ALL.get(code as usize).copied()
+    }
+
+    /// The canonical name, e.g. `UNAVAILABLE`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GrpcCode::Ok => "OK",
+            GrpcCode::Cancelled => "CANCELLED",
+            GrpcCode::Unknown => "UNKNOWN",
+            GrpcCode::InvalidArgument => "INVALID_ARGUMENT",
+            GrpcCode::DeadlineExceeded => "DEADLINE_EXCEEDED",
+            GrpcCode::NotFound => "NOT_FOUND",
+            GrpcCode::AlreadyExists => "ALREADY_EXISTS",
+            GrpcCode::PermissionDenied => "PERMISSION_DENIED",
+            GrpcCode::ResourceExhausted => "RESOURCE_EXHAUSTED",
+            GrpcCode::FailedPrecondition => "FAILED_PRECONDITION",
+            GrpcCode::Aborted => "ABORTED",
+            GrpcCode::OutOfRange => "OUT_OF_RANGE",
+            GrpcCode::Unimplemented => "UNIMPLEMENTED",
+            GrpcCode::Internal => "INTERNAL",
+            GrpcCode::Unavailable => "UNAVAILABLE",
+            GrpcCode::DataLoss => "DATA_LOSS",
+            GrpcCode::Unauthenticated => "UNAUTHENTICATED",
+        }
+    }
+
+    /// The `grpc-status` in `headers`, which are trailers or the headers of
+    /// a trailers-only response.
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let code = headers.get("grpc-status")?.to_str().ok()?.parse().ok()?;
+        Self::from_code(code)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrpcConfig {
+    /// Codes retried under the usual [`RetryConfig`](crate::RetryConfig)
+    /// limits and budget, whatever the method. Only trailers-only responses
+    /// can be retried; once a worker streams a response it is passed on.
+    pub retry_on: Vec<GrpcCode>,
+    pub health_check: Option<GrpcHealthCheckConfig>,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        Self {
+            retry_on: vec![GrpcCode::Unavailable],
+            health_check: None,
+        }
+    }
+}
+
+/// Active health checks with `grpc.health.v1.Health/Check`. Workers must
+/// speak HTTP/2; one that doesn't answer `SERVING` stops receiving requests
+/// until a later check succeeds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrpcHealthCheckConfig {
+    /// Service to ask about; empty asks about the server as a whole.
+    pub service: String,
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for GrpcHealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            service: String::new(),
+            interval: Duration::from_secs(DEFAULT_HEALTH_CHECK_INTERVAL_SECS),
+            timeout: Duration::from_secs(DEFAULT_HEALTH_CHECK_TIMEOUT_SECS),
+        }
+    }
+}
+
+pub(crate) fn is_grpc(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/grpc"))
+}
+
+/// A gRPC response body that reports the status from its trailers, or
+/// `UNKNOWN` if it ends without one.
+pub(crate) struct GrpcStatusBody<B> {
+    inner: B,
+    on_status: Option<Box<dyn FnOnce(GrpcCode) + Send + Sync>>,
+}
+
+impl<B> GrpcStatusBody<B> {
+    pub(crate) fn new(inner: B, on_status: impl FnOnce(GrpcCode) + Send + Sync + 'static) -> Self {
+        Self {
+            inner,
+            on_status: Some(Box::new(on_status)),
+        }
+    }
+
+    fn report(&mut self, code: GrpcCode) {
+        if let Some(on_status) = self.on_status.take() {
+            on_status(code);
+        }
+    }
+}
+
+impl<B> Body for GrpcStatusBody<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let frame = Pin::new(&mut self.inner).poll_frame(cx);
+        match &frame {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(trailers) = frame.trailers_ref() {
+                    let code = GrpcCode::from_headers(trailers).unwrap_or(GrpcCode::Unknown);
+                    self.report(code);
+                }
+            }
+            Poll::Ready(None) => self.report(GrpcCode::Unknown),
+            _ => {}
+        }
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// The latest health check result for each worker. Workers are healthy
+/// until a check says otherwise.
+pub(crate) struct HealthChecks {
+    healthy: HashMap<String, AtomicBool>,
+}
+
+impl HealthChecks {
+    /// Starts checking `workers` in the background until the returned
+    /// handle is dropped. Needs a Tokio runtime.
+    pub(crate) fn start(
+        workers: &[Worker],
+        upstream: Arc<UpstreamClients>,
+        config: GrpcHealthCheckConfig,
+    ) -> Result<Arc<Self>, String> {
+        let runtime = tokio::runtime::Handle::try_current()
+            .map_err(|_| "gRPC health checks need a Tokio runtime".to_string())?;
+        let checks = Arc::new(Self {
+            healthy: workers
+                .iter()
+                .map(|worker| (worker.host.clone(), AtomicBool::new(true)))
+                .collect(),
+        });
+        runtime.spawn(run(
+            Arc::downgrade(&checks),
+            workers.to_vec(),
+            upstream,
+            config,
+        ));
+        Ok(checks)
+    }
+
+    pub(crate) fn is_healthy(&self, host: &str) -> bool {
+        self.healthy
+            .get(host)
+            .is_none_or(|healthy| healthy.load(Ordering::SeqCst))
+    }
+}
+
+async fn run(
+    checks: Weak<HealthChecks>,
+    workers: Vec<Worker>,
+    upstream: Arc<UpstreamClients>,
+    config: GrpcHealthCheckConfig,
+) {
+    let mut interval = tokio::time::interval(config.interval);
+    loop {
+        interval.tick().await;
+        for worker in &workers {
+            let serving = check(worker, &upstream, &config).await;
+            let Some(checks) = checks.upgrade() else {
+                return;
+            };
+            let was_healthy = checks.healthy[&worker.host].swap(serving.is_ok(), Ordering::SeqCst);
+            match serving {
+                Ok(()) if !was_healthy => info!(worker = %worker.host, "Worker is healthy again"),
+                Err(e) if was_healthy => {
+                    warn!(worker = %worker.host, error = %e, "Worker is unhealthy")
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Asks `worker` whether it is serving, under the configured timeout.
+async fn check(
+    worker: &Worker,
+    upstream: &UpstreamClients,
+    config: &GrpcHealthCheckConfig,
+) -> Result<(), String> {
+    let uri = Uri::try_from(format!("{}{}", worker.host, HEALTH_CHECK_PATH))
+        .map_err(|e| e.to_string())?;
+    let body: UpstreamBody = Full::new(health_check_request(&config.service))
+        .map_err(|infallible| match infallible {})
+        .boxed();
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header(CONTENT_TYPE, HeaderValue::from_static("application/grpc"))
+        .header(TE, HeaderValue::from_static("trailers"))
+        .body(body)
+        .expect("health check request");
+
+    let (response, _stream) = upstream.request(worker, req);
+    let call = async {
+        let response = response.await.map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("status {}", response.status()));
+        }
+        let trailers_only = GrpcCode::from_headers(response.headers());
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .map_err(|e| e.to_string())?;
+        let code = trailers_only
+            .or_else(|| body.trailers().and_then(GrpcCode::from_headers))
+            .unwrap_or(GrpcCode::Unknown);
+        if code != GrpcCode::Ok {
+            return Err(format!("gRPC status {}", code.as_str()));
+        }
+        match serving_status(&body.to_bytes()) {
+            SERVING => Ok(()),
+            status => Err(format!("serving status {}", status)),
+        }
+    };
+    tokio::time::timeout(config.timeout, call)
+        .await
+        .map_err(|_| "timed out".to_string())?
+}
+
+/// A length-prefixed `HealthCheckRequest { service }` message.
+fn health_check_request(service: &str) -> Bytes {
+    let mut message = Vec::new();
+    if !service.is_empty() {
+        // Field 1, length-delimited
+        message.push(0x0a);
+        push_varint(&mut message, service.len() as u64);
+        message.extend_from_slice(service.as_bytes());
+    }
+    let mut framed = vec![0];
+    framed.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    framed.extend_from_slice(&message);
+    Bytes::from(framed)
+}
+
+/// The `status` field of a length-prefixed `HealthCheckResponse`, 0
+/// (`UNKNOWN`) if it is missing or the message can't be read.
+fn serving_status(framed: &[u8]) -> u64 {
+    let Some(mut message) = framed.get(5..) else {
+        return 0;
+    };
+    while let Some((&key, rest)) = message.split_first() {
+        message = rest;
+        let Some(value) = read_varint(&mut message) else {
+            return 0;
+        };
+        match key {
+            // Field 1, varint
+            0x08 => return value,
+            // Any other varint field
+            key if key & 0x07 == 0 => {}
+            // A length-delimited field
+            key if key & 0x07 == 2 => match message.get(value as usize..) {
+                Some(rest) => message = rest,
+                None => return 0,
+            },
+            _ => return 0,
+        }
+    }
+    0
+}
+
+fn push_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn read_varint(buf: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buf.split_first()?;
+        *buf = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
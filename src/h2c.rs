@@ -0,0 +1,233 @@
+//! Cleartext HTTP/2 via the HTTP/1.1 `Upgrade: h2c` mechanism (RFC 7540
+//! section 3.2). hyper only speaks HTTP/2 with prior knowledge, so after
+//! answering 101 we replay the upgrade request as stream 1 by splicing a
+//! HEADERS frame into the connection right after the client's preface.
+
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use hyper::{
+    Request, Response, StatusCode, Version,
+    body::{Body, Incoming},
+    header::{CONNECTION, HOST, HeaderValue, UPGRADE},
+    http::request::Parts,
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::ResponseBody;
+
+const PREFACE_LEN: usize = 24;
+const FRAME_HEADER_LEN: usize = 9;
+/// The smallest SETTINGS_MAX_FRAME_SIZE a peer may advertise.
+const MAX_FRAME_SIZE: usize = 16_384;
+const FRAME_HEADERS: u8 = 0x1;
+const FRAME_CONTINUATION: u8 = 0x9;
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+
+/// Headers that only apply to the HTTP/1.1 connection.
+const CONNECTION_HEADERS: [&str; 6] = [
+    "connection",
+    "http2-settings",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Whether `req` asks to switch to h2c. Requests with a body are served
+/// over HTTP/1.1 instead, as the body would have to be replayed within the
+/// client's initial flow-control window.
+pub(crate) fn is_upgrade_request(req: &Request<Incoming>) -> bool {
+    let has_token = |name, token: &str| {
+        req.headers().get_all(name).iter().any(|value| {
+            value.to_str().is_ok_and(|value| {
+                value
+                    .split(',')
+                    .any(|part| part.trim().eq_ignore_ascii_case(token))
+            })
+        })
+    };
+    req.version() == Version::HTTP_11
+        && has_token(UPGRADE, "h2c")
+        && has_token(CONNECTION, "http2-settings")
+        && req.headers().contains_key("http2-settings")
+        && req.body().is_end_stream()
+}
+
+pub(crate) fn switching_protocols() -> Response<ResponseBody> {
+    let mut response = Response::new(ResponseBody::default());
+    *response.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
+    response
+        .headers_mut()
+        .insert(CONNECTION, HeaderValue::from_static("Upgrade"));
+    response
+        .headers_mut()
+        .insert(UPGRADE, HeaderValue::from_static("h2c"));
+    response
+}
+
+/// Encodes the upgrade request as the HEADERS (and CONTINUATION) frames of
+/// stream 1.
+pub(crate) fn replay_frames(parts: &Parts) -> Vec<u8> {
+    let mut block = Vec::new();
+    let authority = parts
+        .headers
+        .get(HOST)
+        .map_or(&b""[..], |host| host.as_bytes());
+    let path = parts
+        .uri
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str());
+    encode_header(&mut block, b":method", parts.method.as_str().as_bytes());
+    encode_header(&mut block, b":scheme", b"http");
+    encode_header(&mut block, b":authority", authority);
+    encode_header(&mut block, b":path", path.as_bytes());
+    for (name, value) in &parts.headers {
+        if name == HOST || CONNECTION_HEADERS.contains(&name.as_str()) {
+            continue;
+        }
+        if name == "te" && value != "trailers" {
+            continue;
+        }
+        encode_header(&mut block, name.as_str().as_bytes(), value.as_bytes());
+    }
+
+    let mut frames = Vec::new();
+    let chunks: Vec<&[u8]> = block.chunks(MAX_FRAME_SIZE).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let (frame_type, mut flags) = if i == 0 {
+            (FRAME_HEADERS, FLAG_END_STREAM)
+        } else {
+            (FRAME_CONTINUATION, 0)
+        };
+        if i == chunks.len() - 1 {
+            flags |= FLAG_END_HEADERS;
+        }
+        frames.extend_from_slice(&(chunk.len() as u32).to_be_bytes()[1..]);
+        frames.push(frame_type);
+        frames.push(flags);
+        frames.extend_from_slice(&1u32.to_be_bytes());
+        frames.extend_from_slice(chunk);
+    }
+    frames
+}
+
+/// HPACK literal header field without indexing, with a literal name.
+fn encode_header(block: &mut Vec<u8>, name: &[u8], value: &[u8]) {
+    block.push(0);
+    encode_string(block, name);
+    encode_string(block, value);
+}
+
+fn encode_string(block: &mut Vec<u8>, value: &[u8]) {
+    // No Huffman coding, so the length has a 7-bit prefix
+    const PREFIX_MAX: usize = 0x7f;
+    let mut len = value.len();
+    if len < PREFIX_MAX {
+        block.push(len as u8);
+    } else {
+        block.push(PREFIX_MAX as u8);
+        len -= PREFIX_MAX;
+        while len >= 0x80 {
+            block.push((len % 0x80) as u8 | 0x80);
+            len /= 0x80;
+        }
+        block.push(len as u8);
+    }
+    block.extend_from_slice(value);
+}
+
+/// An upgraded connection that feeds the replayed request to the HTTP/2
+/// server after the client's preface and first SETTINGS frame.
+pub(crate) struct UpgradedIo<T> {
+    inner: T,
+    replay: Option<Vec<u8>>,
+    buffered: Vec<u8>,
+}
+
+impl<T> UpgradedIo<T> {
+    pub(crate) fn new(inner: T, replay: Vec<u8>) -> Self {
+        Self {
+            inner,
+            replay: Some(replay),
+            buffered: Vec::new(),
+        }
+    }
+}
+
+/// Where the first frame after the connection preface ends, once it has
+/// been read in full.
+fn first_frame_end(buffered: &[u8]) -> Option<usize> {
+    let header = buffered.get(PREFACE_LEN..PREFACE_LEN + FRAME_HEADER_LEN)?;
+    let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+    let end = PREFACE_LEN + FRAME_HEADER_LEN + len;
+    (buffered.len() >= end).then_some(end)
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for UpgradedIo<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while this.replay.is_some() {
+            if let Some(end) = first_frame_end(&this.buffered) {
+                let replay = this.replay.take().unwrap_or_default();
+                this.buffered.splice(end..end, replay);
+                break;
+            }
+
+            let mut chunk = [0; 4096];
+            let mut chunk_buf = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf))?;
+            if chunk_buf.filled().is_empty() {
+                this.replay = None;
+                break;
+            }
+            this.buffered.extend_from_slice(chunk_buf.filled());
+        }
+
+        if !this.buffered.is_empty() {
+            let len = this.buffered.len().min(buf.remaining());
+            buf.put_slice(&this.buffered[..len]);
+            this.buffered.drain(..len);
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for UpgradedIo<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,73 @@
+//! Listening socket handoff for zero-downtime upgrades.
+//!
+//! The new process inherits the listening socket as fd 3 with `LISTEN_FDS=1`
+//! set, the same convention as systemd socket activation, so it can also be
+//! started by systemd directly.
+
+use std::{
+    env, io,
+    net::TcpListener,
+    os::fd::{FromRawFd, RawFd},
+    os::unix::process::CommandExt,
+    process::{Child, Command},
+};
+
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Returns the listening socket passed in by a predecessor, if any.
+pub fn inherited_listener() -> io::Result<Option<TcpListener>> {
+    let listen_fds: u32 = match env::var("LISTEN_FDS").ok().and_then(|n| n.parse().ok()) {
+        Some(n) if n > 0 => n,
+        _ => return Ok(None),
+    };
+    // systemd sets LISTEN_PID; a predecessor process cannot know our pid
+    if let Some(pid) = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        && pid != std::process::id()
+    {
+        return Ok(None);
+    }
+    if listen_fds > 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("expected one listening socket, got {}", listen_fds),
+        ));
+    }
+
+    // SAFETY: LISTEN_FDS tells us fd 3 is an open listening socket we now own
+    let listener = unsafe { TcpListener::from_raw_fd(LISTEN_FDS_START) };
+    listener.set_nonblocking(true)?;
+    Ok(Some(listener))
+}
+
+/// Re-executes the current binary with `listener_fd` inherited as fd 3.
+pub fn spawn_successor(listener_fd: RawFd) -> io::Result<Child> {
+    let mut command = Command::new(env::current_exe()?);
+    command.args(env::args_os().skip(1));
+    pass_listener(&mut command, listener_fd);
+    command.spawn()
+}
+
+/// Makes `command` start with `listener_fd` as fd 3, where
+/// [`inherited_listener`] picks it up.
+pub fn pass_listener(command: &mut Command, listener_fd: RawFd) {
+    command.env("LISTEN_FDS", "1").env_remove("LISTEN_PID");
+
+    // SAFETY: only async-signal-safe libc calls run between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            if listener_fd == LISTEN_FDS_START {
+                let flags = libc::fcntl(listener_fd, libc::F_GETFD);
+                if flags < 0
+                    || libc::fcntl(listener_fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) < 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+            } else if libc::dup2(listener_fd, LISTEN_FDS_START) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,70 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+const DEFAULT_HEDGE_DELAY_MS: u64 = 100;
+const DEFAULT_MAX_HEDGE_RATIO: f64 = 0.1;
+/// Response time samples needed before a percentile delay is trusted.
+pub(crate) const MIN_PERCENTILE_SAMPLES: usize = 20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HedgingConfig {
+    /// Path prefixes of the latency-sensitive routes GETs are hedged on.
+    pub routes: Vec<String>,
+    /// Wait this long for the first worker before hedging.
+    pub delay: Duration,
+    /// When set, wait for this response time percentile (e.g. 0.95) from
+    /// metrics instead, falling back to `delay` until enough samples exist.
+    pub delay_percentile: Option<f64>,
+    /// Upper bound on the fraction of eligible requests that are hedged.
+    pub max_hedge_ratio: f64,
+}
+
+impl Default for HedgingConfig {
+    fn default() -> Self {
+        Self {
+            routes: Vec::new(),
+            delay: Duration::from_millis(DEFAULT_HEDGE_DELAY_MS),
+            delay_percentile: None,
+            max_hedge_ratio: DEFAULT_MAX_HEDGE_RATIO,
+        }
+    }
+}
+
+impl HedgingConfig {
+    pub(crate) fn applies_to(&self, path: &str) -> bool {
+        self.routes.iter().any(|route| path.starts_with(route))
+    }
+}
+
+/// Keeps hedged requests under `max_hedge_ratio` of eligible requests.
+pub(crate) struct HedgeBudget {
+    max_ratio: f64,
+    eligible: AtomicU64,
+    hedged: AtomicU64,
+}
+
+impl HedgeBudget {
+    pub(crate) fn new(max_ratio: f64) -> Self {
+        Self {
+            max_ratio,
+            eligible: AtomicU64::new(0),
+            hedged: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn record_eligible(&self) {
+        self.eligible.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn try_hedge(&self) -> bool {
+        let eligible = self.eligible.load(Ordering::Relaxed) as f64;
+        let hedged = self.hedged.load(Ordering::Relaxed) as f64;
+        if eligible == 0.0 || (hedged + 1.0) / eligible > self.max_ratio {
+            return false;
+        }
+        self.hedged.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+}
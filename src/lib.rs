@@ -1,10 +1,98 @@
+mod adaptive_concurrency;
 pub mod balancing_algorithms;
+mod circuit_breaker;
+mod config;
+mod forwarding;
+mod grpc;
+mod h2c;
+#[cfg(unix)]
+pub mod handoff;
+mod hedging;
+mod hop_by_hop;
 mod load_balancer;
+mod load_shedding;
+mod logging;
 mod metrics;
+mod proxy_protocol;
+mod rate_limit;
+mod request_id;
+mod request_queue;
+mod retries;
+mod server;
+mod tcp_proxy;
+mod timeouts;
+mod tls;
+mod udp_proxy;
+mod upgrade;
+mod upstream;
+mod worker_tracker;
 
+pub use adaptive_concurrency::{AdaptiveConcurrencyConfig, ConcurrencyAlgorithm};
+pub use circuit_breaker::{CircuitBreakerConfig, CircuitState};
+pub use config::LoadBalancerConfig;
+pub use forwarding::ForwardingConfig;
+pub use grpc::{GrpcCode, GrpcConfig, GrpcHealthCheckConfig};
+pub use hedging::HedgingConfig;
 pub use load_balancer::{LoadBalancer, ResponseBody};
+pub use load_shedding::{LoadSheddingConfig, OverloadDetectorConfig, PriorityMatch, PriorityRule};
+pub use logging::{LogConfig, LogFormat};
+pub use proxy_protocol::ProxyProtocolVersion;
+pub use rate_limit::{RateLimitConfig, RateLimitKey, RateLimitRule, RateLimitStatus};
+pub use request_id::{RequestIdConfig, TrustIncomingId};
+pub use request_queue::{QueueConfig, QueueOrdering};
+pub use retries::RetryConfig;
+pub use server::{ConnectionInfo, Http2Config, Server, ServerConfig, ShutdownOutcome};
+pub use tcp_proxy::{TcpConnectionStats, TcpProxy, TcpProxyConfig};
+pub use timeouts::{RouteTimeouts, TimeoutConfig, TimeoutKind};
+pub use tls::{
+    ClientAuthConfig, ClientCertificate, ClientIdentity, TlsCertificate, TlsConfig, TlsTermination,
+    TlsVersion, UpstreamTlsConfig,
+};
+pub use udp_proxy::{UdpProxy, UdpProxyConfig, UdpSessionStats};
+pub use upstream::{HostHeader, UpstreamPoolConfig, UpstreamProtocol};
+pub use worker_tracker::DrainStatus;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Worker {
     pub host: String,
+    /// Most requests the worker may have in flight; `None` is unlimited.
+    pub max_connections: Option<usize>,
+    pub protocol: UpstreamProtocol,
+    /// TLS settings for an `https://` host; `None` verifies the worker
+    /// against the Mozilla root store and sends no client certificate.
+    pub tls: Option<UpstreamTlsConfig>,
+    /// Whether the worker sees the client's `Host` or its own address.
+    pub host_header: HostHeader,
+}
+
+impl Worker {
+    pub fn new(host: impl Into<String>) -> Self {
+        Self {
+            host: host.into(),
+            max_connections: None,
+            protocol: UpstreamProtocol::default(),
+            tls: None,
+            host_header: HostHeader::default(),
+        }
+    }
+
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = Some(max_connections);
+        self
+    }
+
+    pub fn with_protocol(mut self, protocol: UpstreamProtocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    pub fn with_tls(mut self, tls: UpstreamTlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub fn with_host_header(mut self, host_header: HostHeader) -> Self {
+        self.host_header = host_header;
+        self
+    }
 }
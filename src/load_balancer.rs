@@ -1,23 +1,47 @@
-use std::str::FromStr;
+use std::{
+    error::Error as StdError,
+    io,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use http_body_util::{BodyExt, Empty, Full, combinators::BoxBody};
+use http_body_util::{BodyExt, Empty, Full};
 use hyper::{
-    Request, Response, Uri,
-    body::{Bytes, Incoming},
-};
-use hyper_util::{
-    client::legacy::{Client, Error as ClientError, connect::HttpConnector},
-    rt::TokioExecutor,
+    HeaderMap, Method, Request, Response, StatusCode, Uri,
+    body::{Body, Bytes, Incoming},
+    header::{HOST, HeaderValue, RETRY_AFTER},
+    http::request::Parts,
+    upgrade::OnUpgrade,
 };
+use hyper_util::client::legacy::Error as ClientError;
 use serde::Deserialize;
-use tokio::sync::RwLock;
+use tokio::{
+    sync::{Notify, RwLock},
+    time::Instant,
+};
+use tracing::{Instrument, debug, info, info_span, instrument, warn};
 
 use crate::{
-    Worker,
+    ConnectionInfo, HostHeader, Worker,
+    adaptive_concurrency::AdaptiveLimiter,
     balancing_algorithms::{
         AlgorithmType, BalancingAlgorithm, LeastConnectionsAlgorithm, RoundRobinAlgorithm,
     },
+    circuit_breaker::{CircuitBreakers, CircuitState, HalfOpenFull, ProbeGuard},
+    config::LoadBalancerConfig,
+    grpc::{self, GrpcCode, GrpcStatusBody, HealthChecks},
+    hedging::{HedgeBudget, MIN_PERCENTILE_SAMPLES},
+    hop_by_hop,
+    load_shedding::{LoadShedder, Shed, ShedPermit},
     metrics::Metrics,
+    rate_limit::{RateLimitStatus, RateLimited, RateLimiter},
+    request_queue::RequestQueue,
+    retries::{Recording, RecordingBody, RetryBudget, is_idempotent},
+    timeouts::{TimeoutBody, TimeoutConfig, TimeoutKind},
+    upgrade,
+    upstream::{GuardedBody, StreamGuard, UpstreamBody, UpstreamClients},
+    worker_tracker::{DrainStatus, InFlightGuard, WorkerTracker},
 };
 
 pub type ResponseBody = http_body_util::combinators::BoxBody<
@@ -26,43 +50,409 @@ pub type ResponseBody = http_body_util::combinators::BoxBody<
 >;
 
 pub struct LoadBalancer {
-    client: Client<HttpConnector, Incoming>,
+    upstream: Arc<UpstreamClients>,
     worker_hosts: Vec<Worker>,
-    balancing_algorithm: RwLock<Box<dyn BalancingAlgorithm>>,
-    metrics: RwLock<Metrics>,
+    balancing_algorithm: Arc<RwLock<Box<dyn BalancingAlgorithm>>>,
+    metrics: Arc<RwLock<Metrics>>,
+    worker_tracker: WorkerTracker,
+    circuit_breakers: Option<CircuitBreakers>,
+    retry_budget: RetryBudget,
+    hedge_budget: HedgeBudget,
+    rate_limiter: Option<RateLimiter>,
+    request_queue: Option<Arc<RequestQueue>>,
+    adaptive_limiter: Option<AdaptiveLimiter>,
+    load_shedder: Option<LoadShedder>,
+    health_checks: Option<Arc<HealthChecks>>,
+    config: LoadBalancerConfig,
 }
 
 const ALGORITHM_SWITCH_THRESHOLD_MS: u128 = 2000;
+const DEFAULT_DRAIN_DEADLINE_SECS: u64 = 30;
 
 impl LoadBalancer {
     pub fn new(
         worker_hosts: Vec<Worker>,
         balancing_algorithm: Box<dyn BalancingAlgorithm>,
+    ) -> Result<Self, String> {
+        Self::with_config(
+            worker_hosts,
+            balancing_algorithm,
+            LoadBalancerConfig::default(),
+        )
+    }
+
+    pub fn with_config(
+        worker_hosts: Vec<Worker>,
+        balancing_algorithm: Box<dyn BalancingAlgorithm>,
+        config: LoadBalancerConfig,
     ) -> Result<Self, String> {
         if worker_hosts.is_empty() {
             return Err("Worker hosts list cannot be empty".to_string());
         }
+        let rate_limiter = if config.rate_limit.rules.is_empty() {
+            None
+        } else {
+            Some(RateLimiter::new(config.rate_limit.clone())?)
+        };
+
+        let upstream = Arc::new(UpstreamClients::new(
+            &worker_hosts,
+            config.connect_timeout,
+            &config.upstream_pool,
+        )?);
+        let health_checks = match &config.grpc.health_check {
+            Some(health_check) => Some(HealthChecks::start(
+                &worker_hosts,
+                upstream.clone(),
+                health_check.clone(),
+            )?),
+            None => None,
+        };
+        // HTTP/2 workers can't take more requests than their streams allow
+        let tracked_workers: Vec<Worker> = worker_hosts
+            .iter()
+            .map(|worker| Worker {
+                max_connections: config.upstream_pool.capacity_of(worker),
+                ..worker.clone()
+            })
+            .collect();
 
-        let connector = HttpConnector::new();
-        let client = Client::builder(TokioExecutor::new()).build(connector);
+        let request_queue = config
+            .queue
+            .as_ref()
+            .map(|queue| Arc::new(RequestQueue::new(queue)));
 
         Ok(LoadBalancer {
-            client,
+            upstream,
+            worker_tracker: WorkerTracker::new(&tracked_workers, request_queue.clone()),
+            request_queue,
+            adaptive_limiter: config
+                .adaptive_concurrency
+                .clone()
+                .map(AdaptiveLimiter::new),
+            load_shedder: config.load_shedding.clone().map(LoadShedder::new),
+            health_checks,
+            circuit_breakers: config
+                .circuit_breaker
+                .clone()
+                .map(|circuit_breaker| CircuitBreakers::new(&worker_hosts, circuit_breaker)),
             worker_hosts,
-            balancing_algorithm: RwLock::new(balancing_algorithm),
-            metrics: RwLock::new(Metrics::new()),
+            balancing_algorithm: Arc::new(RwLock::new(balancing_algorithm)),
+            metrics: Arc::new(RwLock::new(Metrics::new())),
+            retry_budget: RetryBudget::new(&config.retries),
+            rate_limiter,
+            hedge_budget: HedgeBudget::new(
+                config
+                    .hedging
+                    .as_ref()
+                    .map_or(0.0, |hedging| hedging.max_hedge_ratio),
+            ),
+            config,
         })
     }
 
     pub async fn handle_request(
         &self,
         mut req: Request<Incoming>,
+    ) -> Result<hyper::Response<ResponseBody>, hyper_util::client::legacy::Error> {
+        // Stripped first, so `Connection` can't remove the headers added here
+        let is_upgrade = upgrade::is_upgrade_request(req.headers());
+        hop_by_hop::strip(req.headers_mut(), is_upgrade);
+        let request_id = self
+            .config
+            .request_id
+            .assign(&mut req, &self.config.forwarding);
+
+        // Every event logged for the request carries its ID
+        let span = info_span!(
+            "request",
+            request_id = %request_id,
+            method = %req.method(),
+            path = %req.uri().path(),
+        );
+        async {
+            let started = Instant::now();
+            let mut response = self.proxy(req).await?;
+            info!(
+                status = response.status().as_u16(),
+                elapsed_ms = started.elapsed().as_millis() as u64,
+                "Request completed"
+            );
+            response
+                .headers_mut()
+                .insert(&self.config.request_id.header, request_id.header_value());
+            Ok(response)
+        }
+        .instrument(span)
+        .await
+    }
+
+    async fn proxy(
+        &self,
+        req: Request<Incoming>,
     ) -> Result<hyper::Response<ResponseBody>, hyper_util::client::legacy::Error> {
         if req.uri().path().ends_with("change_algorithm") {
             return self.change_algorithm(&req).await;
         }
+        if req.uri().path().starts_with("/admin/") {
+            return Ok(self.handle_admin(&req).await);
+        }
+
+        let rate_limit = match self.check_rate_limit(&req).await {
+            Ok(rate_limit) => rate_limit,
+            Err(response) => return Ok(response),
+        };
+        let exempt = self
+            .config
+            .load_shedding
+            .as_ref()
+            .is_some_and(|load_shedding| load_shedding.is_exempt(req.uri().path()));
+        let _shed_permit = match self.admit(&req, exempt).await {
+            Ok(permit) => permit,
+            Err(response) => return Ok(response),
+        };
+
+        let timeouts = self.config.timeouts_for(req.uri().path());
+        let total_deadline = timeouts.total.map(|total| Instant::now() + total);
+
+        let (mut parts, body) = req.into_parts();
+        self.config.forwarding.apply(&mut parts);
+        let context = RequestContext {
+            parts,
+            timeouts,
+            total_deadline,
+            rate_limit,
+        };
+        let grpc = grpc::is_grpc(&context.parts.headers);
+        let mut body = match self.prepare_body(body, grpc).await {
+            Ok(body) => body,
+            Err(e) => {
+                warn!(error = %e, "Failed to read request body");
+                return Ok(text_response(
+                    StatusCode::BAD_REQUEST,
+                    "Invalid Request Body",
+                ));
+            }
+        };
+        self.retry_budget.deposit();
+
+        let priority = self
+            .config
+            .queue
+            .as_ref()
+            .map_or(0, |queue| queue.priority_of(&context.parts.headers));
+        // Shed load early once the pool is past its adaptive limit
+        let _limiter_permit = match &self.adaptive_limiter {
+            Some(_) if exempt => None,
+            Some(limiter) => match limiter.try_acquire() {
+                Some(permit) => Some(permit),
+                None => return Ok(self.reject(Rejection::ConcurrencyLimit).await),
+            },
+            None => None,
+        };
+        let mut selected = match self.acquire_worker(priority).await {
+            Ok(selected) => selected,
+            Err(rejection) => return Ok(self.reject(rejection).await),
+        };
+
+        let hedge = self.should_hedge(&context, &body);
+        let mut tried_hosts = Vec::new();
+        let mut retries = 0;
+        loop {
+            let host = selected.worker.host.clone();
+            let outcome = if hedge {
+                self.forward_hedged(selected, &context, &mut body).await
+            } else {
+                self.forward(selected, &context, body.for_attempt(), None)
+                    .await
+            };
+
+            if !self.should_retry(&context.parts.method, &outcome, &body, retries) {
+                return self.finish(outcome, &context).await;
+            }
+            if !self.retry_budget.try_withdraw() {
+                warn!(worker = %host, "Retry budget exhausted, not retrying");
+                self.metrics.write().await.record_retry_budget_exhausted();
+                return self.finish(outcome, &context).await;
+            }
+
+            tokio::time::sleep(self.config.retries.backoff_for(retries)).await;
+            tried_hosts.push(host.clone());
+            let Selection::Selected(next) = self.select_worker(&tried_hosts).await else {
+                return self.finish(outcome, &context).await;
+            };
+
+            info!(worker = %next.worker.host, failed = %host, "Retrying request");
+            self.metrics.write().await.record_retry();
+            selected = next;
+            retries += 1;
+        }
+    }
+
+    /// Rejects the request with 429 Too Many Requests if any matching rate
+    /// limit bucket is empty.
+    async fn check_rate_limit(
+        &self,
+        req: &Request<Incoming>,
+    ) -> Result<Option<RateLimitStatus>, Response<ResponseBody>> {
+        let Some(rate_limiter) = &self.rate_limiter else {
+            return Ok(None);
+        };
+        let connection = req.extensions().get::<ConnectionInfo>();
+        let client_ip = connection.map(|info| info.peer_addr.ip());
+        let client_identity = connection.and_then(|info| info.client_identity.as_deref());
+
+        match rate_limiter.check(req.uri().path(), req.headers(), client_ip, client_identity) {
+            Ok(status) => Ok(status),
+            Err(RateLimited { rule_index, status }) => {
+                let rule = rate_limiter.rule(rule_index);
+                info!(
+                    route = %rule.path_prefix,
+                    key = ?rule.key,
+                    "Rate limited request"
+                );
+                self.metrics
+                    .write()
+                    .await
+                    .record_rate_limited(&rule.path_prefix, &rule.key);
+                let mut response =
+                    text_response(StatusCode::TOO_MANY_REQUESTS, "Too Many Requests");
+                set_rate_limit_headers(response.headers_mut(), &status);
+                Err(response)
+            }
+        }
+    }
+
+    /// Sheds the request with 503 if the balancer is overloaded and its
+    /// priority is too low for the current load.
+    async fn admit(
+        &self,
+        req: &Request<Incoming>,
+        exempt: bool,
+    ) -> Result<Option<ShedPermit<'_>>, Response<ResponseBody>> {
+        let (Some(load_shedder), Some(load_shedding)) =
+            (&self.load_shedder, &self.config.load_shedding)
+        else {
+            return Ok(None);
+        };
+        if exempt {
+            return Ok(None);
+        }
+
+        let client_identity = req
+            .extensions()
+            .get::<ConnectionInfo>()
+            .and_then(|info| info.client_identity.as_deref());
+        let priority = load_shedding.priority_of(req.uri().path(), req.headers(), client_identity);
+        match load_shedder.try_admit(priority) {
+            Ok(permit) => Ok(Some(permit)),
+            Err(Shed { priority, load }) => {
+                warn!(load, priority, "Overloaded, shedding request");
+                self.metrics.write().await.record_shed(priority);
+                Err(self.reject(Rejection::Overloaded).await)
+            }
+        }
+    }
+
+    /// Selects a worker for a new request, waiting in the request queue
+    /// while every eligible worker is at capacity.
+    async fn acquire_worker(&self, priority: u8) -> Result<SelectedWorker, Rejection> {
+        let mut ticket = None;
+        let mut deadline = None;
+        loop {
+            // New arrivals only skip the queue when nobody is waiting in it
+            let queued_ahead = ticket.is_none()
+                && self
+                    .request_queue
+                    .as_ref()
+                    .is_some_and(|queue| queue.len() > 0);
+            if !queued_ahead {
+                match self.select_worker(&[]).await {
+                    Selection::Selected(selected) => return Ok(selected),
+                    Selection::Unavailable => return Err(Rejection::Unavailable),
+                    Selection::AtCapacity => {}
+                }
+            }
+            let (Some(queue), Some(queue_config)) = (&self.request_queue, &self.config.queue)
+            else {
+                return Err(Rejection::Unavailable);
+            };
+
+            let ticket = *ticket.get_or_insert_with(|| queue.ticket(priority));
+            let deadline = *deadline.get_or_insert_with(|| Instant::now() + queue_config.timeout);
+            let mut queued = queue.enqueue(ticket).map_err(|_| Rejection::QueueFull)?;
+
+            // A slot may have freed up before we joined the queue, but only
+            // the first in line may take it
+            if queued.is_first() {
+                match self.select_worker(&[]).await {
+                    Selection::Selected(selected) => return Ok(selected),
+                    Selection::Unavailable => return Err(Rejection::Unavailable),
+                    Selection::AtCapacity => {}
+                }
+            }
+            if tokio::time::timeout_at(deadline, queued.woken())
+                .await
+                .is_err()
+            {
+                return Err(Rejection::QueueTimeout);
+            }
+        }
+    }
+
+    async fn reject(&self, rejection: Rejection) -> Response<ResponseBody> {
+        let message = match rejection {
+            Rejection::Unavailable => "No workers available",
+            Rejection::QueueFull => {
+                warn!("Request queue full, rejecting request");
+                "Request queue full"
+            }
+            Rejection::QueueTimeout => {
+                warn!("Timed out waiting in request queue");
+                "Timed out waiting for a worker"
+            }
+            Rejection::ConcurrencyLimit => {
+                warn!("Concurrency limit reached, shedding request");
+                "Concurrency limit reached"
+            }
+            Rejection::Overloaded => "Overloaded",
+        };
+        self.metrics.write().await.record_rejection(rejection);
+        text_response(StatusCode::SERVICE_UNAVAILABLE, message)
+    }
+
+    /// Chooses a worker that isn't draining, already excluded or at
+    /// capacity, switching algorithms first if the current one has become
+    /// too slow.
+    #[instrument(name = "select", skip_all)]
+    async fn select_worker(&self, excluded_hosts: &[String]) -> Selection {
+        let mut excluded_hosts = excluded_hosts.to_vec();
+        loop {
+            // Draining workers never receive new requests
+            let eligible_workers: Vec<Worker> = self
+                .worker_hosts
+                .iter()
+                .filter(|worker| !self.worker_tracker.is_draining(&worker.host))
+                .filter(|worker| !excluded_hosts.contains(&worker.host))
+                .filter(|worker| {
+                    self.health_checks
+                        .as_ref()
+                        .is_none_or(|health_checks| health_checks.is_healthy(&worker.host))
+                })
+                .filter(|worker| self.circuit_allows(&worker.host))
+                .cloned()
+                .collect();
+            if eligible_workers.is_empty() {
+                return Selection::Unavailable;
+            }
+            let workers_with_capacity: Vec<Worker> = eligible_workers
+                .into_iter()
+                .filter(|worker| self.worker_tracker.has_capacity(&worker.host))
+                .collect();
+            if workers_with_capacity.is_empty() {
+                return Selection::AtCapacity;
+            }
 
-        let (worker, algo_type) = {
             let mut algo_type = self.balancing_algorithm.read().await.get_type();
 
             let metrics_response_time_ms = {
@@ -77,29 +467,194 @@ impl LoadBalancer {
                     self.metrics.write().await.reset(algo_type);
                     *self.balancing_algorithm.write().await = Box::new(RoundRobinAlgorithm::new());
                     algo_type = AlgorithmType::RoundRobin;
-                    println!("Switching to RoundRobinAlgorithm");
+                    info!("Switching to RoundRobinAlgorithm");
                 } else {
                     // Switch to LeastConnectionsAlgorithm
                     self.metrics.write().await.reset(algo_type);
                     *self.balancing_algorithm.write().await =
                         Box::new(LeastConnectionsAlgorithm::new(&self.worker_hosts));
                     algo_type = AlgorithmType::LeastConnections;
-                    println!("Switching to LeastConnectionsAlgorithm");
+                    info!("Switching to LeastConnectionsAlgorithm");
                 }
             }
-            (
-                self.balancing_algorithm
-                    .write()
-                    .await
-                    .choose(&self.worker_hosts),
+
+            let mut algorithm = self.balancing_algorithm.write().await;
+            let worker = algorithm.choose(&workers_with_capacity).clone();
+            // Another request may have taken the last slot since we checked
+            let Some(in_flight) = self.worker_tracker.try_acquire(&worker.host) else {
+                algorithm.release(&worker);
+                return Selection::AtCapacity;
+            };
+            let probe = match &self.circuit_breakers {
+                Some(circuit_breakers) => match circuit_breakers.on_call_started(&worker.host) {
+                    Ok(probe) => probe,
+                    // Its last probe slot went to a concurrent request
+                    Err(HalfOpenFull) => {
+                        algorithm.release(&worker);
+                        excluded_hosts.push(worker.host);
+                        continue;
+                    }
+                },
+                None => None,
+            };
+            drop(algorithm);
+
+            return Selection::Selected(SelectedWorker {
+                worker,
                 algo_type,
-            )
+                in_flight,
+                probe,
+            });
+        }
+    }
+
+    /// Buffers small request bodies so they can be replayed on retry.
+    async fn prepare_body(&self, body: Incoming, grpc: bool) -> Result<ProxiedBody, hyper::Error> {
+        let limit = self.config.retries.max_buffered_body_bytes;
+        if self.config.retries.max_retries == 0 {
+            return Ok(ProxiedBody::Streaming(Some(body)));
+        }
+        match body.size_hint().upper() {
+            Some(size) if size <= limit as u64 => {
+                Ok(ProxiedBody::Buffered(body.collect().await?.to_bytes()))
+            }
+            // gRPC clients rarely send a length, and buffering would stall
+            // streaming calls, so record the body as it streams instead
+            None if grpc => Ok(ProxiedBody::Recorded {
+                body: Some(body),
+                recording: Arc::new(Mutex::new(Recording::new(limit))),
+            }),
+            _ => Ok(ProxiedBody::Streaming(Some(body))),
+        }
+    }
+
+    fn should_hedge(&self, context: &RequestContext, body: &ProxiedBody) -> bool {
+        let Some(hedging) = &self.config.hedging else {
+            return false;
         };
+        // A hedged upgrade would open a second tunnel to another worker
+        if context.parts.method != Method::GET
+            || !body.is_replayable()
+            || upgrade::is_upgrade_request(&context.parts.headers)
+            || !hedging.applies_to(context.parts.uri.path())
+        {
+            return false;
+        }
+        self.hedge_budget.record_eligible();
+        true
+    }
+
+    async fn hedge_delay(&self) -> Duration {
+        let hedging = self.config.hedging.as_ref().expect("hedging is configured");
+        if let Some(percentile) = hedging.delay_percentile
+            && let Some(delay_ms) = self
+                .metrics
+                .read()
+                .await
+                .response_time_percentile_ms(percentile, MIN_PERCENTILE_SAMPLES)
+        {
+            return Duration::from_millis(delay_ms as u64);
+        }
+        hedging.delay
+    }
+
+    /// Sends the request to `worker` and, if it hasn't answered within the
+    /// hedge delay, to a second worker too. The first successful response
+    /// wins and the other attempt is cancelled.
+    async fn forward_hedged(
+        &self,
+        selected: SelectedWorker,
+        context: &RequestContext,
+        body: &mut ProxiedBody,
+    ) -> Result<Response<ResponseBody>, ForwardError> {
+        let primary_host = selected.worker.host.clone();
+        let primary_cancel = Notify::new();
+        let primary = self.forward(selected, context, body.for_attempt(), Some(&primary_cancel));
+        tokio::pin!(primary);
+
+        tokio::select! {
+            outcome = &mut primary => return outcome,
+            _ = tokio::time::sleep(self.hedge_delay().await) => {}
+        }
+
+        if !self.hedge_budget.try_hedge() {
+            return primary.await;
+        }
+        let Selection::Selected(hedge_selected) = self
+            .select_worker(std::slice::from_ref(&primary_host))
+            .await
+        else {
+            return primary.await;
+        };
+
+        info!(
+            worker = %hedge_selected.worker.host,
+            primary = %primary_host,
+            "Hedging request after no response"
+        );
+        self.metrics.write().await.record_hedge();
+        let hedge_cancel = Notify::new();
+        let hedge = self.forward(
+            hedge_selected,
+            context,
+            body.for_attempt(),
+            Some(&hedge_cancel),
+        );
+        tokio::pin!(hedge);
+
+        let (outcome, hedge_won) = tokio::select! {
+            outcome = &mut primary => {
+                if outcome.is_ok() {
+                    hedge_cancel.notify_one();
+                    let _ = hedge.await;
+                    (outcome, false)
+                } else {
+                    (hedge.await, true)
+                }
+            }
+            outcome = &mut hedge => {
+                if outcome.is_ok() {
+                    primary_cancel.notify_one();
+                    let _ = primary.await;
+                    (outcome, true)
+                } else {
+                    (primary.await, false)
+                }
+            }
+        };
+        if hedge_won && outcome.is_ok() {
+            self.metrics.write().await.record_hedge_win();
+        }
+        outcome
+    }
+
+    #[instrument(name = "upstream", skip_all, fields(worker = %selected.worker.host))]
+    async fn forward(
+        &self,
+        selected: SelectedWorker,
+        context: &RequestContext,
+        body: UpstreamBody,
+        cancel: Option<&Notify>,
+    ) -> Result<Response<ResponseBody>, ForwardError> {
+        let SelectedWorker {
+            worker,
+            algo_type,
+            in_flight,
+            probe,
+        } = selected;
+        let worker = &worker;
+        let RequestContext {
+            parts,
+            timeouts,
+            total_deadline,
+            ..
+        } = context;
+        let total_deadline = *total_deadline;
 
         let mut worker_uri = worker.host.clone();
 
         // Extract the path and query from the original request
-        if let Some(path_and_query) = req.uri().path_and_query() {
+        if let Some(path_and_query) = parts.uri.path_and_query() {
             worker_uri.push_str(path_and_query.as_str());
         }
 
@@ -107,36 +662,262 @@ impl LoadBalancer {
         let new_uri = Uri::from_str(&worker_uri).unwrap();
 
         // Clone the original request's headers and method
-        let mut builder = Request::builder()
-            .method(req.method().clone().to_owned())
-            .uri(new_uri);
-        builder
-            .headers_mut()
-            .unwrap()
-            .extend(req.headers_mut().drain());
+        let mut headers = parts.headers.clone();
+        let host = match worker.host_header {
+            HostHeader::Preserve if headers.contains_key(HOST) => None,
+            // HTTP/2 clients send the host as the request's authority
+            HostHeader::Preserve => parts.uri.authority(),
+            HostHeader::Rewrite => new_uri.authority(),
+        };
+        if let Some(host) = host.and_then(|host| HeaderValue::from_str(host.as_str()).ok()) {
+            headers.insert(HOST, host);
+        }
 
-        let new_req = builder.body(req.into_body()).expect("request builder");
+        let mut builder = Request::builder().method(parts.method.clone()).uri(new_uri);
+        builder.headers_mut().unwrap().extend(headers);
 
-        let before_time = std::time::Instant::now();
+        let new_req = builder.body(body).expect("request builder");
+        let (upstream_request, stream) = self.upstream.request(worker, new_req);
+        let active = ActiveRequest {
+            algorithm: self.balancing_algorithm.clone(),
+            worker: worker.clone(),
+            _in_flight: in_flight,
+            _stream: stream,
+        };
 
-        let response = self.client.request(new_req).await;
+        let before_time = Instant::now();
+        let first_byte_deadline = timeouts
+            .first_byte
+            .map(|first_byte| before_time + first_byte);
+        let response_deadline = match (first_byte_deadline, total_deadline) {
+            (Some(first_byte), Some(total)) => Some(first_byte.min(total)),
+            (first_byte, total) => first_byte.or(total),
+        };
 
-        let elapsed_time = before_time.elapsed().as_millis();
+        let request = async {
+            match response_deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, upstream_request)
+                    .await
+                    .map_err(|_| {
+                        if total_deadline == Some(deadline) {
+                            TimeoutKind::Total
+                        } else {
+                            TimeoutKind::FirstByte
+                        }
+                    }),
+                None => Ok(upstream_request.await),
+            }
+        };
+        let response = match cancel {
+            Some(cancel) => tokio::select! {
+                response = request => response,
+                _ = cancel.notified() => {
+                    debug!("Cancelled request to worker");
+                    drop(active);
+                    return Err(ForwardError::Cancelled);
+                }
+            },
+            None => request.await,
+        };
+
+        let elapsed = before_time.elapsed();
+        let elapsed_time = elapsed.as_millis();
+
+        let failed = match &response {
+            Ok(Ok(response)) => response.status().is_server_error(),
+            _ => true,
+        };
+        if let Some(circuit_breakers) = &self.circuit_breakers
+            && let Some(state) = circuit_breakers.record(&worker.host, failed, elapsed)
+        {
+            self.record_circuit_transition(&worker.host, state);
+        }
+        if let Some(probe) = probe {
+            probe.recorded();
+        }
+        if let Some(limiter) = &self.adaptive_limiter {
+            limiter.record(elapsed, failed);
+        }
 
-        self.balancing_algorithm.write().await.release(worker);
         self.metrics
             .write()
             .await
             .record_response_time(algo_type, elapsed_time);
 
-        // Wrap the streaming response body in BoxBody
-        response.map(|res| {
-            let (parts, body) = res.into_parts();
-            let boxed_body: ResponseBody = body
-                .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
-                .boxed();
-            Response::from_parts(parts, boxed_body)
-        })
+        let error = match response {
+            Ok(Ok(response)) if response.status() == StatusCode::SWITCHING_PROTOCOLS => {
+                return Ok(self.start_tunnel(response, context, active).await);
+            }
+            // The worker stays busy with the request until its body is done
+            Ok(Ok(mut response)) => {
+                hop_by_hop::strip(response.headers_mut(), false);
+                return Ok(response.map(|body| {
+                    ResponseBody::new(GuardedBody::new(
+                        body.map_err(|e| Box::new(e) as Box<dyn StdError + Send + Sync>),
+                        active,
+                    ))
+                }));
+            }
+            Ok(Err(e)) if is_connect_timeout(&e) => ForwardError::Timeout(TimeoutKind::Connect),
+            Ok(Err(e)) => {
+                warn!(error = %e, "Request to worker failed");
+                ForwardError::Client(e)
+            }
+            Err(kind) => ForwardError::Timeout(kind),
+        };
+        if let ForwardError::Timeout(kind) = error {
+            warn!(?kind, "Timed out waiting for worker");
+            self.record_timeout(kind).await;
+        }
+        Err(error)
+    }
+
+    /// Splices the client's connection to the worker's once both have
+    /// switched protocols. The tunnel holds the worker's connection slot
+    /// until either side closes, and isn't bound by the total timeout.
+    async fn start_tunnel(
+        &self,
+        mut response: Response<Incoming>,
+        context: &RequestContext,
+        active: ActiveRequest,
+    ) -> Response<ResponseBody> {
+        let Some(client) = context.parts.extensions.get::<OnUpgrade>().cloned() else {
+            warn!("Worker switched protocols for a client that can't");
+            return text_response(StatusCode::BAD_GATEWAY, "Bad Gateway");
+        };
+        let upstream = hyper::upgrade::on(&mut response);
+        self.metrics.write().await.record_upgrade();
+        tokio::spawn(
+            async move {
+                match upgrade::splice(client, upstream).await {
+                    Ok((sent, received)) => info!(sent, received, "Closed upgraded connection"),
+                    Err(e) => warn!(error = %e, "Upgraded connection failed"),
+                }
+                drop(active);
+            }
+            .in_current_span(),
+        );
+
+        let (mut parts, _) = response.into_parts();
+        hop_by_hop::strip(&mut parts.headers, true);
+        let body = ResponseBody::new(Empty::new().map_err(|infallible| match infallible {}));
+        Response::from_parts(parts, body)
+    }
+
+    /// Whether `host`'s circuit lets requests through, recording any
+    /// open to half-open transition this causes.
+    fn circuit_allows(&self, host: &str) -> bool {
+        let Some(circuit_breakers) = &self.circuit_breakers else {
+            return true;
+        };
+        let (available, transition) = circuit_breakers.is_available(host);
+        if let Some(state) = transition {
+            self.record_circuit_transition(host, state);
+        }
+        available
+    }
+
+    fn record_circuit_transition(&self, host: &str, state: CircuitState) {
+        info!(worker = %host, ?state, "Circuit state changed");
+        let metrics = self.metrics.clone();
+        let host = host.to_string();
+        tokio::spawn(async move {
+            metrics
+                .write()
+                .await
+                .record_circuit_transition(&host, state)
+        });
+    }
+
+    pub fn circuit_state(&self, host: &str) -> Option<CircuitState> {
+        self.circuit_breakers.as_ref()?.state(host)
+    }
+
+    fn should_retry(
+        &self,
+        method: &Method,
+        outcome: &Result<Response<ResponseBody>, ForwardError>,
+        body: &ProxiedBody,
+        retries: u32,
+    ) -> bool {
+        let retry_config = &self.config.retries;
+        if retries >= retry_config.max_retries || !body.is_replayable() {
+            return false;
+        }
+        match outcome {
+            // The request never reached the worker, so any method is safe to resend
+            Err(ForwardError::Timeout(TimeoutKind::Connect)) => true,
+            Err(ForwardError::Client(e)) if e.is_connect() => true,
+            Err(ForwardError::Client(_)) => is_idempotent(method),
+            Err(ForwardError::Timeout(_)) | Err(ForwardError::Cancelled) => false,
+            // A trailers-only gRPC response carries its status in the headers
+            Ok(response) if grpc::is_grpc(response.headers()) => {
+                GrpcCode::from_headers(response.headers())
+                    .is_some_and(|code| self.config.grpc.retry_on.contains(&code))
+            }
+            Ok(response) => {
+                is_idempotent(method) && retry_config.retry_on_status.contains(&response.status())
+            }
+        }
+    }
+
+    #[instrument(name = "response", skip_all)]
+    async fn finish(
+        &self,
+        outcome: Result<Response<ResponseBody>, ForwardError>,
+        context: &RequestContext,
+    ) -> Result<Response<ResponseBody>, ClientError> {
+        let response = match outcome {
+            Ok(response) => response,
+            Err(e) => {
+                let mut response = match e {
+                    // Only a losing hedge is cancelled, and its result is never used
+                    ForwardError::Timeout(_) | ForwardError::Cancelled => {
+                        text_response(StatusCode::GATEWAY_TIMEOUT, "Gateway Timeout")
+                    }
+                    ForwardError::Client(e) => {
+                        warn!(error = %e, "Upstream request failed");
+                        self.metrics.write().await.record_upstream_failure();
+                        text_response(StatusCode::BAD_GATEWAY, "Bad Gateway")
+                    }
+                };
+                if let Some(status) = &context.rate_limit {
+                    set_rate_limit_headers(response.headers_mut(), status);
+                }
+                return Ok(response);
+            }
+        };
+
+        let (parts, mut body) = response.into_parts();
+        if grpc::is_grpc(&parts.headers) {
+            match GrpcCode::from_headers(&parts.headers) {
+                Some(code) => self.metrics.write().await.record_grpc_status(code),
+                None => {
+                    let metrics = self.metrics.clone();
+                    body = ResponseBody::new(GrpcStatusBody::new(body, move |code| {
+                        tokio::spawn(async move { metrics.write().await.record_grpc_status(code) });
+                    }));
+                }
+            }
+        }
+        let metrics = self.metrics.clone();
+        let timeout_body = TimeoutBody::new(
+            body,
+            context.timeouts.idle_body,
+            context.total_deadline,
+            move |kind| {
+                tokio::spawn(async move { metrics.write().await.record_timeout(kind) });
+            },
+        );
+        let mut response = Response::from_parts(parts, ResponseBody::new(timeout_body));
+        if let Some(status) = &context.rate_limit {
+            set_rate_limit_headers(response.headers_mut(), status);
+        }
+        Ok(response)
+    }
+
+    pub(crate) async fn record_timeout(&self, kind: TimeoutKind) {
+        self.metrics.write().await.record_timeout(kind);
     }
 
     async fn change_algorithm(
@@ -175,6 +956,298 @@ impl LoadBalancer {
 
         Ok(Response::new(response_body))
     }
+
+    pub async fn metrics_report(&self) -> String {
+        let mut report = self.metrics.read().await.report();
+        if let Some(queue) = &self.request_queue {
+            report.push_str(&format!("request_queue_length {}\n", queue.len()));
+        }
+        if let Some(limiter) = &self.adaptive_limiter {
+            report.push_str(&format!("adaptive_concurrency_limit {}\n", limiter.limit()));
+        }
+        for worker in &self.worker_hosts {
+            if let Some(streams) = self.upstream.http2_streams(&worker.host) {
+                report.push_str(&format!(
+                    "upstream_http2_streams{{worker=\"{}\"}} {}\n",
+                    worker.host, streams
+                ));
+            }
+        }
+        if let Some(load_shedder) = &self.load_shedder {
+            report.push_str(&format!("overload_ratio {:.2}\n", load_shedder.load()));
+        }
+        if let Some(health_checks) = &self.health_checks {
+            for worker in &self.worker_hosts {
+                report.push_str(&format!(
+                    "worker_healthy{{worker=\"{}\"}} {}\n",
+                    worker.host,
+                    u8::from(health_checks.is_healthy(&worker.host))
+                ));
+            }
+        }
+        for worker in &self.worker_hosts {
+            if let Some(state) = self.circuit_state(&worker.host) {
+                report.push_str(&format!(
+                    "circuit_breaker_state{{worker=\"{}\",state=\"{:?}\"}} 1\n",
+                    worker.host, state
+                ));
+            }
+        }
+        report
+    }
+
+    /// Stops routing new requests to `host` until the drain is cancelled.
+    pub fn drain_worker(&self, host: &str, deadline: Duration) -> Result<(), String> {
+        self.worker_tracker.start_drain(host, deadline)?;
+        info!(worker = %host, "Draining worker");
+        Ok(())
+    }
+
+    pub fn undrain_worker(&self, host: &str) -> Result<(), String> {
+        self.worker_tracker.cancel_drain(host)?;
+        info!(worker = %host, "Undrained worker");
+        Ok(())
+    }
+
+    pub fn drain_status(&self, host: &str) -> Option<DrainStatus> {
+        self.worker_tracker.status(host)
+    }
+
+    /// Resolves once `host` has no requests in flight or its drain deadline passes.
+    pub async fn wait_for_drain(&self, host: &str) -> Option<DrainStatus> {
+        self.worker_tracker.wait_for_drain(host).await
+    }
+
+    async fn handle_admin(&self, req: &Request<Incoming>) -> Response<ResponseBody> {
+        let params =
+            match serde_urlencoded::from_str::<AdminRequest>(req.uri().query().unwrap_or_default())
+            {
+                Ok(params) => params,
+                Err(_) => return text_response(StatusCode::BAD_REQUEST, "Invalid Query"),
+            };
+
+        match req.uri().path() {
+            "/admin/metrics" => text_response(StatusCode::OK, self.metrics_report().await),
+            "/admin/circuit_breakers" => {
+                let states: String = self
+                    .worker_hosts
+                    .iter()
+                    .filter_map(|worker| {
+                        let state = self.circuit_state(&worker.host)?;
+                        Some(format!("{} {:?}\n", worker.host, state))
+                    })
+                    .collect();
+                text_response(StatusCode::OK, states)
+            }
+            "/admin/drain" => {
+                let Some(host) = params.host else {
+                    return text_response(StatusCode::BAD_REQUEST, "Missing host");
+                };
+                let deadline = Duration::from_secs(
+                    params.deadline_secs.unwrap_or(DEFAULT_DRAIN_DEADLINE_SECS),
+                );
+                match self.drain_worker(&host, deadline) {
+                    Ok(()) => text_response(StatusCode::OK, "Worker Draining!"),
+                    Err(e) => text_response(StatusCode::NOT_FOUND, e),
+                }
+            }
+            "/admin/undrain" => {
+                let Some(host) = params.host else {
+                    return text_response(StatusCode::BAD_REQUEST, "Missing host");
+                };
+                match self.undrain_worker(&host) {
+                    Ok(()) => text_response(StatusCode::OK, "Worker Undrained!"),
+                    Err(e) => text_response(StatusCode::NOT_FOUND, e),
+                }
+            }
+            "/admin/drain_status" => {
+                let Some(host) = params.host else {
+                    return text_response(StatusCode::BAD_REQUEST, "Missing host");
+                };
+                let status = if params.wait.unwrap_or(false) {
+                    self.wait_for_drain(&host).await
+                } else {
+                    self.drain_status(&host)
+                };
+                match status {
+                    Some(status) => text_response(
+                        StatusCode::OK,
+                        format!(
+                            "draining={} in_flight={} deadline_passed={} complete={}",
+                            status.draining,
+                            status.in_flight,
+                            status.deadline_passed,
+                            status.is_complete()
+                        ),
+                    ),
+                    None => {
+                        text_response(StatusCode::NOT_FOUND, format!("Unknown worker: {}", host))
+                    }
+                }
+            }
+            _ => text_response(StatusCode::NOT_FOUND, "Unknown admin endpoint"),
+        }
+    }
+}
+
+/// A worker chosen for one attempt, holding one of its request slots.
+struct SelectedWorker {
+    worker: Worker,
+    algo_type: AlgorithmType,
+    in_flight: InFlightGuard,
+    /// Holds a half-open probe slot until the outcome is recorded.
+    probe: Option<ProbeGuard>,
+}
+
+enum Selection {
+    Selected(SelectedWorker),
+    /// Every eligible worker is at its connection limit.
+    AtCapacity,
+    /// No worker may take requests at all.
+    Unavailable,
+}
+
+/// Why a request was turned away without reaching a worker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Rejection {
+    Unavailable,
+    QueueFull,
+    QueueTimeout,
+    ConcurrencyLimit,
+    Overloaded,
+}
+
+/// Holds a request's place on its worker until dropped, which for a
+/// successful response is once its body has been sent.
+struct ActiveRequest {
+    algorithm: Arc<RwLock<Box<dyn BalancingAlgorithm>>>,
+    worker: Worker,
+    _in_flight: InFlightGuard,
+    _stream: Option<StreamGuard>,
+}
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        if let Ok(mut algorithm) = self.algorithm.try_write() {
+            algorithm.release(&self.worker);
+            return;
+        }
+        let algorithm = self.algorithm.clone();
+        let worker = self.worker.clone();
+        tokio::spawn(async move { algorithm.write().await.release(&worker) });
+    }
+}
+
+/// Per-request state shared by every attempt to reach a worker.
+struct RequestContext {
+    parts: Parts,
+    timeouts: TimeoutConfig,
+    total_deadline: Option<Instant>,
+    rate_limit: Option<RateLimitStatus>,
+}
+
+enum ForwardError {
+    Timeout(TimeoutKind),
+    Client(ClientError),
+    /// Another hedged attempt won and this one was abandoned.
+    Cancelled,
+}
+
+/// A request body that is either held in memory so it can be resent, or
+/// streamed straight through to a single worker.
+enum ProxiedBody {
+    Buffered(Bytes),
+    Streaming(Option<Incoming>),
+    /// Streamed to the first worker while a copy is kept, so that it can be
+    /// resent once complete.
+    Recorded {
+        body: Option<Incoming>,
+        recording: Arc<Mutex<Recording>>,
+    },
+}
+
+impl ProxiedBody {
+    fn for_attempt(&mut self) -> UpstreamBody {
+        match self {
+            ProxiedBody::Buffered(bytes) => Full::new(bytes.clone())
+                .map_err(|infallible| match infallible {})
+                .boxed(),
+            ProxiedBody::Streaming(body) => match body.take() {
+                Some(body) => body
+                    .map_err(|e| Box::new(e) as Box<dyn StdError + Send + Sync>)
+                    .boxed(),
+                None => Full::new(Bytes::new())
+                    .map_err(|infallible| match infallible {})
+                    .boxed(),
+            },
+            ProxiedBody::Recorded { body, recording } => match body.take() {
+                Some(body) => RecordingBody::new(body, recording.clone())
+                    .map_err(|e| Box::new(e) as Box<dyn StdError + Send + Sync>)
+                    .boxed(),
+                None => Full::new(recording.lock().unwrap().replay().unwrap_or_default())
+                    .map_err(|infallible| match infallible {})
+                    .boxed(),
+            },
+        }
+    }
+
+    fn is_replayable(&self) -> bool {
+        match self {
+            ProxiedBody::Buffered(_) => true,
+            ProxiedBody::Streaming(_) => false,
+            ProxiedBody::Recorded { body, recording } => {
+                body.is_none() && recording.lock().unwrap().is_replayable()
+            }
+        }
+    }
+}
+
+/// Connect timeouts surface as an I/O `TimedOut` somewhere in the error chain.
+fn is_connect_timeout(error: &ClientError) -> bool {
+    if !error.is_connect() {
+        return false;
+    }
+    let mut source = error.source();
+    while let Some(err) = source {
+        if let Some(io_err) = err.downcast_ref::<io::Error>()
+            && io_err.kind() == io::ErrorKind::TimedOut
+        {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+fn set_rate_limit_headers(headers: &mut HeaderMap, status: &RateLimitStatus) {
+    let ceil_secs = |duration: Duration| {
+        duration
+            .as_secs()
+            .saturating_add(u64::from(duration.subsec_nanos() > 0))
+    };
+    headers.insert("ratelimit-limit", HeaderValue::from(status.limit));
+    headers.insert("ratelimit-remaining", HeaderValue::from(status.remaining));
+    headers.insert(
+        "ratelimit-reset",
+        HeaderValue::from(ceil_secs(status.reset)),
+    );
+    if let Some(retry_after) = status.retry_after {
+        headers.insert(RETRY_AFTER, HeaderValue::from(ceil_secs(retry_after)));
+    }
+}
+
+fn text_response(status: StatusCode, text: impl Into<String>) -> Response<ResponseBody> {
+    let body = ResponseBody::new(text.into().map_err(|infallible| match infallible {}));
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    response
+}
+
+#[derive(Deserialize)]
+struct AdminRequest {
+    host: Option<String>,
+    deadline_secs: Option<u64>,
+    wait: Option<bool>,
 }
 
 #[derive(Deserialize)]
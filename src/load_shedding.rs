@@ -0,0 +1,245 @@
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use hyper::{HeaderMap, header::HeaderName};
+
+use crate::ClientIdentity;
+
+const DEFAULT_SHED_START: f64 = 0.8;
+const DEFAULT_CPU_SAMPLE_INTERVAL_MS: u64 = 250;
+
+/// Decides which requests a rule assigns its priority to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PriorityMatch {
+    /// Requests whose path starts with this prefix.
+    Route(String),
+    /// Requests carrying this header with exactly this value, e.g. a client
+    /// class set by an upstream authentication proxy.
+    Header(HeaderName, String),
+    /// Requests from clients whose verified certificate has this subject or
+    /// subject alternative name.
+    ClientIdentity(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PriorityRule {
+    pub matches: PriorityMatch,
+    /// Higher values are shed later; 255 is only shed at full load.
+    pub priority: u8,
+}
+
+/// Measures how close the balancer is to overload. Each configured signal
+/// gives a load ratio where 1.0 is full load; the highest one wins.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverloadDetectorConfig {
+    /// Requests in flight through the balancer at full load.
+    pub max_in_flight: Option<usize>,
+    /// Process CPU utilisation (0.0 to 1.0 of all cores) at full load.
+    /// Only measured on unix.
+    pub max_cpu_utilization: Option<f64>,
+    pub cpu_sample_interval: Duration,
+}
+
+impl Default for OverloadDetectorConfig {
+    fn default() -> Self {
+        Self {
+            max_in_flight: None,
+            max_cpu_utilization: None,
+            cpu_sample_interval: Duration::from_millis(DEFAULT_CPU_SAMPLE_INTERVAL_MS),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadSheddingConfig {
+    /// The first matching rule sets a request's priority.
+    pub rules: Vec<PriorityRule>,
+    /// Priority of requests no rule matches.
+    pub default_priority: u8,
+    /// Path prefixes that are never shed, such as health checks.
+    pub never_shed: Vec<String>,
+    /// Load ratio at which priority 0 starts being shed. Higher priorities
+    /// are shed at proportionally higher load, up to 1.0 for priority 255.
+    pub shed_start: f64,
+    pub detector: OverloadDetectorConfig,
+}
+
+impl Default for LoadSheddingConfig {
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            default_priority: 0,
+            never_shed: vec!["/health".to_string(), "/admin/".to_string()],
+            shed_start: DEFAULT_SHED_START,
+            detector: OverloadDetectorConfig::default(),
+        }
+    }
+}
+
+impl LoadSheddingConfig {
+    pub(crate) fn is_exempt(&self, path: &str) -> bool {
+        self.never_shed
+            .iter()
+            .any(|prefix| path.starts_with(prefix))
+    }
+
+    pub(crate) fn priority_of(
+        &self,
+        path: &str,
+        headers: &HeaderMap,
+        client_identity: Option<&ClientIdentity>,
+    ) -> u8 {
+        self.rules
+            .iter()
+            .find(|rule| match &rule.matches {
+                PriorityMatch::Route(prefix) => path.starts_with(prefix),
+                PriorityMatch::Header(name, value) => headers
+                    .get(name)
+                    .is_some_and(|header| header.as_bytes() == value.as_bytes()),
+                PriorityMatch::ClientIdentity(name) => {
+                    client_identity.is_some_and(|identity| identity.matches(name))
+                }
+            })
+            .map_or(self.default_priority, |rule| rule.priority)
+    }
+
+    /// Load ratio at which requests of `priority` are shed.
+    fn shed_threshold(&self, priority: u8) -> f64 {
+        let shed_start = self.shed_start.clamp(0.0, 1.0);
+        shed_start + (1.0 - shed_start) * (priority as f64 / u8::MAX as f64)
+    }
+}
+
+/// Sheds lower-priority requests first as the overload detector's load
+/// ratio rises.
+pub(crate) struct LoadShedder {
+    config: LoadSheddingConfig,
+    in_flight: AtomicUsize,
+    cpu: Mutex<CpuSampler>,
+}
+
+/// Counts a request towards the in-flight load until dropped.
+pub(crate) struct ShedPermit<'a> {
+    shedder: &'a LoadShedder,
+}
+
+impl Drop for ShedPermit<'_> {
+    fn drop(&mut self) {
+        self.shedder.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub(crate) struct Shed {
+    pub(crate) priority: u8,
+    pub(crate) load: f64,
+}
+
+impl LoadShedder {
+    pub(crate) fn new(config: LoadSheddingConfig) -> Self {
+        Self {
+            config,
+            in_flight: AtomicUsize::new(0),
+            cpu: Mutex::new(CpuSampler::new()),
+        }
+    }
+
+    /// Admits the request, or returns `Err` if it should be shed at the
+    /// current load.
+    pub(crate) fn try_admit(&self, priority: u8) -> Result<ShedPermit<'_>, Shed> {
+        let threshold = self.config.shed_threshold(priority);
+        let cpu_load = self.cpu_load();
+        let max_in_flight = self.config.detector.max_in_flight;
+
+        let mut load = cpu_load;
+        self.in_flight
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |in_flight| {
+                let in_flight_load =
+                    max_in_flight.map_or(0.0, |max| in_flight as f64 / max.max(1) as f64);
+                load = cpu_load.max(in_flight_load);
+                (load < threshold).then_some(in_flight + 1)
+            })
+            .map_err(|_| Shed { priority, load })?;
+        Ok(ShedPermit { shedder: self })
+    }
+
+    /// The current load ratio, for metrics.
+    pub(crate) fn load(&self) -> f64 {
+        let in_flight_load = self.config.detector.max_in_flight.map_or(0.0, |max| {
+            self.in_flight.load(Ordering::SeqCst) as f64 / max.max(1) as f64
+        });
+        self.cpu_load().max(in_flight_load)
+    }
+
+    fn cpu_load(&self) -> f64 {
+        let Some(max_cpu) = self.config.detector.max_cpu_utilization else {
+            return 0.0;
+        };
+        let utilization = self
+            .cpu
+            .lock()
+            .unwrap()
+            .utilization(self.config.detector.cpu_sample_interval);
+        utilization / max_cpu.max(f64::EPSILON)
+    }
+}
+
+/// Process CPU utilisation, re-sampled at most once per interval so the
+/// request path doesn't make a syscall every time.
+struct CpuSampler {
+    sampled_at: Instant,
+    cpu_time: Option<Duration>,
+    utilization: f64,
+}
+
+impl CpuSampler {
+    fn new() -> Self {
+        Self {
+            sampled_at: Instant::now(),
+            cpu_time: process_cpu_time(),
+            utilization: 0.0,
+        }
+    }
+
+    fn utilization(&mut self, interval: Duration) -> f64 {
+        let elapsed = self.sampled_at.elapsed();
+        if elapsed < interval {
+            return self.utilization;
+        }
+
+        let cpu_time = process_cpu_time();
+        if let (Some(previous), Some(current)) = (self.cpu_time, cpu_time) {
+            let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+            self.utilization = current.saturating_sub(previous).as_secs_f64()
+                / (elapsed.as_secs_f64() * cores as f64);
+        }
+        self.sampled_at = Instant::now();
+        self.cpu_time = cpu_time;
+        self.utilization
+    }
+}
+
+#[cfg(unix)]
+fn process_cpu_time() -> Option<Duration> {
+    // SAFETY: getrusage only writes to the struct we hand it
+    let usage = unsafe {
+        let mut usage: libc::rusage = std::mem::zeroed();
+        if libc::getrusage(libc::RUSAGE_SELF, &mut usage) != 0 {
+            return None;
+        }
+        usage
+    };
+    let to_duration = |time: libc::timeval| {
+        Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+    };
+    Some(to_duration(usage.ru_utime) + to_duration(usage.ru_stime))
+}
+
+#[cfg(not(unix))]
+fn process_cpu_time() -> Option<Duration> {
+    None
+}
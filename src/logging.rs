@@ -0,0 +1,61 @@
+//! Log output for the binaries. Filters use the `RUST_LOG` syntax, so one
+//! module can be made more verbose than the rest, as in
+//! `info,load_balancer::tcp_proxy=debug`.
+
+use std::env;
+
+use tracing_subscriber::EnvFilter;
+
+const DEFAULT_FILTER: &str = "info";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// One human-readable line per event.
+    #[default]
+    Text,
+    /// One JSON object per event, with span fields included.
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogConfig {
+    pub filter: String,
+    pub format: LogFormat,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            filter: DEFAULT_FILTER.to_string(),
+            format: LogFormat::default(),
+        }
+    }
+}
+
+impl LogConfig {
+    /// Reads the filter from `RUST_LOG` and the format from `LOG_FORMAT`
+    /// (`text` or `json`), keeping the defaults for anything unset.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            filter: env::var("RUST_LOG").unwrap_or(defaults.filter),
+            format: match env::var("LOG_FORMAT").as_deref() {
+                Ok("json") => LogFormat::Json,
+                _ => defaults.format,
+            },
+        }
+    }
+
+    /// Installs the global subscriber. Fails on an invalid filter, or if a
+    /// subscriber is already installed.
+    pub fn init(&self) -> Result<(), String> {
+        let filter = EnvFilter::try_new(&self.filter)
+            .map_err(|e| format!("invalid log filter {:?}: {}", self.filter, e))?;
+        let builder = tracing_subscriber::fmt().with_env_filter(filter);
+        match self.format {
+            LogFormat::Text => builder.try_init(),
+            LogFormat::Json => builder.json().with_current_span(true).try_init(),
+        }
+        .map_err(|e| format!("failed to install logger: {}", e))
+    }
+}
@@ -1,26 +1,30 @@
-use std::{net::SocketAddr, sync::Arc};
-
-use http_body_util::combinators::BoxBody;
-use hyper::server::conn::http1;
-use hyper::{
-    Request, Response,
-    body::{Bytes, Incoming},
-    service::service_fn,
-};
-use hyper_util::rt::TokioIo;
+use std::{env, net::SocketAddr, process::ExitCode, sync::Arc, time::Duration};
+
 use load_balancer::balancing_algorithms::LeastConnectionsAlgorithm;
-use load_balancer::{LoadBalancer, Worker};
-use tokio::{net::TcpListener, task};
+use load_balancer::{
+    ClientAuthConfig, LoadBalancer, LogConfig, ProxyProtocolVersion, Server, ServerConfig,
+    ShutdownOutcome, TcpProxy, TcpProxyConfig, TlsCertificate, TlsConfig, TlsTermination, UdpProxy,
+    UdpProxyConfig, Worker,
+};
+use tokio::{
+    net::{TcpListener, UdpSocket},
+    sync::watch,
+};
+use tracing::{error, info, warn};
+
+/// How long a successor must stay up before this process starts draining.
+#[cfg(unix)]
+const SUCCESSOR_STARTUP_CHECK: Duration = Duration::from_millis(500);
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
+    LogConfig::from_env()
+        .init()
+        .expect("failed to initialize logging");
+
     let worker_hosts = vec![
-        Worker {
-            host: "http://localhost:3000".to_string(),
-        },
-        Worker {
-            host: "http://localhost:3001".to_string(),
-        },
+        Worker::new("http://localhost:3000"),
+        Worker::new("http://localhost:3001"),
     ];
 
     let algo = Box::new(LeastConnectionsAlgorithm::new(&worker_hosts));
@@ -28,37 +32,249 @@ async fn main() {
     let load_balancer =
         Arc::new(LoadBalancer::new(worker_hosts, algo).expect("failed to create load balancer"));
 
+    let mut server_config = ServerConfig::default();
+    if let Some(secs) = env::var("SHUTDOWN_GRACE_PERIOD_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+    {
+        server_config.shutdown_grace_period = Duration::from_secs(secs);
+    }
+    let accept_proxy_protocol =
+        env::var("ACCEPT_PROXY_PROTOCOL").is_ok_and(|value| value == "true");
+    server_config.accept_proxy_protocol = accept_proxy_protocol;
+    if let (Ok(cert_path), Ok(key_path)) = (env::var("TLS_CERT_FILE"), env::var("TLS_KEY_FILE")) {
+        let tls = TlsTermination::new(TlsConfig {
+            certificates: vec![TlsCertificate {
+                cert_path: cert_path.into(),
+                key_path: key_path.into(),
+                server_names: Vec::new(),
+            }],
+            client_auth: env::var("TLS_CLIENT_CA_FILE")
+                .ok()
+                .map(ClientAuthConfig::new),
+            ..TlsConfig::default()
+        })
+        .expect("failed to load TLS certificate");
+        #[cfg(unix)]
+        tokio::spawn(reload_tls_on_sighup(tls.clone()));
+        server_config.tls = Some(tls);
+    }
+    let scheme = if server_config.tls.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+
+    // Flipped once the HTTP server starts shutting down, so the proxies stop too
+    let (stopping, stop) = watch::channel(false);
+    if let (Ok(listen), Ok(workers)) = (env::var("TCP_PROXY_LISTEN"), env::var("TCP_PROXY_WORKERS"))
+    {
+        start_tcp_proxy(&listen, &workers, accept_proxy_protocol, stop.clone()).await;
+    }
+    if let (Ok(listen), Ok(workers)) = (env::var("UDP_PROXY_LISTEN"), env::var("UDP_PROXY_WORKERS"))
+    {
+        start_udp_proxy(&listen, &workers, stop).await;
+    }
+
     let addr: SocketAddr = SocketAddr::from(([127, 0, 0, 1], 1337));
 
-    let listener = TcpListener::bind(addr)
+    let listener = bind_listener(addr).await;
+    let addr = listener.local_addr().expect("failed to read local address");
+
+    info!(%scheme, %addr, "Load balancer listening");
+
+    #[cfg(unix)]
+    let shutdown = shutdown_signal(std::os::fd::AsRawFd::as_raw_fd(&listener));
+    #[cfg(not(unix))]
+    let shutdown = shutdown_signal();
+    let shutdown = async move {
+        shutdown.await;
+        stopping.send_replace(true);
+    };
+
+    let outcome = Server::new(load_balancer.clone(), server_config)
+        .serve(listener, shutdown)
+        .await;
+
+    info!(
+        metrics = %load_balancer.metrics_report().await,
+        "Final metrics"
+    );
+
+    match outcome {
+        ShutdownOutcome::Graceful => {
+            info!("Shutdown complete");
+            ExitCode::SUCCESS
+        }
+        ShutdownOutcome::GracePeriodElapsed => {
+            warn!("Grace period elapsed with requests still in flight");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Proxies raw TCP from `listen` to the comma-separated `host:port` workers,
+/// alongside the HTTP listener. `TCP_PROXY_SEND_PROXY_PROTOCOL` set to `v1`
+/// or `v2` passes client addresses on to the workers.
+async fn start_tcp_proxy(
+    listen: &str,
+    workers: &str,
+    accept_proxy_protocol: bool,
+    stop: watch::Receiver<bool>,
+) {
+    let workers: Vec<Worker> = workers
+        .split(',')
+        .map(|host| Worker::new(host.trim()))
+        .collect();
+    let algo = Box::new(LeastConnectionsAlgorithm::new(&workers));
+    let proxy = Arc::new(
+        TcpProxy::new(
+            workers,
+            algo,
+            TcpProxyConfig {
+                accept_proxy_protocol,
+                send_proxy_protocol: match env::var("TCP_PROXY_SEND_PROXY_PROTOCOL").as_deref() {
+                    Ok("v1") => Some(ProxyProtocolVersion::V1),
+                    Ok("v2") => Some(ProxyProtocolVersion::V2),
+                    _ => None,
+                },
+                ..TcpProxyConfig::default()
+            },
+        )
+        .expect("failed to create TCP proxy"),
+    );
+    let listener = TcpListener::bind(listen)
         .await
-        .expect("failed to bind TCP listener");
+        .expect("failed to bind TCP proxy listener");
+    info!(
+        addr = %listener.local_addr().expect("failed to read local address"),
+        "TCP proxy listening"
+    );
+    tokio::spawn(proxy.serve(listener, stopped(stop)));
+}
 
-    println!("load balancer listening on http://{}", addr);
+/// Balances UDP datagrams from `listen` across the comma-separated
+/// `host:port` workers.
+async fn start_udp_proxy(listen: &str, workers: &str, stop: watch::Receiver<bool>) {
+    let workers: Vec<Worker> = workers
+        .split(',')
+        .map(|host| Worker::new(host.trim()))
+        .collect();
+    let algo = Box::new(LeastConnectionsAlgorithm::new(&workers));
+    let proxy = Arc::new(
+        UdpProxy::new(workers, algo, UdpProxyConfig::default())
+            .await
+            .expect("failed to create UDP proxy"),
+    );
+    let socket = UdpSocket::bind(listen)
+        .await
+        .expect("failed to bind UDP proxy socket");
+    info!(
+        addr = %socket.local_addr().expect("failed to read local address"),
+        "UDP proxy listening"
+    );
+    tokio::spawn(proxy.serve(socket, stopped(stop)));
+}
+
+/// Resolves once `stop` is flipped, or its sender is gone.
+async fn stopped(mut stop: watch::Receiver<bool>) {
+    let _ = stop.wait_for(|stopped| *stopped).await;
+}
+
+/// Takes over the listening socket from a predecessor process if one was
+/// handed to us, otherwise binds a fresh one.
+async fn bind_listener(addr: SocketAddr) -> TcpListener {
+    #[cfg(unix)]
+    if let Some(listener) =
+        load_balancer::handoff::inherited_listener().expect("failed to inherit listener")
+    {
+        info!("Inherited listening socket from predecessor");
+        return TcpListener::from_std(listener).expect("failed to register inherited listener");
+    }
+
+    TcpListener::bind(addr)
+        .await
+        .expect("failed to bind TCP listener")
+}
+
+/// Resolves on Ctrl+C or SIGTERM, or after SIGUSR2 has started a successor
+/// process that now shares the listening socket.
+#[cfg(unix)]
+async fn shutdown_signal(listener_fd: std::os::fd::RawFd) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+    let mut upgrade =
+        signal(SignalKind::user_defined2()).expect("failed to install SIGUSR2 handler");
 
     loop {
-        let (stream, _) = listener.accept().await.expect("failed to accept");
-        println!("accepted connection from {}", stream.peer_addr().unwrap());
-        let load_balancer = load_balancer.clone();
-
-        task::spawn(async move {
-            println!(
-                "spawned task for connection from {}",
-                stream.peer_addr().unwrap()
-            );
-            let io = TokioIo::new(stream);
-            let service = service_fn(move |req| handle(req, load_balancer.clone()));
-
-            if let Err(e) = http1::Builder::new().serve_connection(io, service).await {
-                eprintln!("error: {}", e);
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {
+                info!("Received Ctrl+C");
+                return;
+            }
+            _ = terminate.recv() => {
+                info!("Received SIGTERM");
+                return;
             }
-        });
+            _ = upgrade.recv() => {
+                info!("Received SIGUSR2, starting successor");
+                if start_successor(listener_fd).await {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Reloads the TLS certificates and client CA bundle from disk on every
+/// SIGHUP.
+#[cfg(unix)]
+async fn reload_tls_on_sighup(tls: TlsTermination) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangup = signal(SignalKind::hangup()).expect("failed to install SIGHUP handler");
+    while hangup.recv().await.is_some() {
+        info!("Received SIGHUP, reloading TLS certificates");
+        if let Err(e) = tls.reload() {
+            error!(error = %e, "Failed to reload TLS certificates");
+        }
     }
 }
 
-async fn handle(
-    req: Request<Incoming>,
-    load_balancer: Arc<LoadBalancer>,
-) -> Result<Response<load_balancer::ResponseBody>, hyper_util::client::legacy::Error> {
-    load_balancer.handle_request(req).await
+/// Returns true once the successor is up and this process should drain.
+#[cfg(unix)]
+async fn start_successor(listener_fd: std::os::fd::RawFd) -> bool {
+    let mut child = match load_balancer::handoff::spawn_successor(listener_fd) {
+        Ok(child) => child,
+        Err(e) => {
+            error!(error = %e, "Failed to start successor");
+            return false;
+        }
+    };
+
+    tokio::time::sleep(SUCCESSOR_STARTUP_CHECK).await;
+    match child.try_wait() {
+        Ok(None) => {
+            info!(pid = child.id(), "Successor running, draining");
+            true
+        }
+        Ok(Some(status)) => {
+            error!(%status, "Successor exited during startup");
+            false
+        }
+        Err(e) => {
+            error!(error = %e, "Failed to check successor");
+            false
+        }
+    }
+}
+
+/// Resolves on Ctrl+C.
+#[cfg(not(unix))]
+async fn shutdown_signal() {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("failed to install Ctrl+C handler");
+    info!("Received Ctrl+C");
 }
@@ -1,11 +1,30 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
-use crate::balancing_algorithms::AlgorithmType;
+use crate::{
+    balancing_algorithms::AlgorithmType, circuit_breaker::CircuitState, grpc::GrpcCode,
+    load_balancer::Rejection, rate_limit::RateLimitKey, timeouts::TimeoutKind,
+};
+
+/// Response times kept for percentile estimates.
+const RESPONSE_TIME_SAMPLES: usize = 1000;
 
 #[derive(Default)]
 pub struct Metrics {
     average_response_time_for_algorithm: HashMap<AlgorithmType, u128>,
     request_count: u128,
+    timeouts: HashMap<TimeoutKind, u64>,
+    retries: u64,
+    retry_budget_exhausted: u64,
+    circuit_transitions: HashMap<(String, CircuitState), u64>,
+    recent_response_times: VecDeque<u128>,
+    hedged_requests: u64,
+    hedge_wins: u64,
+    rate_limited: HashMap<(String, String), u64>,
+    rejections: HashMap<Rejection, u64>,
+    shed: HashMap<u8, u64>,
+    upgrades: u64,
+    upstream_failures: u64,
+    grpc_responses: HashMap<GrpcCode, u64>,
 }
 
 impl Metrics {
@@ -13,6 +32,19 @@ impl Metrics {
         Metrics {
             average_response_time_for_algorithm: HashMap::new(),
             request_count: 0,
+            timeouts: HashMap::new(),
+            retries: 0,
+            retry_budget_exhausted: 0,
+            circuit_transitions: HashMap::new(),
+            recent_response_times: VecDeque::new(),
+            hedged_requests: 0,
+            hedge_wins: 0,
+            rate_limited: HashMap::new(),
+            rejections: HashMap::new(),
+            shed: HashMap::new(),
+            upgrades: 0,
+            upstream_failures: 0,
+            grpc_responses: HashMap::new(),
         }
     }
 
@@ -23,6 +55,84 @@ impl Metrics {
             .or_insert(0);
         *entry = ((*entry * self.request_count) + time_ms) / (self.request_count + 1);
         self.request_count += 1;
+
+        self.recent_response_times.push_back(time_ms);
+        if self.recent_response_times.len() > RESPONSE_TIME_SAMPLES {
+            self.recent_response_times.pop_front();
+        }
+    }
+
+    /// The `percentile` (0.0 to 1.0) of recent response times, once at least
+    /// `min_samples` responses have been recorded.
+    pub fn response_time_percentile_ms(&self, percentile: f64, min_samples: usize) -> Option<u128> {
+        if self.recent_response_times.len() < min_samples.max(1) {
+            return None;
+        }
+        let mut sorted: Vec<u128> = self.recent_response_times.iter().copied().collect();
+        sorted.sort_unstable();
+        let index = ((sorted.len() - 1) as f64 * percentile.clamp(0.0, 1.0)).round() as usize;
+        Some(sorted[index])
+    }
+
+    pub fn record_hedge(&mut self) {
+        self.hedged_requests += 1;
+    }
+
+    pub fn record_hedge_win(&mut self) {
+        self.hedge_wins += 1;
+    }
+
+    pub fn record_timeout(&mut self, kind: TimeoutKind) {
+        *self.timeouts.entry(kind).or_insert(0) += 1;
+    }
+
+    pub fn record_retry(&mut self) {
+        self.retries += 1;
+    }
+
+    pub fn record_retry_budget_exhausted(&mut self) {
+        self.retry_budget_exhausted += 1;
+    }
+
+    pub fn record_circuit_transition(&mut self, host: &str, state: CircuitState) {
+        *self
+            .circuit_transitions
+            .entry((host.to_string(), state))
+            .or_insert(0) += 1;
+    }
+
+    pub fn record_rate_limited(&mut self, path_prefix: &str, key: &RateLimitKey) {
+        let key = match key {
+            RateLimitKey::ClientIp => "client_ip".to_string(),
+            RateLimitKey::Header(name) => format!("header:{}", name),
+            RateLimitKey::Route => "route".to_string(),
+            RateLimitKey::ClientIdentity => "client_identity".to_string(),
+        };
+        *self
+            .rate_limited
+            .entry((path_prefix.to_string(), key))
+            .or_insert(0) += 1;
+    }
+
+    pub fn record_rejection(&mut self, rejection: Rejection) {
+        *self.rejections.entry(rejection).or_insert(0) += 1;
+    }
+
+    pub fn record_shed(&mut self, priority: u8) {
+        *self.shed.entry(priority).or_insert(0) += 1;
+    }
+
+    pub fn record_upgrade(&mut self) {
+        self.upgrades += 1;
+    }
+
+    /// A request that got no response from any worker it was sent to.
+    pub fn record_upstream_failure(&mut self) {
+        self.upstream_failures += 1;
+    }
+
+    pub fn record_grpc_status(&mut self, code: GrpcCode) {
+        *self.grpc_responses.entry(code).or_insert(0) += 1;
     }
 
     pub fn get_average_response_time_ms(&self, algorithm_type: AlgorithmType) -> u128 {
@@ -32,6 +142,67 @@ impl Metrics {
             .unwrap_or(&0)
     }
 
+    /// Renders the metrics in the Prometheus text format.
+    pub fn report(&self) -> String {
+        let mut report = format!("request_count {}\n", self.request_count);
+        for (algorithm_type, average) in &self.average_response_time_for_algorithm {
+            report.push_str(&format!(
+                "average_response_time_ms{{algorithm=\"{:?}\"}} {}\n",
+                algorithm_type, average
+            ));
+        }
+        for (kind, count) in &self.timeouts {
+            report.push_str(&format!(
+                "timeouts_total{{kind=\"{:?}\"}} {}\n",
+                kind, count
+            ));
+        }
+        report.push_str(&format!("retries_total {}\n", self.retries));
+        report.push_str(&format!(
+            "retry_budget_exhausted_total {}\n",
+            self.retry_budget_exhausted
+        ));
+        report.push_str(&format!("hedged_requests_total {}\n", self.hedged_requests));
+        report.push_str(&format!("hedge_wins_total {}\n", self.hedge_wins));
+        report.push_str(&format!("upgraded_connections_total {}\n", self.upgrades));
+        report.push_str(&format!(
+            "upstream_failures_total {}\n",
+            self.upstream_failures
+        ));
+        for ((route, key), count) in &self.rate_limited {
+            report.push_str(&format!(
+                "rate_limited_total{{route=\"{}\",key=\"{}\"}} {}\n",
+                route, key, count
+            ));
+        }
+        for (rejection, count) in &self.rejections {
+            report.push_str(&format!(
+                "rejected_requests_total{{reason=\"{:?}\"}} {}\n",
+                rejection, count
+            ));
+        }
+        for (code, count) in &self.grpc_responses {
+            report.push_str(&format!(
+                "grpc_responses_total{{status=\"{}\"}} {}\n",
+                code.as_str(),
+                count
+            ));
+        }
+        for (priority, count) in &self.shed {
+            report.push_str(&format!(
+                "shed_requests_total{{priority=\"{}\"}} {}\n",
+                priority, count
+            ));
+        }
+        for ((host, state), count) in &self.circuit_transitions {
+            report.push_str(&format!(
+                "circuit_breaker_transitions_total{{worker=\"{}\",to=\"{:?}\"}} {}\n",
+                host, state, count
+            ));
+        }
+        report
+    }
+
     pub fn reset(&mut self, algorithm_type: AlgorithmType) {
         self.average_response_time_for_algorithm
             .remove(&algorithm_type);
@@ -0,0 +1,194 @@
+//! The HAProxy PROXY protocol, which prefixes a connection with the address
+//! of the client it is being relayed for.
+//! See <https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt>.
+
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+/// The longest v1 header allowed, CRLF included.
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_VERSION: u8 = 0x20;
+const V2_COMMAND_LOCAL: u8 = 0x00;
+const V2_COMMAND_PROXY: u8 = 0x01;
+const V2_FAMILY_TCP4: u8 = 0x11;
+const V2_FAMILY_UDP4: u8 = 0x12;
+const V2_FAMILY_TCP6: u8 = 0x21;
+const V2_FAMILY_UDP6: u8 = 0x22;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyProtocolVersion {
+    /// The human-readable text header.
+    V1,
+    /// The binary header.
+    V2,
+}
+
+/// The addresses a PROXY protocol header reports for the original
+/// connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ProxiedAddresses {
+    pub(crate) source: SocketAddr,
+    pub(crate) destination: SocketAddr,
+}
+
+/// Reads a v1 or v2 header from the start of `stream`, consuming exactly the
+/// header. Returns `None` for headers that carry no addresses, such as
+/// health checks from the proxy itself.
+pub(crate) async fn read_header<R: AsyncRead + Unpin>(
+    stream: &mut R,
+) -> io::Result<Option<ProxiedAddresses>> {
+    // No header is shorter than the v1 prefix, so reading it can't overrun
+    let mut prefix = [0; V1_PREFIX.len()];
+    stream.read_exact(&mut prefix).await?;
+    if prefix == V1_PREFIX {
+        read_v1(stream).await
+    } else if prefix == V2_SIGNATURE[..prefix.len()] {
+        read_v2(stream).await
+    } else {
+        Err(invalid("missing PROXY protocol header"))
+    }
+}
+
+async fn read_v1<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<Option<ProxiedAddresses>> {
+    let mut line = V1_PREFIX.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() == V1_MAX_LEN {
+            return Err(invalid("PROXY protocol v1 header too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line[V1_PREFIX.len()..line.len() - 2])
+        .map_err(|_| invalid("PROXY protocol v1 header is not ASCII"))?;
+
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["UNKNOWN", ..] => Ok(None),
+        [
+            family @ ("TCP4" | "TCP6"),
+            source,
+            destination,
+            source_port,
+            destination_port,
+        ] => {
+            let parse_ip = |ip: &str| {
+                ip.parse::<IpAddr>()
+                    .ok()
+                    .filter(|ip| ip.is_ipv4() == (*family == "TCP4"))
+                    .ok_or_else(|| invalid("bad address in PROXY protocol v1 header"))
+            };
+            let parse_port = |port: &str| {
+                port.parse::<u16>()
+                    .map_err(|_| invalid("bad port in PROXY protocol v1 header"))
+            };
+            Ok(Some(ProxiedAddresses {
+                source: SocketAddr::new(parse_ip(source)?, parse_port(source_port)?),
+                destination: SocketAddr::new(parse_ip(destination)?, parse_port(destination_port)?),
+            }))
+        }
+        _ => Err(invalid("malformed PROXY protocol v1 header")),
+    }
+}
+
+async fn read_v2<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<Option<ProxiedAddresses>> {
+    let mut header = [0; 16];
+    header[..V1_PREFIX.len()].copy_from_slice(&V2_SIGNATURE[..V1_PREFIX.len()]);
+    stream.read_exact(&mut header[V1_PREFIX.len()..]).await?;
+    if &header[..12] != V2_SIGNATURE {
+        return Err(invalid("missing PROXY protocol header"));
+    }
+    if header[12] & 0xf0 != V2_VERSION {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+    let mut payload = vec![0; u16::from_be_bytes([header[14], header[15]]) as usize];
+    stream.read_exact(&mut payload).await?;
+
+    match header[12] & 0x0f {
+        V2_COMMAND_LOCAL => return Ok(None),
+        V2_COMMAND_PROXY => {}
+        _ => return Err(invalid("unsupported PROXY protocol command")),
+    }
+    // Trailing TLVs are ignored
+    match header[13] {
+        V2_FAMILY_TCP4 | V2_FAMILY_UDP4 if payload.len() >= 12 => {
+            let ip = |at: usize| IpAddr::from(<[u8; 4]>::try_from(&payload[at..at + 4]).unwrap());
+            let port = |at: usize| u16::from_be_bytes([payload[at], payload[at + 1]]);
+            Ok(Some(ProxiedAddresses {
+                source: SocketAddr::new(ip(0), port(8)),
+                destination: SocketAddr::new(ip(4), port(10)),
+            }))
+        }
+        V2_FAMILY_TCP6 | V2_FAMILY_UDP6 if payload.len() >= 36 => {
+            let ip = |at: usize| IpAddr::from(<[u8; 16]>::try_from(&payload[at..at + 16]).unwrap());
+            let port = |at: usize| u16::from_be_bytes([payload[at], payload[at + 1]]);
+            Ok(Some(ProxiedAddresses {
+                source: SocketAddr::new(ip(0), port(32)),
+                destination: SocketAddr::new(ip(16), port(34)),
+            }))
+        }
+        // Unix sockets and unspecified families carry nothing usable
+        _ => Ok(None),
+    }
+}
+
+/// Encodes a header announcing a TCP connection from `source` to
+/// `destination`. Mixed address families are both sent as IPv6.
+pub(crate) fn encode_header(
+    version: ProxyProtocolVersion,
+    source: SocketAddr,
+    destination: SocketAddr,
+) -> Vec<u8> {
+    let (source_ip, destination_ip) = match (source.ip(), destination.ip()) {
+        (IpAddr::V4(source), IpAddr::V6(destination)) => {
+            (IpAddr::V6(source.to_ipv6_mapped()), IpAddr::V6(destination))
+        }
+        (IpAddr::V6(source), IpAddr::V4(destination)) => {
+            (IpAddr::V6(source), IpAddr::V6(destination.to_ipv6_mapped()))
+        }
+        addresses => addresses,
+    };
+
+    match version {
+        ProxyProtocolVersion::V1 => format!(
+            "PROXY {} {} {} {} {}\r\n",
+            if source_ip.is_ipv4() { "TCP4" } else { "TCP6" },
+            source_ip,
+            destination_ip,
+            source.port(),
+            destination.port()
+        )
+        .into_bytes(),
+        ProxyProtocolVersion::V2 => {
+            let mut header = V2_SIGNATURE.to_vec();
+            header.push(V2_VERSION | V2_COMMAND_PROXY);
+            let mut addresses = Vec::with_capacity(36);
+            match (source_ip, destination_ip) {
+                (IpAddr::V4(source_ip), IpAddr::V4(destination_ip)) => {
+                    header.push(V2_FAMILY_TCP4);
+                    addresses.extend_from_slice(&source_ip.octets());
+                    addresses.extend_from_slice(&destination_ip.octets());
+                }
+                (IpAddr::V6(source_ip), IpAddr::V6(destination_ip)) => {
+                    header.push(V2_FAMILY_TCP6);
+                    addresses.extend_from_slice(&source_ip.octets());
+                    addresses.extend_from_slice(&destination_ip.octets());
+                }
+                _ => unreachable!("address families were unified above"),
+            }
+            addresses.extend_from_slice(&source.port().to_be_bytes());
+            addresses.extend_from_slice(&destination.port().to_be_bytes());
+            header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+            header.extend_from_slice(&addresses);
+            header
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
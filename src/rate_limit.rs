@@ -0,0 +1,174 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use hyper::{HeaderMap, header::HeaderName};
+
+use crate::ClientIdentity;
+
+/// Buckets are pruned of idle (full) entries once there are this many.
+const MAX_BUCKETS: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RateLimitKey {
+    /// One bucket per client IP address.
+    ClientIp,
+    /// One bucket per value of this header, e.g. an API key. Requests
+    /// without the header aren't limited by the rule.
+    Header(HeaderName),
+    /// One bucket shared by every request to the route.
+    Route,
+    /// One bucket per verified client certificate. Requests without one
+    /// aren't limited by the rule.
+    ClientIdentity,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitRule {
+    /// Requests whose path starts with this prefix are limited.
+    pub path_prefix: String,
+    pub key: RateLimitKey,
+    /// Requests allowed in a burst; also the bucket capacity.
+    pub burst: u32,
+    /// Tokens added back per second.
+    pub refill_per_sec: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RateLimitConfig {
+    pub rules: Vec<RateLimitRule>,
+}
+
+/// The state of the most constrained bucket a request was checked against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitStatus {
+    pub limit: u32,
+    pub remaining: u32,
+    /// Time until the bucket is full again.
+    pub reset: Duration,
+    /// Set when the request was rejected: time until a token is available.
+    pub retry_after: Option<Duration>,
+}
+
+/// A request rejected by the rule at `rule_index`.
+pub(crate) struct RateLimited {
+    pub(crate) rule_index: usize,
+    pub(crate) status: RateLimitStatus,
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+pub(crate) struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Mutex<HashMap<(usize, String), Bucket>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(config: RateLimitConfig) -> Result<Self, String> {
+        if let Some(rule) = config
+            .rules
+            .iter()
+            .find(|rule| !rule.refill_per_sec.is_finite() || rule.refill_per_sec <= 0.0)
+        {
+            return Err(format!(
+                "Invalid refill rate {} for rate limit on {}",
+                rule.refill_per_sec, rule.path_prefix
+            ));
+        }
+        Ok(Self {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Takes a token from every bucket the request maps to. Returns the
+    /// most constrained bucket's status, or `None` if no rule applies.
+    pub(crate) fn check(
+        &self,
+        path: &str,
+        headers: &HeaderMap,
+        client_ip: Option<IpAddr>,
+        client_identity: Option<&ClientIdentity>,
+    ) -> Result<Option<RateLimitStatus>, RateLimited> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let now = Instant::now();
+        if buckets.len() >= MAX_BUCKETS {
+            buckets.retain(|(rule_index, _), bucket| {
+                let rule = &self.config.rules[*rule_index];
+                Self::refill(bucket, rule, now) < rule.burst as f64
+            });
+        }
+
+        let mut most_constrained: Option<RateLimitStatus> = None;
+        for (rule_index, rule) in self.config.rules.iter().enumerate() {
+            if !path.starts_with(&rule.path_prefix) {
+                continue;
+            }
+            let key = match &rule.key {
+                RateLimitKey::ClientIp => match client_ip {
+                    Some(ip) => ip.to_string(),
+                    None => continue,
+                },
+                RateLimitKey::Header(name) => match headers.get(name) {
+                    Some(value) => String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                    None => continue,
+                },
+                RateLimitKey::Route => rule.path_prefix.clone(),
+                RateLimitKey::ClientIdentity => match client_identity {
+                    Some(identity) => identity.to_string(),
+                    None => continue,
+                },
+            };
+
+            let bucket = buckets.entry((rule_index, key)).or_insert(Bucket {
+                tokens: rule.burst as f64,
+                last_refill: now,
+            });
+            let tokens = Self::refill(bucket, rule, now);
+
+            if tokens < 1.0 {
+                let status = RateLimitStatus {
+                    limit: rule.burst,
+                    remaining: 0,
+                    reset: Self::time_to_refill(rule, rule.burst as f64 - tokens),
+                    retry_after: Some(Self::time_to_refill(rule, 1.0 - tokens)),
+                };
+                return Err(RateLimited { rule_index, status });
+            }
+
+            bucket.tokens = tokens - 1.0;
+            let status = RateLimitStatus {
+                limit: rule.burst,
+                remaining: bucket.tokens.floor() as u32,
+                reset: Self::time_to_refill(rule, rule.burst as f64 - bucket.tokens),
+                retry_after: None,
+            };
+            if most_constrained.is_none_or(|current| status.remaining < current.remaining) {
+                most_constrained = Some(status);
+            }
+        }
+        Ok(most_constrained)
+    }
+
+    pub(crate) fn rule(&self, index: usize) -> &RateLimitRule {
+        &self.config.rules[index]
+    }
+
+    fn refill(bucket: &mut Bucket, rule: &RateLimitRule, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rule.refill_per_sec).min(rule.burst as f64);
+        bucket.last_refill = now;
+        bucket.tokens
+    }
+
+    /// Saturates at `Duration::MAX` for rates too slow to represent.
+    fn time_to_refill(rule: &RateLimitRule, tokens: f64) -> Duration {
+        Duration::try_from_secs_f64(tokens.max(0.0) / rule.refill_per_sec).unwrap_or(Duration::MAX)
+    }
+}
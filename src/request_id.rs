@@ -0,0 +1,100 @@
+use std::{fmt, net::IpAddr, sync::Arc};
+
+use hyper::{
+    Request,
+    header::{HeaderName, HeaderValue},
+};
+use uuid::Uuid;
+
+use crate::{ConnectionInfo, forwarding::ForwardingConfig};
+
+const DEFAULT_HEADER: &str = "x-request-id";
+/// Longest incoming ID that is reused rather than replaced.
+const MAX_INCOMING_LEN: usize = 200;
+
+/// Whose request IDs are reused instead of generating a fresh one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrustIncomingId {
+    /// Every request gets a new ID.
+    Never,
+    /// Clients listed in `ForwardingConfig::trusted_proxies`.
+    #[default]
+    TrustedProxies,
+    /// Any client, for balancers only reachable from inside the network.
+    Always,
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestIdConfig {
+    /// Carries the ID to the worker and back to the client.
+    pub header: HeaderName,
+    pub trust_incoming: TrustIncomingId,
+}
+
+impl Default for RequestIdConfig {
+    fn default() -> Self {
+        Self {
+            header: HeaderName::from_static(DEFAULT_HEADER),
+            trust_incoming: TrustIncomingId::default(),
+        }
+    }
+}
+
+/// Identifies one request in the worker's logs and in the balancer's own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RequestId(Arc<str>);
+
+impl RequestId {
+    pub(crate) fn header_value(&self) -> HeaderValue {
+        HeaderValue::from_str(&self.0).expect("request IDs are valid header values")
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl RequestIdConfig {
+    /// Reuses the request's ID if its client is trusted to set one, and
+    /// generates a UUIDv7 otherwise. The request then carries the ID in
+    /// its header for the worker.
+    pub(crate) fn assign<B>(
+        &self,
+        req: &mut Request<B>,
+        forwarding: &ForwardingConfig,
+    ) -> RequestId {
+        let client_ip = req
+            .extensions()
+            .get::<ConnectionInfo>()
+            .map(|info| info.peer_addr.ip());
+        let incoming = req
+            .headers()
+            .get(&self.header)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| is_valid(id))
+            .filter(|_| self.trusts(client_ip, forwarding));
+
+        let id = match incoming {
+            Some(id) => RequestId(id.into()),
+            None => RequestId(Uuid::now_v7().to_string().into()),
+        };
+        req.headers_mut().insert(&self.header, id.header_value());
+        id
+    }
+
+    fn trusts(&self, client_ip: Option<IpAddr>, forwarding: &ForwardingConfig) -> bool {
+        match self.trust_incoming {
+            TrustIncomingId::Never => false,
+            TrustIncomingId::TrustedProxies => {
+                client_ip.is_some_and(|ip| forwarding.is_trusted(ip))
+            }
+            TrustIncomingId::Always => true,
+        }
+    }
+}
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_INCOMING_LEN && id.bytes().all(|byte| byte.is_ascii_graphic())
+}
@@ -0,0 +1,203 @@
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering as AtomicOrdering},
+    },
+    time::Duration,
+};
+
+use hyper::{HeaderMap, header::HeaderName};
+use tokio::sync::oneshot;
+
+const DEFAULT_MAX_QUEUE_SIZE: usize = 100;
+const DEFAULT_QUEUE_TIMEOUT_SECS: u64 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueueOrdering {
+    /// Requests are served in arrival order.
+    Fifo,
+    /// Requests with a higher numeric value in this header are served
+    /// first; missing or invalid values count as 0.
+    Priority(HeaderName),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueConfig {
+    /// Requests allowed to wait at once; further requests get 503.
+    pub max_size: usize,
+    /// How long a request waits for a worker before getting 503.
+    pub timeout: Duration,
+    pub ordering: QueueOrdering,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            max_size: DEFAULT_MAX_QUEUE_SIZE,
+            timeout: Duration::from_secs(DEFAULT_QUEUE_TIMEOUT_SECS),
+            ordering: QueueOrdering::Fifo,
+        }
+    }
+}
+
+impl QueueConfig {
+    pub(crate) fn priority_of(&self, headers: &HeaderMap) -> u8 {
+        match &self.ordering {
+            QueueOrdering::Fifo => 0,
+            QueueOrdering::Priority(header) => headers
+                .get(header)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse().ok())
+                .unwrap_or(0),
+        }
+    }
+}
+
+/// A place in the queue. Keeping the ticket across wake-ups lets a request
+/// that loses the race for a freed slot keep its position.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct QueueTicket {
+    priority: u8,
+    seq: u64,
+}
+
+struct Waiter {
+    ticket: QueueTicket,
+    wake: oneshot::Sender<()>,
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Waiter {
+    // Max-heap: higher priority first, then earlier arrival
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.ticket
+            .priority
+            .cmp(&other.ticket.priority)
+            .then_with(|| other.ticket.seq.cmp(&self.ticket.seq))
+    }
+}
+
+/// Requests waiting for a worker with spare capacity.
+pub(crate) struct RequestQueue {
+    max_size: usize,
+    next_seq: AtomicU64,
+    waiters: Mutex<BinaryHeap<Waiter>>,
+}
+
+pub(crate) struct QueueFull;
+
+impl RequestQueue {
+    pub(crate) fn new(config: &QueueConfig) -> Self {
+        Self {
+            max_size: config.max_size,
+            next_seq: AtomicU64::new(0),
+            waiters: Mutex::new(BinaryHeap::new()),
+        }
+    }
+
+    pub(crate) fn ticket(&self, priority: u8) -> QueueTicket {
+        QueueTicket {
+            priority,
+            seq: self.next_seq.fetch_add(1, AtomicOrdering::Relaxed),
+        }
+    }
+
+    /// Joins the queue, holding the place until the returned request is
+    /// woken or dropped.
+    pub(crate) fn enqueue(
+        self: &Arc<Self>,
+        ticket: QueueTicket,
+    ) -> Result<QueuedRequest, QueueFull> {
+        let mut waiters = self.waiters.lock().unwrap();
+        prune(&mut waiters);
+        if waiters.len() >= self.max_size {
+            return Err(QueueFull);
+        }
+
+        let (wake, woken) = oneshot::channel();
+        waiters.push(Waiter { ticket, wake });
+        Ok(QueuedRequest {
+            queue: self.clone(),
+            ticket,
+            woken,
+            was_woken: false,
+        })
+    }
+
+    /// Wakes the first waiter still waiting.
+    pub(crate) fn wake_one(&self) {
+        let mut waiters = self.waiters.lock().unwrap();
+        while let Some(waiter) = waiters.pop() {
+            if waiter.wake.send(()).is_ok() {
+                return;
+            }
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        let mut waiters = self.waiters.lock().unwrap();
+        prune(&mut waiters);
+        waiters.len()
+    }
+
+    /// Whether `ticket` is next in line among the requests still waiting.
+    fn is_first(&self, ticket: QueueTicket) -> bool {
+        let mut waiters = self.waiters.lock().unwrap();
+        prune(&mut waiters);
+        waiters
+            .peek()
+            .is_some_and(|waiter| waiter.ticket.seq == ticket.seq)
+    }
+}
+
+/// Drops waiters that timed out or whose client went away.
+fn prune(waiters: &mut BinaryHeap<Waiter>) {
+    waiters.retain(|waiter| !waiter.wake.is_closed());
+}
+
+/// A request's place in the queue. Dropping it without having been woken,
+/// or with a wake-up it never used, wakes the next waiter instead, so a
+/// freed slot isn't left idle when a waiter times out or is cancelled.
+pub(crate) struct QueuedRequest {
+    queue: Arc<RequestQueue>,
+    ticket: QueueTicket,
+    woken: oneshot::Receiver<()>,
+    was_woken: bool,
+}
+
+impl QueuedRequest {
+    pub(crate) fn is_first(&self) -> bool {
+        self.queue.is_first(self.ticket)
+    }
+
+    /// Resolves once a worker slot may have freed up.
+    pub(crate) async fn woken(&mut self) {
+        let _ = (&mut self.woken).await;
+        self.was_woken = true;
+    }
+}
+
+impl Drop for QueuedRequest {
+    fn drop(&mut self) {
+        // Closing first means no wake-up can arrive after the check
+        self.woken.close();
+        if self.woken.try_recv().is_ok() || !self.was_woken {
+            self.queue.wake_one();
+        }
+    }
+}
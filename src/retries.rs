@@ -0,0 +1,211 @@
+use std::{
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use hyper::{
+    Method, StatusCode,
+    body::{Body, Bytes, Frame, Incoming, SizeHint},
+};
+
+const DEFAULT_MAX_RETRIES: u32 = 1;
+const DEFAULT_MAX_BUFFERED_BODY_BYTES: usize = 64 * 1024;
+const DEFAULT_BACKOFF_MS: u64 = 25;
+const DEFAULT_MAX_BACKOFF_MS: u64 = 250;
+const DEFAULT_BUDGET_RATIO: f64 = 0.2;
+const DEFAULT_BUDGET_MIN_PER_SEC: u32 = 10;
+/// The budget can save up at most this many seconds of `budget_min_per_sec`.
+const BUDGET_WINDOW_SECS: f64 = 10.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryConfig {
+    /// Retries after the first attempt; 0 disables retries.
+    pub max_retries: u32,
+    /// Worker responses that are retried for idempotent methods.
+    pub retry_on_status: Vec<StatusCode>,
+    /// Request bodies up to this size are buffered so they can be replayed.
+    /// Larger or unsized bodies are streamed and never retried.
+    pub max_buffered_body_bytes: usize,
+    /// Delay before the first retry, doubled on each following retry.
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// Retries allowed per proxied request, on top of `budget_min_per_sec`.
+    pub budget_ratio: f64,
+    /// Retries always allowed per second regardless of traffic.
+    pub budget_min_per_sec: u32,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: DEFAULT_MAX_RETRIES,
+            retry_on_status: vec![
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+            max_buffered_body_bytes: DEFAULT_MAX_BUFFERED_BODY_BYTES,
+            backoff: Duration::from_millis(DEFAULT_BACKOFF_MS),
+            max_backoff: Duration::from_millis(DEFAULT_MAX_BACKOFF_MS),
+            budget_ratio: DEFAULT_BUDGET_RATIO,
+            budget_min_per_sec: DEFAULT_BUDGET_MIN_PER_SEC,
+        }
+    }
+}
+
+impl RetryConfig {
+    pub(crate) fn backoff_for(&self, retry: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff)
+    }
+}
+
+/// Idempotent methods per RFC 9110 section 9.2.2.
+pub(crate) fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS | Method::TRACE
+    )
+}
+
+/// Limits retries across all requests so a struggling pool isn't hit with
+/// a retry storm.
+pub(crate) struct RetryBudget {
+    ratio: f64,
+    min_per_sec: f64,
+    max_tokens: f64,
+    state: Mutex<BudgetState>,
+}
+
+struct BudgetState {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RetryBudget {
+    pub(crate) fn new(config: &RetryConfig) -> Self {
+        let min_per_sec = config.budget_min_per_sec as f64;
+        let max_tokens = (min_per_sec * BUDGET_WINDOW_SECS).max(BUDGET_WINDOW_SECS);
+        Self {
+            ratio: config.budget_ratio,
+            min_per_sec,
+            max_tokens,
+            state: Mutex::new(BudgetState {
+                tokens: max_tokens,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    pub(crate) fn deposit(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.tokens = (state.tokens + self.ratio).min(self.max_tokens);
+    }
+
+    pub(crate) fn try_withdraw(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let refill = now.duration_since(state.last_refill).as_secs_f64() * self.min_per_sec;
+        state.tokens = (state.tokens + refill).min(self.max_tokens);
+        state.last_refill = now;
+
+        if state.tokens >= 1.0 {
+            state.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// A copy of a streamed request body, kept while it is sent to the first
+/// worker so that the request can be resent once the body is complete.
+pub(crate) struct Recording {
+    chunks: Vec<Bytes>,
+    len: usize,
+    limit: usize,
+    complete: bool,
+    /// Set once the body outgrew `limit`, had trailers or failed.
+    abandoned: bool,
+}
+
+impl Recording {
+    pub(crate) fn new(limit: usize) -> Self {
+        Self {
+            chunks: Vec::new(),
+            len: 0,
+            limit,
+            complete: false,
+            abandoned: false,
+        }
+    }
+
+    pub(crate) fn is_replayable(&self) -> bool {
+        self.complete && !self.abandoned
+    }
+
+    /// The whole body, if it has been recorded in full.
+    pub(crate) fn replay(&self) -> Option<Bytes> {
+        self.is_replayable()
+            .then(|| Bytes::from(self.chunks.concat()))
+    }
+
+    fn abandon(&mut self) {
+        self.abandoned = true;
+        self.chunks.clear();
+    }
+}
+
+/// Streams `inner` through, recording it as it goes.
+pub(crate) struct RecordingBody {
+    inner: Incoming,
+    recording: Arc<Mutex<Recording>>,
+}
+
+impl RecordingBody {
+    pub(crate) fn new(inner: Incoming, recording: Arc<Mutex<Recording>>) -> Self {
+        Self { inner, recording }
+    }
+}
+
+impl Body for RecordingBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let frame = Pin::new(&mut self.inner).poll_frame(cx);
+        let mut recording = self.recording.lock().unwrap();
+        match &frame {
+            Poll::Ready(Some(Ok(frame))) if !recording.abandoned => match frame.data_ref() {
+                Some(data) if recording.len + data.len() <= recording.limit => {
+                    recording.len += data.len();
+                    recording.chunks.push(data.clone());
+                }
+                _ => recording.abandon(),
+            },
+            Poll::Ready(Some(Err(_))) => recording.abandon(),
+            Poll::Ready(None) => recording.complete = true,
+            _ => {}
+        }
+        // Senders stop polling once the body says it has ended
+        if self.inner.is_end_stream() {
+            recording.complete = true;
+        }
+        drop(recording);
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,353 @@
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use hyper::{
+    Request,
+    body::Incoming,
+    header::{HeaderName, HeaderValue},
+    service::service_fn,
+    upgrade::OnUpgrade,
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::{
+        conn::auto,
+        graceful::{GracefulShutdown, Watcher},
+    },
+};
+use tokio::net::TcpListener;
+use tracing::{Instrument, debug, info, info_span, warn};
+
+use crate::{ClientIdentity, LoadBalancer, TimeoutKind, TlsTermination, h2c, proxy_protocol};
+
+const DEFAULT_SHUTDOWN_GRACE_PERIOD_SECS: u64 = 30;
+const DEFAULT_REQUEST_HEADER_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_MAX_CONCURRENT_STREAMS: u32 = 200;
+/// Pause before accepting again after running out of file descriptors or
+/// memory, which would otherwise fail again straight away in a hot loop.
+pub(crate) const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// How long in-flight requests may take to finish once shutdown starts.
+    pub shutdown_grace_period: Duration,
+    /// How long a client may take to send the request headers.
+    pub request_header_timeout: Option<Duration>,
+    pub http2: Http2Config,
+    /// Terminates TLS on every connection; `None` serves plain HTTP.
+    pub tls: Option<TlsTermination>,
+    /// Require a PROXY protocol v1 or v2 header on every connection, and take
+    /// the client address from it. Only enable this behind a proxy that
+    /// always sends one, since clients could otherwise forge their address.
+    /// Headers without an address, v1 `UNKNOWN` and v2 `LOCAL` as sent for
+    /// the proxy's own health checks, are accepted and keep the socket's
+    /// peer address.
+    pub accept_proxy_protocol: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            shutdown_grace_period: Duration::from_secs(DEFAULT_SHUTDOWN_GRACE_PERIOD_SECS),
+            request_header_timeout: Some(Duration::from_secs(DEFAULT_REQUEST_HEADER_TIMEOUT_SECS)),
+            http2: Http2Config::default(),
+            tls: None,
+            accept_proxy_protocol: false,
+        }
+    }
+}
+
+/// HTTP/2 settings for client connections. Clients may use HTTP/2 with prior
+/// knowledge or upgrade to it from HTTP/1.1. `None` keeps hyper's default.
+#[derive(Debug, Clone)]
+pub struct Http2Config {
+    /// Streams a client may have open at once on one connection.
+    pub max_concurrent_streams: Option<u32>,
+    /// Flow-control window for each stream, in bytes.
+    pub initial_stream_window_size: Option<u32>,
+    /// Flow-control window for the whole connection, in bytes.
+    pub initial_connection_window_size: Option<u32>,
+    /// Grow the windows from measured bandwidth-delay, overriding the
+    /// initial window sizes.
+    pub adaptive_window: bool,
+    pub max_frame_size: Option<u32>,
+    /// Accept `Upgrade: h2c` on HTTP/1.1 requests.
+    pub allow_upgrade: bool,
+}
+
+impl Default for Http2Config {
+    fn default() -> Self {
+        Self {
+            max_concurrent_streams: Some(DEFAULT_MAX_CONCURRENT_STREAMS),
+            initial_stream_window_size: None,
+            initial_connection_window_size: None,
+            adaptive_window: false,
+            max_frame_size: None,
+            allow_upgrade: true,
+        }
+    }
+}
+
+/// Details of the client connection, attached to every request's extensions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    pub peer_addr: SocketAddr,
+    /// Whether the client connected over TLS.
+    pub tls: bool,
+    /// The verified client certificate, when the listener uses mTLS.
+    pub client_identity: Option<Arc<ClientIdentity>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownOutcome {
+    /// Every connection finished within the grace period.
+    Graceful,
+    /// The grace period elapsed with connections still open.
+    GracePeriodElapsed,
+}
+
+pub struct Server {
+    load_balancer: Arc<LoadBalancer>,
+    builder: auto::Builder<TokioExecutor>,
+    config: ServerConfig,
+}
+
+/// An accepted h2c upgrade, served once the HTTP/1.1 connection hands over.
+type PendingUpgrade = Arc<Mutex<Option<(OnUpgrade, Vec<u8>)>>>;
+
+impl Server {
+    pub fn new(load_balancer: Arc<LoadBalancer>, config: ServerConfig) -> Self {
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        builder
+            .http1()
+            .timer(TokioTimer::new())
+            .header_read_timeout(config.request_header_timeout);
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .max_concurrent_streams(config.http2.max_concurrent_streams)
+            .initial_stream_window_size(config.http2.initial_stream_window_size)
+            .initial_connection_window_size(config.http2.initial_connection_window_size)
+            .adaptive_window(config.http2.adaptive_window)
+            .max_frame_size(config.http2.max_frame_size);
+        Self {
+            load_balancer,
+            builder,
+            config,
+        }
+    }
+
+    /// Accepts connections until `shutdown` resolves, then stops accepting,
+    /// closes idle keep-alive connections and waits for in-flight requests.
+    pub async fn serve(
+        &self,
+        listener: TcpListener,
+        shutdown: impl Future<Output = ()>,
+    ) -> ShutdownOutcome {
+        let graceful = GracefulShutdown::new();
+        tokio::pin!(shutdown);
+
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (mut stream, peer_addr) = match accepted {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            warn!(error = %e, "Failed to accept connection");
+                            if is_resource_exhaustion(&e) {
+                                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                            }
+                            continue;
+                        }
+                    };
+                    let span = info_span!("accept", peer = %peer_addr);
+                    span.in_scope(|| debug!("Accepted connection"));
+
+                    let mut connection = ClientConnection {
+                        load_balancer: self.load_balancer.clone(),
+                        builder: self.builder.clone(),
+                        info: ConnectionInfo {
+                            peer_addr,
+                            tls: self.config.tls.is_some(),
+                            client_identity: None,
+                        },
+                        identity_header: self
+                            .config
+                            .tls
+                            .as_ref()
+                            .and_then(|tls| tls.client_identity_header().cloned()),
+                        // h2c is cleartext only; over TLS, ALPN picks the protocol
+                        allow_upgrade: self.config.http2.allow_upgrade && self.config.tls.is_none(),
+                        watcher: graceful.watcher(),
+                        upgrade_watcher: graceful.watcher(),
+                    };
+                    let tls = self.config.tls.clone();
+                    let handshake_timeout = self.config.request_header_timeout;
+                    let accept_proxy_protocol = self.config.accept_proxy_protocol;
+                    tokio::spawn(async move {
+                        if accept_proxy_protocol {
+                            let header = with_timeout(
+                                handshake_timeout,
+                                proxy_protocol::read_header(&mut stream),
+                            );
+                            match header.await {
+                                Ok(Some(addresses)) => {
+                                    debug!(
+                                        client = %addresses.source,
+                                        "PROXY protocol header reports client"
+                                    );
+                                    connection.info.peer_addr = addresses.source;
+                                }
+                                Ok(None) => {}
+                                Err(e) => {
+                                    warn!(error = %e, "Bad PROXY protocol header");
+                                    return;
+                                }
+                            }
+                        }
+                        let Some(tls) = tls else {
+                            return connection.serve(TokioIo::new(stream)).await;
+                        };
+                        match with_timeout(handshake_timeout, tls.accept(stream)).await {
+                            Ok((stream, identity)) => {
+                                connection.info.client_identity = identity.map(Arc::new);
+                                connection.serve(TokioIo::new(stream)).await
+                            }
+                            Err(e) => warn!(error = %e, "TLS handshake failed"),
+                        }
+                    }.instrument(span));
+                }
+                _ = &mut shutdown => break,
+            }
+        }
+
+        drop(listener);
+        info!(
+            grace_period = ?self.config.shutdown_grace_period,
+            connections = graceful.count(),
+            "Shutting down"
+        );
+
+        match tokio::time::timeout(self.config.shutdown_grace_period, graceful.shutdown()).await {
+            Ok(()) => ShutdownOutcome::Graceful,
+            Err(_) => ShutdownOutcome::GracePeriodElapsed,
+        }
+    }
+}
+
+/// Whether an `accept` error means the process or system ran out of file
+/// descriptors, buffers or memory.
+pub(crate) fn is_resource_exhaustion(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    if let Some(code) = e.raw_os_error() {
+        return matches!(
+            code,
+            libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM
+        );
+    }
+    e.kind() == io::ErrorKind::OutOfMemory
+}
+
+/// Runs `future`, failing with `TimedOut` once `timeout` elapses.
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    future: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future)
+            .await
+            .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"))),
+        None => future.await,
+    }
+}
+
+/// One accepted client connection, served over plain TCP or TLS.
+struct ClientConnection {
+    load_balancer: Arc<LoadBalancer>,
+    builder: auto::Builder<TokioExecutor>,
+    info: ConnectionInfo,
+    /// Replaced on every request with the client's verified identity.
+    identity_header: Option<HeaderName>,
+    allow_upgrade: bool,
+    watcher: Watcher,
+    upgrade_watcher: Watcher,
+}
+
+impl ClientConnection {
+    async fn serve<I>(self, io: I)
+    where
+        I: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+    {
+        let ClientConnection {
+            load_balancer,
+            builder,
+            info,
+            identity_header,
+            allow_upgrade,
+            watcher,
+            upgrade_watcher,
+        } = self;
+        let pending_upgrade = PendingUpgrade::default();
+        let upgrade_slot = pending_upgrade.clone();
+        let request_load_balancer = load_balancer.clone();
+        let service = service_fn(move |mut req: Request<Incoming>| {
+            if let Some(header) = &identity_header {
+                req.headers_mut().remove(header);
+                if let Some(identity) = &info.client_identity
+                    && let Ok(value) = HeaderValue::from_str(&identity.to_string())
+                {
+                    req.headers_mut().insert(header.clone(), value);
+                }
+            }
+            req.extensions_mut().insert(info.clone());
+            let load_balancer = request_load_balancer.clone();
+            let upgrade_slot = upgrade_slot.clone();
+            async move {
+                if allow_upgrade && h2c::is_upgrade_request(&req) {
+                    let on_upgrade = hyper::upgrade::on(&mut req);
+                    let (parts, _) = req.into_parts();
+                    *upgrade_slot.lock().unwrap() = Some((on_upgrade, h2c::replay_frames(&parts)));
+                    return Ok(h2c::switching_protocols());
+                }
+                load_balancer.handle_request(req).await
+            }
+        });
+
+        let connection = builder.serve_connection_with_upgrades(io, service.clone());
+        if let Err(e) = watcher.watch(connection.into_owned()).await {
+            if e.downcast_ref::<hyper::Error>()
+                .is_some_and(|e| e.is_timeout())
+            {
+                load_balancer
+                    .record_timeout(TimeoutKind::RequestHeader)
+                    .await;
+            }
+            warn!(error = %e, "Connection error");
+        }
+
+        let Some((on_upgrade, replay)) = pending_upgrade.lock().unwrap().take() else {
+            return;
+        };
+        let upgraded = match on_upgrade.await {
+            Ok(upgraded) => upgraded,
+            Err(e) => {
+                warn!(error = %e, "h2c upgrade failed");
+                return;
+            }
+        };
+        debug!("Upgraded connection to h2c");
+        let io = h2c::UpgradedIo::new(TokioIo::new(upgraded), replay);
+        let connection = builder
+            .http2_only()
+            .serve_connection(TokioIo::new(io), service)
+            .into_owned();
+        if let Err(e) = upgrade_watcher.watch(connection).await {
+            warn!(error = %e, "Connection error");
+        }
+    }
+}
@@ -0,0 +1,434 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    net::SocketAddr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::Instant,
+};
+use tracing::{Instrument, info, info_span, warn};
+
+use crate::{
+    ProxyProtocolVersion, Worker,
+    balancing_algorithms::BalancingAlgorithm,
+    proxy_protocol::{self, ProxiedAddresses},
+    server::{ACCEPT_ERROR_BACKOFF, is_resource_exhaustion},
+};
+
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 5;
+const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 300;
+const BUFFER_SIZE: usize = 16 * 1024;
+
+/// Layer-4 proxying, where a worker's `host` is its `host:port` address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TcpProxyConfig {
+    /// How long connecting to one worker may take before the next is tried.
+    pub connect_timeout: Option<Duration>,
+    /// Connections with no bytes flowing either way for this long are closed.
+    /// Also bounds the wait for an inbound PROXY protocol header.
+    pub idle_timeout: Option<Duration>,
+    /// Require a PROXY protocol header on every connection, and take the
+    /// client address from it. Headers without an address, v1 `UNKNOWN` and
+    /// v2 `LOCAL`, are accepted and keep the socket's peer address.
+    pub accept_proxy_protocol: bool,
+    /// Announce the client's address to workers with a PROXY protocol
+    /// header before any of its bytes.
+    pub send_proxy_protocol: Option<ProxyProtocolVersion>,
+}
+
+impl Default for TcpProxyConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Some(Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS)),
+            idle_timeout: Some(Duration::from_secs(DEFAULT_IDLE_TIMEOUT_SECS)),
+            accept_proxy_protocol: false,
+            send_proxy_protocol: None,
+        }
+    }
+}
+
+/// Bytes moved so far over one open connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TcpConnectionStats {
+    pub client_addr: SocketAddr,
+    pub worker: String,
+    pub bytes_to_worker: u64,
+    pub bytes_to_client: u64,
+}
+
+/// Pipes accepted TCP connections to workers chosen by a balancing
+/// algorithm. A connection holds its worker until either side closes it, so
+/// least-connections balances by open sockets.
+pub struct TcpProxy {
+    workers: Vec<Worker>,
+    algorithm: Mutex<Box<dyn BalancingAlgorithm>>,
+    config: TcpProxyConfig,
+    connections: Mutex<HashMap<u64, Arc<ConnectionCounters>>>,
+    next_connection_id: AtomicU64,
+    totals: TcpTotals,
+}
+
+struct ConnectionCounters {
+    client_addr: SocketAddr,
+    worker: String,
+    to_worker: AtomicU64,
+    to_client: AtomicU64,
+}
+
+#[derive(Default)]
+struct TcpTotals {
+    connections: AtomicU64,
+    connect_failures: AtomicU64,
+    idle_timeouts: AtomicU64,
+    bytes_to_workers: AtomicU64,
+    bytes_to_clients: AtomicU64,
+    active: AtomicUsize,
+}
+
+impl TcpProxy {
+    pub fn new(
+        workers: Vec<Worker>,
+        algorithm: Box<dyn BalancingAlgorithm>,
+        config: TcpProxyConfig,
+    ) -> Result<Self, String> {
+        if workers.is_empty() {
+            return Err("Worker hosts list cannot be empty".to_string());
+        }
+        Ok(Self {
+            workers,
+            algorithm: Mutex::new(algorithm),
+            config,
+            connections: Mutex::new(HashMap::new()),
+            next_connection_id: AtomicU64::new(0),
+            totals: TcpTotals::default(),
+        })
+    }
+
+    /// Accepts connections until `shutdown` resolves. Connections already
+    /// open are left to finish on their own.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener, shutdown: impl Future<Output = ()>) {
+        tokio::pin!(shutdown);
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, client_addr) = match accepted {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            warn!(error = %e, "Failed to accept TCP connection");
+                            if is_resource_exhaustion(&e) {
+                                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                            }
+                            continue;
+                        }
+                    };
+                    let proxy = self.clone();
+                    let span = info_span!("tcp_connection", peer = %client_addr);
+                    tokio::spawn(
+                        async move { proxy.handle_connection(stream, client_addr).await }
+                            .instrument(span),
+                    );
+                }
+                _ = &mut shutdown => break,
+            }
+        }
+    }
+
+    async fn handle_connection(&self, mut client: TcpStream, peer_addr: SocketAddr) {
+        self.totals.connections.fetch_add(1, Ordering::Relaxed);
+        let mut addresses = ProxiedAddresses {
+            source: peer_addr,
+            destination: match client.local_addr() {
+                Ok(addr) => addr,
+                Err(e) => {
+                    warn!(error = %e, "Failed to read local address");
+                    return;
+                }
+            },
+        };
+        if self.config.accept_proxy_protocol {
+            let header = proxy_protocol::read_header(&mut client);
+            let header = match self.config.idle_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, header)
+                    .await
+                    .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"))),
+                None => header.await,
+            };
+            match header {
+                Ok(Some(proxied)) => addresses = proxied,
+                Ok(None) => {}
+                Err(e) => {
+                    warn!(error = %e, "Bad PROXY protocol header");
+                    return;
+                }
+            }
+        }
+        let client_addr = addresses.source;
+
+        let Some((mut upstream, lease)) = self.connect(client_addr).await else {
+            warn!(client = %client_addr, "No worker reachable for TCP client");
+            return;
+        };
+        if let Some(version) = self.config.send_proxy_protocol {
+            let header =
+                proxy_protocol::encode_header(version, addresses.source, addresses.destination);
+            if let Err(e) = upstream.write_all(&header).await {
+                warn!(
+                    worker = %lease.worker.host,
+                    error = %e,
+                    "Failed to send PROXY protocol header"
+                );
+                return;
+            }
+        }
+
+        let id = self.next_connection_id.fetch_add(1, Ordering::Relaxed);
+        let counters = Arc::new(ConnectionCounters {
+            client_addr,
+            worker: lease.worker.host.clone(),
+            to_worker: AtomicU64::new(0),
+            to_client: AtomicU64::new(0),
+        });
+        self.connections
+            .lock()
+            .unwrap()
+            .insert(id, counters.clone());
+        self.totals.active.fetch_add(1, Ordering::SeqCst);
+
+        let result = pipe(client, upstream, self.config.idle_timeout, &counters).await;
+
+        self.totals.active.fetch_sub(1, Ordering::SeqCst);
+        self.connections.lock().unwrap().remove(&id);
+        let to_worker = counters.to_worker.load(Ordering::Relaxed);
+        let to_client = counters.to_client.load(Ordering::Relaxed);
+        self.totals
+            .bytes_to_workers
+            .fetch_add(to_worker, Ordering::Relaxed);
+        self.totals
+            .bytes_to_clients
+            .fetch_add(to_client, Ordering::Relaxed);
+        match result {
+            Ok(()) => info!(
+                client = %client_addr,
+                worker = %counters.worker,
+                bytes_to_worker = to_worker,
+                bytes_to_client = to_client,
+                "Closed TCP connection"
+            ),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                self.totals.idle_timeouts.fetch_add(1, Ordering::Relaxed);
+                info!(
+                    client = %client_addr,
+                    worker = %counters.worker,
+                    "Closed idle TCP connection"
+                );
+            }
+            Err(e) => warn!(
+                client = %client_addr,
+                worker = %counters.worker,
+                error = %e,
+                "TCP connection failed"
+            ),
+        }
+        drop(lease);
+    }
+
+    /// Connects to a worker chosen by the algorithm, moving on to the next
+    /// choice whenever a worker can't be reached.
+    async fn connect(&self, client_addr: SocketAddr) -> Option<(TcpStream, WorkerLease<'_>)> {
+        let mut tried = Vec::new();
+        loop {
+            let lease = self.choose(&tried)?;
+            let connect = TcpStream::connect(&lease.worker.host);
+            let result = match self.config.connect_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, connect)
+                    .await
+                    .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"))),
+                None => connect.await,
+            };
+            match result {
+                Ok(upstream) => {
+                    info!(
+                        client = %client_addr,
+                        worker = %lease.worker.host,
+                        "Proxying TCP client"
+                    );
+                    return Some((upstream, lease));
+                }
+                Err(e) => {
+                    warn!(worker = %lease.worker.host, error = %e, "Failed to connect to worker");
+                    self.totals.connect_failures.fetch_add(1, Ordering::Relaxed);
+                    tried.push(lease.worker.host.clone());
+                }
+            }
+        }
+    }
+
+    fn choose(&self, excluded_hosts: &[String]) -> Option<WorkerLease<'_>> {
+        let eligible: Vec<Worker> = self
+            .workers
+            .iter()
+            .filter(|worker| !excluded_hosts.contains(&worker.host))
+            .cloned()
+            .collect();
+        if eligible.is_empty() {
+            return None;
+        }
+        let worker = self.algorithm.lock().unwrap().choose(&eligible).clone();
+        Some(WorkerLease {
+            algorithm: &self.algorithm,
+            worker,
+        })
+    }
+
+    pub fn active_connections(&self) -> usize {
+        self.totals.active.load(Ordering::SeqCst)
+    }
+
+    /// Byte counters of every open connection.
+    pub fn connections(&self) -> Vec<TcpConnectionStats> {
+        self.connections
+            .lock()
+            .unwrap()
+            .values()
+            .map(|counters| TcpConnectionStats {
+                client_addr: counters.client_addr,
+                worker: counters.worker.clone(),
+                bytes_to_worker: counters.to_worker.load(Ordering::Relaxed),
+                bytes_to_client: counters.to_client.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Renders the metrics in the Prometheus text format. Byte totals cover
+    /// closed connections.
+    pub fn metrics_report(&self) -> String {
+        let totals = &self.totals;
+        let mut report = format!(
+            "tcp_connections_total {}\n",
+            totals.connections.load(Ordering::Relaxed)
+        );
+        report.push_str(&format!(
+            "tcp_active_connections {}\n",
+            totals.active.load(Ordering::SeqCst)
+        ));
+        report.push_str(&format!(
+            "tcp_connect_failures_total {}\n",
+            totals.connect_failures.load(Ordering::Relaxed)
+        ));
+        report.push_str(&format!(
+            "tcp_idle_timeouts_total {}\n",
+            totals.idle_timeouts.load(Ordering::Relaxed)
+        ));
+        report.push_str(&format!(
+            "tcp_bytes_total{{direction=\"to_worker\"}} {}\n",
+            totals.bytes_to_workers.load(Ordering::Relaxed)
+        ));
+        report.push_str(&format!(
+            "tcp_bytes_total{{direction=\"to_client\"}} {}\n",
+            totals.bytes_to_clients.load(Ordering::Relaxed)
+        ));
+        report
+    }
+}
+
+/// A worker chosen for a connection, released back to the algorithm on drop.
+struct WorkerLease<'a> {
+    algorithm: &'a Mutex<Box<dyn BalancingAlgorithm>>,
+    worker: Worker,
+}
+
+impl Drop for WorkerLease<'_> {
+    fn drop(&mut self) {
+        self.algorithm.lock().unwrap().release(&self.worker);
+    }
+}
+
+/// Copies bytes both ways until both sides have closed, passing on
+/// half-closes. Fails with `TimedOut` once nothing has been read or written
+/// for `idle`, including while a write is stuck on a peer that stopped
+/// reading.
+async fn pipe(
+    client: TcpStream,
+    upstream: TcpStream,
+    idle: Option<Duration>,
+    counters: &ConnectionCounters,
+) -> io::Result<()> {
+    let (mut client_read, mut client_write) = client.into_split();
+    let (mut upstream_read, mut upstream_write) = upstream.into_split();
+    let last_activity = Mutex::new(Instant::now());
+
+    // Each direction runs on its own, so one blocked write can't stall the other
+    let copies = async {
+        tokio::try_join!(
+            copy_half(
+                &mut client_read,
+                &mut upstream_write,
+                &counters.to_worker,
+                &last_activity
+            ),
+            copy_half(
+                &mut upstream_read,
+                &mut client_write,
+                &counters.to_client,
+                &last_activity
+            ),
+        )
+        .map(|_| ())
+    };
+    let Some(idle) = idle else {
+        return copies.await;
+    };
+    tokio::select! {
+        result = copies => result,
+        _ = idle_expiry(idle, &last_activity) => {
+            Err(io::Error::new(io::ErrorKind::TimedOut, "idle timeout"))
+        }
+    }
+}
+
+/// Copies from `reader` to `writer` until `reader` closes, then shuts down
+/// `writer`.
+async fn copy_half(
+    reader: &mut (impl AsyncRead + Unpin),
+    writer: &mut (impl AsyncWrite + Unpin),
+    bytes: &AtomicU64,
+    last_activity: &Mutex<Instant>,
+) -> io::Result<()> {
+    let mut buf = vec![0; BUFFER_SIZE];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        *last_activity.lock().unwrap() = Instant::now();
+        if n == 0 {
+            return writer.shutdown().await;
+        }
+        let mut written = 0;
+        while written < n {
+            match writer.write(&buf[written..n]).await? {
+                0 => return Err(io::ErrorKind::WriteZero.into()),
+                sent => written += sent,
+            }
+            *last_activity.lock().unwrap() = Instant::now();
+        }
+        bytes.fetch_add(n as u64, Ordering::Relaxed);
+    }
+}
+
+/// Resolves once `idle` has passed since the last activity.
+async fn idle_expiry(idle: Duration, last_activity: &Mutex<Instant>) {
+    loop {
+        let deadline = *last_activity.lock().unwrap() + idle;
+        if deadline <= Instant::now() {
+            return;
+        }
+        tokio::time::sleep_until(deadline).await;
+    }
+}
@@ -0,0 +1,158 @@
+use std::{
+    error::Error,
+    fmt,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use tokio::time::{Instant, Sleep};
+
+use crate::ResponseBody;
+
+const DEFAULT_FIRST_BYTE_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_IDLE_BODY_TIMEOUT_SECS: u64 = 30;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeoutConfig {
+    /// Time allowed for the worker to send response headers.
+    pub first_byte: Option<Duration>,
+    /// Longest gap allowed between chunks of the worker's response body.
+    pub idle_body: Option<Duration>,
+    /// Time allowed for the whole exchange, including the response body.
+    pub total: Option<Duration>,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            first_byte: Some(Duration::from_secs(DEFAULT_FIRST_BYTE_TIMEOUT_SECS)),
+            idle_body: Some(Duration::from_secs(DEFAULT_IDLE_BODY_TIMEOUT_SECS)),
+            total: None,
+        }
+    }
+}
+
+impl TimeoutConfig {
+    /// Fills any unset timeout from `fallback`.
+    pub(crate) fn or(&self, fallback: &TimeoutConfig) -> TimeoutConfig {
+        TimeoutConfig {
+            first_byte: self.first_byte.or(fallback.first_byte),
+            idle_body: self.idle_body.or(fallback.idle_body),
+            total: self.total.or(fallback.total),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteTimeouts {
+    pub path_prefix: String,
+    pub timeouts: TimeoutConfig,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TimeoutKind {
+    Connect,
+    RequestHeader,
+    FirstByte,
+    IdleBody,
+    Total,
+}
+
+#[derive(Debug)]
+pub struct TimeoutError(pub TimeoutKind);
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} timeout elapsed", self.0)
+    }
+}
+
+impl Error for TimeoutError {}
+
+/// Fails the response body if the worker stalls between chunks for longer
+/// than the idle timeout or the total deadline passes.
+pub(crate) struct TimeoutBody {
+    inner: ResponseBody,
+    idle: Option<Duration>,
+    deadline: Option<Instant>,
+    sleep: Option<Pin<Box<Sleep>>>,
+    on_timeout: Option<Box<dyn FnOnce(TimeoutKind) + Send + Sync>>,
+}
+
+impl TimeoutBody {
+    pub(crate) fn new(
+        inner: ResponseBody,
+        idle: Option<Duration>,
+        deadline: Option<Instant>,
+        on_timeout: impl FnOnce(TimeoutKind) + Send + Sync + 'static,
+    ) -> Self {
+        let mut body = Self {
+            inner,
+            idle,
+            deadline,
+            sleep: None,
+            on_timeout: Some(Box::new(on_timeout)),
+        };
+        body.sleep = body
+            .next_deadline()
+            .map(|at| Box::pin(tokio::time::sleep_until(at)));
+        body
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        let idle_deadline = self.idle.map(|idle| Instant::now() + idle);
+        match (idle_deadline, self.deadline) {
+            (Some(idle), Some(total)) => Some(idle.min(total)),
+            (idle, total) => idle.or(total),
+        }
+    }
+
+    fn timeout_kind(&self) -> TimeoutKind {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => TimeoutKind::Total,
+            _ => TimeoutKind::IdleBody,
+        }
+    }
+}
+
+impl Body for TimeoutBody {
+    type Data = Bytes;
+    type Error = Box<dyn Error + Send + Sync>;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = &mut *self;
+        if let Poll::Ready(frame) = Pin::new(&mut this.inner).poll_frame(cx) {
+            if let Some(at) = this.next_deadline()
+                && let Some(sleep) = this.sleep.as_mut()
+            {
+                sleep.as_mut().reset(at);
+            }
+            return Poll::Ready(frame);
+        }
+
+        if let Some(sleep) = this.sleep.as_mut()
+            && sleep.as_mut().poll(cx).is_ready()
+        {
+            let kind = this.timeout_kind();
+            if let Some(on_timeout) = this.on_timeout.take() {
+                on_timeout(kind);
+            }
+            return Poll::Ready(Some(Err(Box::new(TimeoutError(kind)))));
+        }
+
+        Poll::Pending
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,505 @@
+use std::{
+    collections::HashMap,
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use hyper::header::HeaderName;
+use rustls::{
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme, SupportedProtocolVersion,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature},
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime, pem::PemObject},
+    server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier, danger::ClientCertVerifier},
+    sign::CertifiedKey,
+};
+use tokio::net::TcpStream;
+use tokio_rustls::{TlsAcceptor, server::TlsStream};
+use tracing::info;
+use x509_parser::extensions::GeneralName;
+
+const DEFAULT_CLIENT_IDENTITY_HEADER: &str = "x-forwarded-client-cert";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+}
+
+/// A PEM certificate chain and private key served for some server names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsCertificate {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// SNI names this certificate is served for. `*.example.com` matches
+    /// any single label under `example.com`.
+    pub server_names: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// The first certificate is also served to clients whose SNI matches
+    /// none of them, or who send none.
+    pub certificates: Vec<TlsCertificate>,
+    pub versions: Vec<TlsVersion>,
+    /// Cipher suite names such as `TLS13_AES_128_GCM_SHA256`; empty allows
+    /// every suite the crypto provider supports.
+    pub cipher_suites: Vec<String>,
+    /// Verifies client certificates; `None` doesn't ask for them.
+    pub client_auth: Option<ClientAuthConfig>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            certificates: Vec::new(),
+            versions: vec![TlsVersion::Tls12, TlsVersion::Tls13],
+            cipher_suites: Vec::new(),
+            client_auth: None,
+        }
+    }
+}
+
+/// Client certificate authentication (mTLS) on the listener. The verified
+/// [`ClientIdentity`] can key rate limits and load-shedding priorities, and
+/// is forwarded to workers; it doesn't influence which worker is chosen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientAuthConfig {
+    /// PEM CA certificates that client certificates must chain to.
+    pub ca_bundle: PathBuf,
+    /// Refuse clients without a certificate. Otherwise they may connect
+    /// anonymously, but a certificate they do present must still verify.
+    pub required: bool,
+    /// Header that carries the verified [`ClientIdentity`] to workers. Any
+    /// value the client sent in it is removed first.
+    pub forward_header: Option<HeaderName>,
+}
+
+impl ClientAuthConfig {
+    pub fn new(ca_bundle: impl Into<PathBuf>) -> Self {
+        Self {
+            ca_bundle: ca_bundle.into(),
+            required: true,
+            forward_header: Some(HeaderName::from_static(DEFAULT_CLIENT_IDENTITY_HEADER)),
+        }
+    }
+
+    fn verifier(
+        &self,
+        provider: &Arc<CryptoProvider>,
+    ) -> Result<Arc<dyn ClientCertVerifier>, String> {
+        let roots = load_roots(&self.ca_bundle)?;
+        let builder =
+            WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone());
+        let builder = if self.required {
+            builder
+        } else {
+            builder.allow_unauthenticated()
+        };
+        builder.build().map_err(|e| {
+            format!(
+                "Invalid client CA bundle {}: {}",
+                self.ca_bundle.display(),
+                e
+            )
+        })
+    }
+}
+
+/// The verified certificate a client authenticated with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdentity {
+    /// Distinguished name, e.g. `CN=billing, O=Example`.
+    pub subject: String,
+    pub dns_names: Vec<String>,
+    pub uris: Vec<String>,
+}
+
+impl ClientIdentity {
+    fn from_certificate(der: &CertificateDer<'_>) -> Option<Self> {
+        let (_, certificate) = x509_parser::parse_x509_certificate(der).ok()?;
+        let mut identity = Self {
+            subject: certificate.subject().to_string(),
+            dns_names: Vec::new(),
+            uris: Vec::new(),
+        };
+        if let Ok(Some(names)) = certificate.subject_alternative_name() {
+            for name in &names.value.general_names {
+                match name {
+                    GeneralName::DNSName(name) => identity.dns_names.push(name.to_string()),
+                    GeneralName::URI(uri) => identity.uris.push(uri.to_string()),
+                    _ => {}
+                }
+            }
+        }
+        Some(identity)
+    }
+
+    /// Whether `name` is the subject or one of the subject alternative names.
+    pub fn matches(&self, name: &str) -> bool {
+        self.subject == name
+            || self.dns_names.iter().any(|dns_name| dns_name == name)
+            || self.uris.iter().any(|uri| uri == name)
+    }
+}
+
+/// Formats like Envoy's `x-forwarded-client-cert`:
+/// `Subject="CN=billing";DNS=billing.internal;URI=spiffe://example/billing`.
+/// Names containing `;`, `,` or `"` are quoted so they can't split the value.
+impl fmt::Display for ClientIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Subject={}", quoted(&self.subject))?;
+        for dns_name in &self.dns_names {
+            write!(f, ";DNS={}", quote_if_needed(dns_name))?;
+        }
+        for uri in &self.uris {
+            write!(f, ";URI={}", quote_if_needed(uri))?;
+        }
+        Ok(())
+    }
+}
+
+fn quoted(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn quote_if_needed(value: &str) -> String {
+    if value.contains([';', ',', '"']) {
+        quoted(value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Terminates TLS on accepted connections. Clones share their certificates,
+/// so a [`reload`](Self::reload) applies to every server using them.
+#[derive(Clone)]
+pub struct TlsTermination {
+    /// Replaced on reload when client certificates are verified, since the
+    /// verifier's CAs are fixed once built.
+    acceptor: Arc<RwLock<TlsAcceptor>>,
+    resolver: Arc<CertificateResolver>,
+}
+
+impl fmt::Debug for TlsTermination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsTermination")
+            .field("certificates", &self.resolver.config.certificates)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TlsTermination {
+    pub fn new(config: TlsConfig) -> Result<Self, String> {
+        if config.certificates.is_empty() {
+            return Err("TLS needs at least one certificate".to_string());
+        }
+
+        let mut provider = rustls::crypto::ring::default_provider();
+        if !config.cipher_suites.is_empty() {
+            provider.cipher_suites = config
+                .cipher_suites
+                .iter()
+                .map(|name| {
+                    provider
+                        .cipher_suites
+                        .iter()
+                        .find(|suite| format!("{:?}", suite.suite()) == *name)
+                        .copied()
+                        .ok_or_else(|| format!("Unsupported cipher suite: {}", name))
+                })
+                .collect::<Result<_, _>>()?;
+        }
+        let provider = Arc::new(provider);
+        let resolver = Arc::new(CertificateResolver {
+            store: RwLock::new(Arc::new(CertificateStore::load(&config, &provider)?)),
+            provider,
+            config,
+        });
+
+        Ok(Self {
+            acceptor: Arc::new(RwLock::new(acceptor(&resolver)?)),
+            resolver,
+        })
+    }
+
+    /// Re-reads every certificate and key from disk, and the client CA
+    /// bundle. New handshakes use them straight away; on error the current
+    /// ones stay in use.
+    pub fn reload(&self) -> Result<(), String> {
+        let store = CertificateStore::load(&self.resolver.config, &self.resolver.provider)?;
+        let acceptor = match self.resolver.config.client_auth {
+            Some(_) => Some(acceptor(&self.resolver)?),
+            None => None,
+        };
+        *self.resolver.store.write().unwrap() = Arc::new(store);
+        if let Some(acceptor) = acceptor {
+            *self.acceptor.write().unwrap() = acceptor;
+        }
+        info!("Reloaded TLS certificates");
+        Ok(())
+    }
+
+    /// Completes the handshake, returning the client's identity if it
+    /// presented a certificate.
+    pub(crate) async fn accept(
+        &self,
+        stream: TcpStream,
+    ) -> io::Result<(TlsStream<TcpStream>, Option<ClientIdentity>)> {
+        let acceptor = self.acceptor.read().unwrap().clone();
+        let stream = acceptor.accept(stream).await?;
+        let identity = stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|certificates| certificates.first())
+            .and_then(ClientIdentity::from_certificate);
+        Ok((stream, identity))
+    }
+
+    pub(crate) fn client_identity_header(&self) -> Option<&HeaderName> {
+        self.resolver
+            .config
+            .client_auth
+            .as_ref()?
+            .forward_header
+            .as_ref()
+    }
+}
+
+/// Builds the listener's TLS settings, reading the client CA bundle afresh.
+fn acceptor(resolver: &Arc<CertificateResolver>) -> Result<TlsAcceptor, String> {
+    let versions: Vec<&'static SupportedProtocolVersion> = resolver
+        .config
+        .versions
+        .iter()
+        .map(|version| match version {
+            TlsVersion::Tls12 => &rustls::version::TLS12,
+            TlsVersion::Tls13 => &rustls::version::TLS13,
+        })
+        .collect();
+
+    let builder = rustls::ServerConfig::builder_with_provider(resolver.provider.clone())
+        .with_protocol_versions(&versions)
+        .map_err(|e| format!("Invalid TLS versions: {}", e))?;
+    let builder = match &resolver.config.client_auth {
+        Some(client_auth) => {
+            builder.with_client_cert_verifier(client_auth.verifier(&resolver.provider)?)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut server_config = builder.with_cert_resolver(resolver.clone());
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// Picks the certificate for each handshake from the client's SNI.
+#[derive(Debug)]
+struct CertificateResolver {
+    store: RwLock<Arc<CertificateStore>>,
+    provider: Arc<CryptoProvider>,
+    config: TlsConfig,
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let store = self.store.read().unwrap().clone();
+        let by_name = client_hello
+            .server_name()
+            .and_then(|name| store.find(&name.to_ascii_lowercase()));
+        Some(by_name.unwrap_or_else(|| store.default.clone()))
+    }
+}
+
+#[derive(Debug)]
+struct CertificateStore {
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+    default: Arc<CertifiedKey>,
+}
+
+impl CertificateStore {
+    fn load(config: &TlsConfig, provider: &CryptoProvider) -> Result<Self, String> {
+        let mut by_name = HashMap::new();
+        let mut default = None;
+        for certificate in &config.certificates {
+            let key = Arc::new(load_certified_key(certificate, provider)?);
+            for name in &certificate.server_names {
+                by_name.insert(name.to_ascii_lowercase(), key.clone());
+            }
+            default.get_or_insert(key);
+        }
+        Ok(Self {
+            by_name,
+            default: default.ok_or("TLS needs at least one certificate")?,
+        })
+    }
+
+    fn find(&self, server_name: &str) -> Option<Arc<CertifiedKey>> {
+        if let Some(key) = self.by_name.get(server_name) {
+            return Some(key.clone());
+        }
+        let (_, parent) = server_name.split_once('.')?;
+        self.by_name.get(&format!("*.{}", parent)).cloned()
+    }
+}
+
+fn load_certified_key(
+    certificate: &TlsCertificate,
+    provider: &CryptoProvider,
+) -> Result<CertifiedKey, String> {
+    let certs = load_certificates(&certificate.cert_path)?;
+    let key = load_private_key(&certificate.key_path)?;
+    let key = provider.key_provider.load_private_key(key).map_err(|e| {
+        format!(
+            "Invalid private key in {}: {}",
+            certificate.key_path.display(),
+            e
+        )
+    })?;
+    Ok(CertifiedKey::new(certs, key))
+}
+
+fn load_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Failed to read certificates from {}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificates in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+fn load_roots(path: &Path) -> Result<RootCertStore, String> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certificates(path)? {
+        roots
+            .add(cert)
+            .map_err(|e| format!("Invalid CA certificate in {}: {}", path.display(), e))?;
+    }
+    Ok(roots)
+}
+
+fn load_private_key(path: &Path) -> Result<PrivateKeyDer<'static>, String> {
+    PrivateKeyDer::from_pem_file(path)
+        .map_err(|e| format!("Failed to read private key from {}: {}", path.display(), e))
+}
+
+/// A PEM certificate chain and key presented to workers that require
+/// client certificates.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ClientCertificate {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+/// TLS settings for connections to an `https://` worker.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UpstreamTlsConfig {
+    /// PEM CA certificates the worker's certificate must chain to; `None`
+    /// trusts the Mozilla root store.
+    pub ca_bundle: Option<PathBuf>,
+    pub client_certificate: Option<ClientCertificate>,
+    /// Sent as SNI and verified against the worker's certificate instead of
+    /// the host in its URL.
+    pub server_name: Option<String>,
+    /// Verify the worker's certificate. Only turn this off for testing.
+    pub verify: bool,
+}
+
+impl Default for UpstreamTlsConfig {
+    fn default() -> Self {
+        Self {
+            ca_bundle: None,
+            client_certificate: None,
+            server_name: None,
+            verify: true,
+        }
+    }
+}
+
+impl UpstreamTlsConfig {
+    pub(crate) fn client_config(&self) -> Result<ClientConfig, String> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| format!("Invalid TLS versions: {}", e))?;
+
+        let builder = if self.verify {
+            let roots = match &self.ca_bundle {
+                Some(path) => load_roots(path)?,
+                None => RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+            };
+            builder.with_root_certificates(roots)
+        } else {
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(NoVerification(provider)))
+        };
+
+        match &self.client_certificate {
+            Some(certificate) => builder
+                .with_client_auth_cert(
+                    load_certificates(&certificate.cert_path)?,
+                    load_private_key(&certificate.key_path)?,
+                )
+                .map_err(|e| {
+                    format!(
+                        "Invalid client certificate {}: {}",
+                        certificate.cert_path.display(),
+                        e
+                    )
+                }),
+            None => Ok(builder.with_no_client_auth()),
+        }
+    }
+}
+
+/// Accepts any worker certificate, still checking handshake signatures.
+#[derive(Debug)]
+struct NoVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
@@ -0,0 +1,328 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    net::SocketAddr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::{net::UdpSocket, time::Instant};
+use tracing::{Instrument, debug, info, info_span, warn};
+
+use crate::{Worker, balancing_algorithms::BalancingAlgorithm};
+
+const DEFAULT_SESSION_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_MAX_SESSIONS: usize = 10_000;
+/// Large enough for any UDP payload.
+const DATAGRAM_SIZE: usize = 65_535;
+
+/// Session tracking for UDP balancing, where a worker's `host` is its
+/// `host:port` address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UdpProxyConfig {
+    /// Sessions with no datagrams either way for this long are forgotten,
+    /// and the client's next datagram picks a worker afresh.
+    pub session_timeout: Duration,
+    /// Live sessions allowed at once. Once reached, datagrams from clients
+    /// without a session are dropped until older sessions expire.
+    pub max_sessions: usize,
+}
+
+impl Default for UdpProxyConfig {
+    fn default() -> Self {
+        Self {
+            session_timeout: Duration::from_secs(DEFAULT_SESSION_TIMEOUT_SECS),
+            max_sessions: DEFAULT_MAX_SESSIONS,
+        }
+    }
+}
+
+/// Traffic so far in one live session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UdpSessionStats {
+    pub client_addr: SocketAddr,
+    pub worker: String,
+    pub bytes_to_worker: u64,
+    pub bytes_to_client: u64,
+}
+
+/// Balances UDP datagrams across workers. Every client address and port
+/// gets a session pinned to one worker, with its own upstream socket so that
+/// replies find their way back to that client. A session holds its worker
+/// until it expires, so least-connections balances by live sessions.
+pub struct UdpProxy {
+    workers: Vec<Worker>,
+    /// Worker addresses, resolved up front so that starting a session never
+    /// waits on DNS while other sessions' datagrams queue up.
+    addresses: HashMap<String, SocketAddr>,
+    algorithm: Mutex<Box<dyn BalancingAlgorithm>>,
+    config: UdpProxyConfig,
+    sessions: Mutex<HashMap<SocketAddr, Arc<Session>>>,
+    totals: UdpTotals,
+}
+
+struct Session {
+    worker: Worker,
+    upstream: UdpSocket,
+    last_activity: Mutex<Instant>,
+    to_worker: AtomicU64,
+    to_client: AtomicU64,
+}
+
+#[derive(Default)]
+struct UdpTotals {
+    sessions: AtomicU64,
+    expired_sessions: AtomicU64,
+    dropped_datagrams: AtomicU64,
+    bytes_to_workers: AtomicU64,
+    bytes_to_clients: AtomicU64,
+}
+
+impl UdpProxy {
+    /// Resolves every worker address, failing if any doesn't resolve.
+    pub async fn new(
+        workers: Vec<Worker>,
+        algorithm: Box<dyn BalancingAlgorithm>,
+        config: UdpProxyConfig,
+    ) -> Result<Self, String> {
+        if workers.is_empty() {
+            return Err("Worker hosts list cannot be empty".to_string());
+        }
+        let mut addresses = HashMap::new();
+        for worker in &workers {
+            addresses.insert(worker.host.clone(), resolve(&worker.host).await?);
+        }
+        Ok(Self {
+            workers,
+            addresses,
+            algorithm: Mutex::new(algorithm),
+            config,
+            sessions: Mutex::new(HashMap::new()),
+            totals: UdpTotals::default(),
+        })
+    }
+
+    /// Forwards datagrams until `shutdown` resolves. Replies keep flowing
+    /// back to clients until their sessions expire.
+    pub async fn serve(self: Arc<Self>, socket: UdpSocket, shutdown: impl Future<Output = ()>) {
+        let socket = Arc::new(socket);
+        let mut buf = vec![0; DATAGRAM_SIZE];
+        tokio::pin!(shutdown);
+        loop {
+            tokio::select! {
+                received = socket.recv_from(&mut buf) => {
+                    let (len, client_addr) = match received {
+                        Ok(received) => received,
+                        Err(e) => {
+                            warn!(error = %e, "Failed to receive datagram");
+                            continue;
+                        }
+                    };
+                    let session = match self.session_for(client_addr, &socket).await {
+                        Ok(Some(session)) => session,
+                        Ok(None) => {
+                            self.totals.dropped_datagrams.fetch_add(1, Ordering::Relaxed);
+                            debug!(client = %client_addr, "Session limit reached, dropping datagram");
+                            continue;
+                        }
+                        Err(e) => {
+                            warn!(client = %client_addr, error = %e, "No session for UDP client");
+                            continue;
+                        }
+                    };
+                    match session.upstream.send(&buf[..len]).await {
+                        Ok(sent) => {
+                            session.to_worker.fetch_add(sent as u64, Ordering::Relaxed);
+                            self.totals
+                                .bytes_to_workers
+                                .fetch_add(sent as u64, Ordering::Relaxed);
+                        }
+                        Err(e) => warn!(
+                            worker = %session.worker.host,
+                            error = %e,
+                            "Failed to forward datagram to worker"
+                        ),
+                    }
+                }
+                _ = &mut shutdown => break,
+            }
+        }
+    }
+
+    /// Returns the client's live session, or starts one on a newly chosen
+    /// worker. `None` when the client has no session and there is no room
+    /// for another.
+    async fn session_for(
+        self: &Arc<Self>,
+        client_addr: SocketAddr,
+        listener: &Arc<UdpSocket>,
+    ) -> io::Result<Option<Arc<Session>>> {
+        {
+            let sessions = self.sessions.lock().unwrap();
+            if let Some(session) = sessions.get(&client_addr) {
+                // Touched under the sessions lock, so expiry can't race it
+                *session.last_activity.lock().unwrap() = Instant::now();
+                return Ok(Some(session.clone()));
+            }
+            // Only this receive loop adds sessions, so the count can't grow
+            // before ours is inserted
+            if sessions.len() >= self.config.max_sessions {
+                return Ok(None);
+            }
+        }
+
+        let worker = self.algorithm.lock().unwrap().choose(&self.workers).clone();
+        let upstream = match connect_upstream(self.addresses[&worker.host]).await {
+            Ok(upstream) => upstream,
+            Err(e) => {
+                self.algorithm.lock().unwrap().release(&worker);
+                return Err(e);
+            }
+        };
+        info!(client = %client_addr, worker = %worker.host, "New UDP session");
+        let session = Arc::new(Session {
+            worker,
+            upstream,
+            last_activity: Mutex::new(Instant::now()),
+            to_worker: AtomicU64::new(0),
+            to_client: AtomicU64::new(0),
+        });
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(client_addr, session.clone());
+        self.totals.sessions.fetch_add(1, Ordering::Relaxed);
+
+        let proxy = self.clone();
+        let listener = listener.clone();
+        let replies = session.clone();
+        let span = info_span!("udp_session", peer = %client_addr);
+        tokio::spawn(
+            async move { proxy.relay_replies(client_addr, replies, listener).await }
+                .instrument(span),
+        );
+        Ok(Some(session))
+    }
+
+    /// Sends the worker's replies back to the client until the session has
+    /// been idle for the session timeout, then ends it.
+    async fn relay_replies(
+        &self,
+        client_addr: SocketAddr,
+        session: Arc<Session>,
+        listener: Arc<UdpSocket>,
+    ) {
+        let mut buf = vec![0; DATAGRAM_SIZE];
+        loop {
+            let deadline = *session.last_activity.lock().unwrap() + self.config.session_timeout;
+            match tokio::time::timeout_at(deadline, session.upstream.recv(&mut buf)).await {
+                Ok(Ok(len)) => {
+                    *session.last_activity.lock().unwrap() = Instant::now();
+                    match listener.send_to(&buf[..len], client_addr).await {
+                        Ok(sent) => {
+                            session.to_client.fetch_add(sent as u64, Ordering::Relaxed);
+                            self.totals
+                                .bytes_to_clients
+                                .fetch_add(sent as u64, Ordering::Relaxed);
+                        }
+                        Err(e) => warn!(error = %e, "Failed to send reply to UDP client"),
+                    }
+                }
+                // Typically an ICMP port unreachable for an earlier datagram
+                Ok(Err(e)) => warn!(
+                    worker = %session.worker.host,
+                    error = %e,
+                    "Failed to receive from worker"
+                ),
+                Err(_) => {
+                    let mut sessions = self.sessions.lock().unwrap();
+                    let last_activity = *session.last_activity.lock().unwrap();
+                    if last_activity + self.config.session_timeout <= Instant::now() {
+                        sessions.remove(&client_addr);
+                        break;
+                    }
+                }
+            }
+        }
+
+        self.totals.expired_sessions.fetch_add(1, Ordering::Relaxed);
+        self.algorithm.lock().unwrap().release(&session.worker);
+        info!(
+            worker = %session.worker.host,
+            bytes_to_worker = session.to_worker.load(Ordering::Relaxed),
+            bytes_to_client = session.to_client.load(Ordering::Relaxed),
+            "Expired UDP session"
+        );
+    }
+
+    pub fn active_sessions(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    /// Traffic counters of every live session.
+    pub fn sessions(&self) -> Vec<UdpSessionStats> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(client_addr, session)| UdpSessionStats {
+                client_addr: *client_addr,
+                worker: session.worker.host.clone(),
+                bytes_to_worker: session.to_worker.load(Ordering::Relaxed),
+                bytes_to_client: session.to_client.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Renders the metrics in the Prometheus text format.
+    pub fn metrics_report(&self) -> String {
+        let totals = &self.totals;
+        let mut report = format!(
+            "udp_sessions_total {}\n",
+            totals.sessions.load(Ordering::Relaxed)
+        );
+        report.push_str(&format!("udp_active_sessions {}\n", self.active_sessions()));
+        report.push_str(&format!(
+            "udp_expired_sessions_total {}\n",
+            totals.expired_sessions.load(Ordering::Relaxed)
+        ));
+        report.push_str(&format!(
+            "udp_dropped_datagrams_total {}\n",
+            totals.dropped_datagrams.load(Ordering::Relaxed)
+        ));
+        report.push_str(&format!(
+            "udp_bytes_total{{direction=\"to_worker\"}} {}\n",
+            totals.bytes_to_workers.load(Ordering::Relaxed)
+        ));
+        report.push_str(&format!(
+            "udp_bytes_total{{direction=\"to_client\"}} {}\n",
+            totals.bytes_to_clients.load(Ordering::Relaxed)
+        ));
+        report
+    }
+}
+
+async fn resolve(host: &str) -> Result<SocketAddr, String> {
+    tokio::net::lookup_host(host)
+        .await
+        .map_err(|e| format!("Invalid worker address {}: {}", host, e))?
+        .next()
+        .ok_or_else(|| format!("Worker address not found: {}", host))
+}
+
+/// A socket on an ephemeral port that only talks to the worker at
+/// `worker_addr`.
+async fn connect_upstream(worker_addr: SocketAddr) -> io::Result<UdpSocket> {
+    let bind_addr: SocketAddr = if worker_addr.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    socket.connect(worker_addr).await?;
+    Ok(socket)
+}
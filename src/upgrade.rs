@@ -0,0 +1,31 @@
+//! Proxying of HTTP/1.1 upgrades such as WebSockets. The upgrade request is
+//! forwarded like any other; once the worker answers 101 Switching
+//! Protocols, both connections are handed over and spliced together.
+
+use std::io;
+
+use hyper::{
+    HeaderMap,
+    header::{CONNECTION, UPGRADE},
+    upgrade::OnUpgrade,
+};
+use hyper_util::rt::TokioIo;
+
+/// Whether the request asks to switch protocols on its connection.
+pub(crate) fn is_upgrade_request(headers: &HeaderMap) -> bool {
+    headers.contains_key(UPGRADE)
+        && headers.get_all(CONNECTION).iter().any(|value| {
+            value.to_str().is_ok_and(|value| {
+                value
+                    .split(',')
+                    .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+            })
+        })
+}
+
+/// Waits for both sides to finish switching protocols, then copies bytes
+/// between them until either closes. Returns the bytes sent each way.
+pub(crate) async fn splice(client: OnUpgrade, upstream: OnUpgrade) -> io::Result<(u64, u64)> {
+    let (client, upstream) = tokio::try_join!(client, upstream).map_err(io::Error::other)?;
+    tokio::io::copy_bidirectional(&mut TokioIo::new(client), &mut TokioIo::new(upstream)).await
+}
@@ -0,0 +1,284 @@
+use std::{
+    collections::HashMap,
+    error::Error as StdError,
+    future::Future,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use http_body_util::combinators::BoxBody;
+use hyper::{
+    Request, Response,
+    body::{Body, Bytes, Frame, Incoming, SizeHint},
+};
+use hyper_rustls::{FixedServerNameResolver, HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::{
+    client::legacy::{Client, Error as ClientError, connect::HttpConnector},
+    rt::{TokioExecutor, TokioTimer},
+};
+use rustls::pki_types::ServerName;
+use tracing::warn;
+
+use crate::Worker;
+
+const DEFAULT_MAX_IDLE_PER_WORKER: usize = 32;
+const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 90;
+const DEFAULT_HTTP2_CONNECTIONS_PER_WORKER: usize = 1;
+const DEFAULT_MAX_STREAMS_PER_CONNECTION: usize = 100;
+
+pub(crate) type UpstreamBody = BoxBody<Bytes, Box<dyn StdError + Send + Sync>>;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum UpstreamProtocol {
+    #[default]
+    Http1,
+    /// HTTP/2 with prior knowledge, or negotiated with ALPN for `https://`
+    /// workers, multiplexing requests as streams over a small set of
+    /// connections.
+    Http2,
+}
+
+/// The `Host` a worker is sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum HostHeader {
+    /// The `Host` the client asked for, for workers serving several
+    /// virtual hosts.
+    #[default]
+    Preserve,
+    /// The worker's own address, for workers that only answer to it.
+    Rewrite,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamPoolConfig {
+    /// Idle HTTP/1.1 connections kept open to each worker.
+    pub max_idle_per_worker: usize,
+    /// How long an unused connection stays open.
+    pub idle_timeout: Option<Duration>,
+    /// Connections opened to each HTTP/2 worker.
+    pub http2_connections_per_worker: usize,
+    /// Streams multiplexed onto one HTTP/2 connection. Together with
+    /// `http2_connections_per_worker` this caps an HTTP/2 worker's requests
+    /// in flight, like its `max_connections`.
+    pub max_streams_per_connection: usize,
+}
+
+impl Default for UpstreamPoolConfig {
+    fn default() -> Self {
+        Self {
+            max_idle_per_worker: DEFAULT_MAX_IDLE_PER_WORKER,
+            idle_timeout: Some(Duration::from_secs(DEFAULT_IDLE_TIMEOUT_SECS)),
+            http2_connections_per_worker: DEFAULT_HTTP2_CONNECTIONS_PER_WORKER,
+            max_streams_per_connection: DEFAULT_MAX_STREAMS_PER_CONNECTION,
+        }
+    }
+}
+
+impl UpstreamPoolConfig {
+    /// Requests `worker` may have in flight, taking its protocol into account.
+    pub(crate) fn capacity_of(&self, worker: &Worker) -> Option<usize> {
+        match worker.protocol {
+            UpstreamProtocol::Http1 => worker.max_connections,
+            UpstreamProtocol::Http2 => {
+                let streams = self.http2_connections_per_worker.max(1)
+                    * self.max_streams_per_connection.max(1);
+                Some(
+                    worker
+                        .max_connections
+                        .map_or(streams, |max| max.min(streams)),
+                )
+            }
+        }
+    }
+}
+
+type Connector = HttpsConnector<HttpConnector>;
+
+/// Connection pools to the workers: an HTTP/1.1 pool per HTTP/1.1 worker,
+/// and a fixed set of HTTP/2 connections per HTTP/2 worker.
+pub(crate) struct UpstreamClients {
+    pools: HashMap<String, WorkerPool>,
+    max_streams_per_connection: usize,
+}
+
+enum WorkerPool {
+    Http1(Box<Client<Connector, UpstreamBody>>),
+    Http2(Vec<Http2Connection>),
+}
+
+/// Each connection is its own client so that its pool holds exactly one
+/// multiplexed connection.
+struct Http2Connection {
+    client: Client<Connector, UpstreamBody>,
+    streams: Arc<AtomicUsize>,
+}
+
+/// An open HTTP/2 stream, counted against its connection until dropped.
+pub(crate) struct StreamGuard {
+    streams: Arc<AtomicUsize>,
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        self.streams.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl UpstreamClients {
+    pub(crate) fn new(
+        workers: &[Worker],
+        connect_timeout: Option<Duration>,
+        config: &UpstreamPoolConfig,
+    ) -> Result<Self, String> {
+        let mut pools = HashMap::new();
+        for worker in workers {
+            let connector = connector(worker, connect_timeout)?;
+            let pool = match worker.protocol {
+                UpstreamProtocol::Http1 => WorkerPool::Http1(Box::new(
+                    Client::builder(TokioExecutor::new())
+                        .pool_timer(TokioTimer::new())
+                        .pool_idle_timeout(config.idle_timeout)
+                        .pool_max_idle_per_host(config.max_idle_per_worker)
+                        .build(connector),
+                )),
+                UpstreamProtocol::Http2 => WorkerPool::Http2(
+                    (0..config.http2_connections_per_worker.max(1))
+                        .map(|_| Http2Connection {
+                            client: Client::builder(TokioExecutor::new())
+                                .pool_timer(TokioTimer::new())
+                                .pool_idle_timeout(config.idle_timeout)
+                                .http2_only(true)
+                                .build(connector.clone()),
+                            streams: Arc::new(AtomicUsize::new(0)),
+                        })
+                        .collect(),
+                ),
+            };
+            pools.insert(worker.host.clone(), pool);
+        }
+
+        Ok(Self {
+            pools,
+            max_streams_per_connection: config.max_streams_per_connection.max(1),
+        })
+    }
+
+    /// Sends `req` to `worker`. For HTTP/2 workers the request goes to the
+    /// connection with the fewest open streams, and the returned guard keeps
+    /// the stream counted until dropped.
+    pub(crate) fn request(
+        &self,
+        worker: &Worker,
+        req: Request<UpstreamBody>,
+    ) -> (
+        impl Future<Output = Result<Response<Incoming>, ClientError>> + use<>,
+        Option<StreamGuard>,
+    ) {
+        let connections = match self.pools.get(&worker.host) {
+            Some(WorkerPool::Http2(connections)) => connections,
+            Some(WorkerPool::Http1(client)) => return (client.request(req), None),
+            None => panic!("no connection pool for worker {}", worker.host),
+        };
+        let connection = connections
+            .iter()
+            .min_by_key(|connection| connection.streams.load(Ordering::SeqCst))
+            .expect("HTTP/2 workers have at least one connection");
+        let open = connection.streams.fetch_add(1, Ordering::SeqCst);
+        if open >= self.max_streams_per_connection {
+            warn!(
+                worker = %worker.host,
+                streams = open,
+                "All HTTP/2 connections are at their stream limit"
+            );
+        }
+        let guard = StreamGuard {
+            streams: connection.streams.clone(),
+        };
+        (connection.client.request(req), Some(guard))
+    }
+
+    /// Open streams on each HTTP/2 worker's connections, for metrics.
+    pub(crate) fn http2_streams(&self, host: &str) -> Option<usize> {
+        let Some(WorkerPool::Http2(connections)) = self.pools.get(host) else {
+            return None;
+        };
+        Some(
+            connections
+                .iter()
+                .map(|connection| connection.streams.load(Ordering::SeqCst))
+                .sum(),
+        )
+    }
+}
+
+/// Connects over plain TCP for `http://` workers and over TLS, configured by
+/// the worker's [`UpstreamTlsConfig`](crate::UpstreamTlsConfig), for `https://` ones.
+fn connector(worker: &Worker, connect_timeout: Option<Duration>) -> Result<Connector, String> {
+    let mut http = HttpConnector::new();
+    http.set_connect_timeout(connect_timeout);
+    http.enforce_http(false);
+
+    let tls = worker.tls.clone().unwrap_or_default();
+    let mut builder = HttpsConnectorBuilder::new()
+        .with_tls_config(tls.client_config()?)
+        .https_or_http();
+    if let Some(name) = tls.server_name {
+        let name = ServerName::try_from(name.clone())
+            .map_err(|e| format!("Invalid TLS server name {}: {}", name, e))?;
+        builder = builder.with_server_name_resolver(FixedServerNameResolver::new(name));
+    }
+    Ok(match worker.protocol {
+        UpstreamProtocol::Http1 => builder.enable_http1().wrap_connector(http),
+        UpstreamProtocol::Http2 => builder.enable_http2().wrap_connector(http),
+    })
+}
+
+/// A response body that holds on to `guard` until the body is finished or
+/// dropped, so that the request stays counted while its body streams.
+pub(crate) struct GuardedBody<B, G> {
+    body: B,
+    guard: Option<G>,
+}
+
+impl<B, G> GuardedBody<B, G> {
+    pub(crate) fn new(body: B, guard: G) -> Self {
+        Self {
+            body,
+            guard: Some(guard),
+        }
+    }
+}
+
+impl<B, G> Body for GuardedBody<B, G>
+where
+    B: Body + Unpin,
+    G: Unpin,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        let frame = Pin::new(&mut this.body).poll_frame(cx);
+        if let Poll::Ready(None) = frame {
+            this.guard = None;
+        }
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
@@ -0,0 +1,156 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::sync::Notify;
+
+use crate::{Worker, request_queue::RequestQueue};
+
+/// Tracks per-worker in-flight requests, capacity and drain state.
+pub struct WorkerTracker {
+    states: HashMap<String, Arc<WorkerState>>,
+    queue: Option<Arc<RequestQueue>>,
+}
+
+struct WorkerState {
+    in_flight: AtomicUsize,
+    max_connections: Option<usize>,
+    drain_deadline: Mutex<Option<Instant>>,
+    idle: Notify,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DrainStatus {
+    pub draining: bool,
+    pub in_flight: usize,
+    pub deadline_passed: bool,
+}
+
+impl DrainStatus {
+    /// A draining worker is done once nothing is in flight or its deadline has passed.
+    pub fn is_complete(&self) -> bool {
+        self.draining && (self.in_flight == 0 || self.deadline_passed)
+    }
+}
+
+/// Decrements the worker's in-flight count when dropped, handing the freed
+/// slot to the next queued request.
+pub struct InFlightGuard {
+    state: Arc<WorkerState>,
+    queue: Option<Arc<RequestQueue>>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.state.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.state.idle.notify_waiters();
+        }
+        if let Some(queue) = &self.queue {
+            queue.wake_one();
+        }
+    }
+}
+
+impl WorkerTracker {
+    pub fn new(workers: &[Worker], queue: Option<Arc<RequestQueue>>) -> Self {
+        let states = workers
+            .iter()
+            .map(|worker| {
+                (
+                    worker.host.clone(),
+                    Arc::new(WorkerState {
+                        in_flight: AtomicUsize::new(0),
+                        max_connections: worker.max_connections,
+                        drain_deadline: Mutex::new(None),
+                        idle: Notify::new(),
+                    }),
+                )
+            })
+            .collect();
+        Self { states, queue }
+    }
+
+    pub fn is_draining(&self, host: &str) -> bool {
+        self.states
+            .get(host)
+            .is_some_and(|state| state.drain_deadline.lock().unwrap().is_some())
+    }
+
+    pub fn has_capacity(&self, host: &str) -> bool {
+        self.states.get(host).is_some_and(|state| {
+            state
+                .max_connections
+                .is_none_or(|max| state.in_flight.load(Ordering::SeqCst) < max)
+        })
+    }
+
+    /// Takes one of the worker's request slots, unless it is at capacity.
+    pub fn try_acquire(&self, host: &str) -> Option<InFlightGuard> {
+        let state = self.states.get(host)?;
+        state
+            .in_flight
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |in_flight| match state
+                .max_connections
+            {
+                Some(max) if in_flight >= max => None,
+                _ => Some(in_flight + 1),
+            })
+            .ok()?;
+        Some(InFlightGuard {
+            state: state.clone(),
+            queue: self.queue.clone(),
+        })
+    }
+
+    pub fn start_drain(&self, host: &str, deadline: Duration) -> Result<(), String> {
+        let state = self
+            .states
+            .get(host)
+            .ok_or_else(|| format!("Unknown worker: {}", host))?;
+        *state.drain_deadline.lock().unwrap() = Some(Instant::now() + deadline);
+        Ok(())
+    }
+
+    pub fn cancel_drain(&self, host: &str) -> Result<(), String> {
+        let state = self
+            .states
+            .get(host)
+            .ok_or_else(|| format!("Unknown worker: {}", host))?;
+        *state.drain_deadline.lock().unwrap() = None;
+        Ok(())
+    }
+
+    pub fn status(&self, host: &str) -> Option<DrainStatus> {
+        let state = self.states.get(host)?;
+        Some(Self::status_of(state))
+    }
+
+    /// Waits until the worker is idle or its drain deadline passes.
+    pub async fn wait_for_drain(&self, host: &str) -> Option<DrainStatus> {
+        let state = self.states.get(host)?;
+        loop {
+            let notified = state.idle.notified();
+            let status = Self::status_of(state);
+            if !status.draining || status.is_complete() {
+                return Some(status);
+            }
+
+            let deadline = (*state.drain_deadline.lock().unwrap())?;
+            let _ = tokio::time::timeout_at(deadline.into(), notified).await;
+        }
+    }
+
+    fn status_of(state: &WorkerState) -> DrainStatus {
+        let deadline = *state.drain_deadline.lock().unwrap();
+        DrainStatus {
+            draining: deadline.is_some(),
+            in_flight: state.in_flight.load(Ordering::SeqCst),
+            deadline_passed: deadline.is_some_and(|deadline| Instant::now() >= deadline),
+        }
+    }
+}
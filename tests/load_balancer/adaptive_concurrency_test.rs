@@ -0,0 +1,99 @@
+use std::{sync::Arc, time::Duration};
+
+use hyper::StatusCode;
+use load_balancer::balancing_algorithms::RoundRobinAlgorithm;
+use load_balancer::{
+    AdaptiveConcurrencyConfig, ConcurrencyAlgorithm, LoadBalancer, LoadBalancerConfig,
+};
+
+use crate::support::{get, spawn_balancer, spawn_upstream};
+
+async fn limited_balancer(adaptive_concurrency: AdaptiveConcurrencyConfig) -> Arc<LoadBalancer> {
+    let workers = vec![spawn_upstream("a").await];
+    let config = LoadBalancerConfig {
+        adaptive_concurrency: Some(adaptive_concurrency),
+        ..LoadBalancerConfig::default()
+    };
+    Arc::new(
+        LoadBalancer::with_config(workers, Box::new(RoundRobinAlgorithm::new()), config)
+            .expect("Failed to create load balancer"),
+    )
+}
+
+#[tokio::test]
+async fn test_requests_over_limit_are_shed() {
+    let load_balancer = limited_balancer(AdaptiveConcurrencyConfig {
+        initial_limit: 1,
+        max_limit: 1,
+        ..AdaptiveConcurrencyConfig::default()
+    })
+    .await;
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    let first = tokio::spawn(async move { get(addr, "/slow").await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    assert_eq!(get(addr, "/").await.0, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(first.await.unwrap().0, StatusCode::OK);
+    assert!(
+        load_balancer
+            .metrics_report()
+            .await
+            .contains("rejected_requests_total{reason=\"ConcurrencyLimit\"} 1")
+    );
+}
+
+#[tokio::test]
+async fn test_aimd_backs_off_on_slow_responses() {
+    let load_balancer = limited_balancer(AdaptiveConcurrencyConfig {
+        algorithm: ConcurrencyAlgorithm::Aimd {
+            latency_threshold: Duration::from_millis(100),
+            backoff_ratio: 0.5,
+        },
+        initial_limit: 8,
+        ..AdaptiveConcurrencyConfig::default()
+    })
+    .await;
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    get(addr, "/slow").await;
+    get(addr, "/slow").await;
+
+    assert!(
+        load_balancer
+            .metrics_report()
+            .await
+            .contains("adaptive_concurrency_limit 2\n")
+    );
+}
+
+#[tokio::test]
+async fn test_gradient_lowers_limit_as_latency_rises() {
+    let load_balancer = limited_balancer(AdaptiveConcurrencyConfig {
+        algorithm: ConcurrencyAlgorithm::gradient(),
+        initial_limit: 20,
+        max_limit: 20,
+        ..AdaptiveConcurrencyConfig::default()
+    })
+    .await;
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    for _ in 0..5 {
+        get(addr, "/").await;
+    }
+    let before = concurrency_limit(&load_balancer).await;
+
+    get(addr, "/slow").await;
+    get(addr, "/slow").await;
+    assert!(concurrency_limit(&load_balancer).await < before);
+}
+
+async fn concurrency_limit(load_balancer: &LoadBalancer) -> usize {
+    load_balancer
+        .metrics_report()
+        .await
+        .lines()
+        .find_map(|line| line.strip_prefix("adaptive_concurrency_limit "))
+        .and_then(|limit| limit.parse().ok())
+        .expect("limit is reported")
+}
@@ -0,0 +1,194 @@
+use std::{sync::Arc, time::Duration};
+
+use hyper::StatusCode;
+use load_balancer::balancing_algorithms::{LeastConnectionsAlgorithm, RoundRobinAlgorithm};
+use load_balancer::{
+    CircuitBreakerConfig, CircuitState, LoadBalancer, LoadBalancerConfig, RetryConfig, Worker,
+};
+
+use crate::support::{
+    get, spawn_balancer, spawn_delayed_upstream, spawn_failing_upstream, spawn_upstream,
+};
+
+fn config(circuit_breaker: CircuitBreakerConfig) -> LoadBalancerConfig {
+    LoadBalancerConfig {
+        retries: RetryConfig {
+            max_retries: 0,
+            ..RetryConfig::default()
+        },
+        circuit_breaker: Some(circuit_breaker),
+        ..LoadBalancerConfig::default()
+    }
+}
+
+fn quick_breaker() -> CircuitBreakerConfig {
+    CircuitBreakerConfig {
+        minimum_calls: 2,
+        window_size: 2,
+        open_duration: Duration::from_millis(200),
+        half_open_max_calls: 1,
+        ..CircuitBreakerConfig::default()
+    }
+}
+
+#[tokio::test]
+async fn test_failing_worker_circuit_opens_and_is_skipped() {
+    let failing = spawn_failing_upstream(StatusCode::INTERNAL_SERVER_ERROR).await;
+    let failing_host = failing.host.clone();
+    let workers: Vec<Worker> = vec![failing, spawn_upstream("b").await];
+    let load_balancer = Arc::new(
+        LoadBalancer::with_config(
+            workers,
+            Box::new(RoundRobinAlgorithm::new()),
+            config(quick_breaker()),
+        )
+        .expect("Failed to create load balancer"),
+    );
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    for _ in 0..4 {
+        get(addr, "/").await;
+    }
+    assert_eq!(
+        load_balancer.circuit_state(&failing_host),
+        Some(CircuitState::Open)
+    );
+
+    for _ in 0..4 {
+        assert_eq!(get(addr, "/").await, (StatusCode::OK, "b".to_string()));
+    }
+    let report = load_balancer.metrics_report().await;
+    assert!(report.contains(&format!(
+        "circuit_breaker_state{{worker=\"{}\",state=\"Open\"}} 1",
+        failing_host
+    )));
+}
+
+#[tokio::test]
+async fn test_open_circuit_is_skipped_by_least_connections() {
+    let failing = spawn_failing_upstream(StatusCode::INTERNAL_SERVER_ERROR).await;
+    let failing_host = failing.host.clone();
+    let workers: Vec<Worker> = vec![failing, spawn_upstream("b").await];
+    let algorithm = Box::new(LeastConnectionsAlgorithm::new(&workers));
+    let load_balancer = Arc::new(
+        LoadBalancer::with_config(workers, algorithm, config(quick_breaker()))
+            .expect("Failed to create load balancer"),
+    );
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    while load_balancer.circuit_state(&failing_host) != Some(CircuitState::Open) {
+        get(addr, "/").await;
+    }
+    for _ in 0..4 {
+        assert_eq!(get(addr, "/").await.1, "b");
+    }
+}
+
+#[tokio::test]
+async fn test_half_open_probe_success_closes_circuit() {
+    // Probes stay in flight long enough for every request to arrive first
+    let workers = vec![spawn_delayed_upstream("a", Duration::from_millis(100)).await];
+    let host = workers[0].host.clone();
+    let breaker = CircuitBreakerConfig {
+        slow_call_duration: Duration::from_millis(300),
+        slow_call_rate_threshold: 1.0,
+        ..quick_breaker()
+    };
+    let load_balancer = Arc::new(
+        LoadBalancer::with_config(
+            workers,
+            Box::new(RoundRobinAlgorithm::new()),
+            config(breaker),
+        )
+        .expect("Failed to create load balancer"),
+    );
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    // Two slow calls open the circuit
+    get(addr, "/slow").await;
+    get(addr, "/slow").await;
+    assert_eq!(load_balancer.circuit_state(&host), Some(CircuitState::Open));
+    assert_eq!(get(addr, "/").await.0, StatusCode::SERVICE_UNAVAILABLE);
+
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert_eq!(get(addr, "/").await.0, StatusCode::OK);
+    assert_eq!(
+        load_balancer.circuit_state(&host),
+        Some(CircuitState::Closed)
+    );
+}
+
+#[tokio::test]
+async fn test_cancelled_half_open_probe_frees_its_slot() {
+    // Probes stay in flight long enough for every request to arrive first
+    let workers = vec![spawn_delayed_upstream("a", Duration::from_millis(100)).await];
+    let host = workers[0].host.clone();
+    let breaker = CircuitBreakerConfig {
+        slow_call_duration: Duration::from_millis(300),
+        slow_call_rate_threshold: 1.0,
+        ..quick_breaker()
+    };
+    let load_balancer = Arc::new(
+        LoadBalancer::with_config(
+            workers,
+            Box::new(RoundRobinAlgorithm::new()),
+            config(breaker),
+        )
+        .expect("Failed to create load balancer"),
+    );
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    get(addr, "/slow").await;
+    get(addr, "/slow").await;
+    assert_eq!(load_balancer.circuit_state(&host), Some(CircuitState::Open));
+    tokio::time::sleep(Duration::from_millis(250)).await;
+
+    // The client gives up on the only probe before the worker answers
+    let probe = tokio::time::timeout(Duration::from_millis(100), get(addr, "/slow")).await;
+    assert!(probe.is_err());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert_eq!(get(addr, "/").await.0, StatusCode::OK);
+    assert_eq!(
+        load_balancer.circuit_state(&host),
+        Some(CircuitState::Closed)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_requests_share_half_open_probe_slots() {
+    // Probes stay in flight long enough for every request to arrive first
+    let workers = vec![spawn_delayed_upstream("a", Duration::from_millis(100)).await];
+    let host = workers[0].host.clone();
+    let breaker = CircuitBreakerConfig {
+        slow_call_duration: Duration::from_millis(300),
+        slow_call_rate_threshold: 1.0,
+        half_open_max_calls: 2,
+        ..quick_breaker()
+    };
+    let load_balancer = Arc::new(
+        LoadBalancer::with_config(
+            workers,
+            Box::new(RoundRobinAlgorithm::new()),
+            config(breaker),
+        )
+        .expect("Failed to create load balancer"),
+    );
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    get(addr, "/slow").await;
+    get(addr, "/slow").await;
+    assert_eq!(load_balancer.circuit_state(&host), Some(CircuitState::Open));
+    tokio::time::sleep(Duration::from_millis(250)).await;
+
+    let requests: Vec<_> = (0..64)
+        .map(|_| tokio::spawn(async move { get(addr, "/").await.0 }))
+        .collect();
+    let mut probes = 0;
+    for request in requests {
+        if request.await.unwrap() == StatusCode::OK {
+            probes += 1;
+        }
+    }
+    assert_eq!(probes, 2);
+}
@@ -0,0 +1,84 @@
+use std::{sync::Arc, time::Duration};
+
+use hyper::StatusCode;
+use load_balancer::LoadBalancer;
+use load_balancer::balancing_algorithms::RoundRobinAlgorithm;
+
+use crate::support::{get, spawn_balancer, spawn_upstream};
+
+#[tokio::test]
+async fn test_draining_worker_receives_no_new_requests() {
+    let workers = vec![spawn_upstream("a").await, spawn_upstream("b").await];
+    let drained_host = workers[0].host.clone();
+    let load_balancer = Arc::new(
+        LoadBalancer::new(workers, Box::new(RoundRobinAlgorithm::new()))
+            .expect("Failed to create load balancer"),
+    );
+    load_balancer
+        .drain_worker(&drained_host, Duration::from_secs(30))
+        .unwrap();
+    let addr = spawn_balancer(load_balancer).await;
+
+    for _ in 0..4 {
+        let (status, body) = get(addr, "/").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "b");
+    }
+}
+
+#[tokio::test]
+async fn test_all_workers_draining_returns_service_unavailable() {
+    let workers = vec![spawn_upstream("a").await];
+    let host = workers[0].host.clone();
+    let load_balancer = Arc::new(
+        LoadBalancer::new(workers, Box::new(RoundRobinAlgorithm::new()))
+            .expect("Failed to create load balancer"),
+    );
+    load_balancer
+        .drain_worker(&host, Duration::from_secs(30))
+        .unwrap();
+    let addr = spawn_balancer(load_balancer).await;
+
+    let (status, _) = get(addr, "/").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn test_wait_for_drain_completes_when_in_flight_finishes() {
+    let workers = vec![spawn_upstream("a").await];
+    let host = workers[0].host.clone();
+    let load_balancer = Arc::new(
+        LoadBalancer::new(workers, Box::new(RoundRobinAlgorithm::new()))
+            .expect("Failed to create load balancer"),
+    );
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    let slow_request = tokio::spawn(async move { get(addr, "/slow").await });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    load_balancer
+        .drain_worker(&host, Duration::from_secs(30))
+        .unwrap();
+    let status = load_balancer.drain_status(&host).unwrap();
+    assert!(status.draining);
+    assert_eq!(status.in_flight, 1);
+    assert!(!status.is_complete());
+
+    let status = load_balancer.wait_for_drain(&host).await.unwrap();
+    assert_eq!(status.in_flight, 0);
+    assert!(status.is_complete());
+    assert_eq!(slow_request.await.unwrap().0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_drain_unknown_worker_is_an_error() {
+    let workers = vec![spawn_upstream("a").await];
+    let load_balancer = LoadBalancer::new(workers, Box::new(RoundRobinAlgorithm::new()))
+        .expect("Failed to create load balancer");
+
+    assert!(
+        load_balancer
+            .drain_worker("http://unknown:1", Duration::from_secs(1))
+            .is_err()
+    );
+}
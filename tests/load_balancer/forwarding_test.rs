@@ -0,0 +1,107 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use http_body_util::BodyExt;
+use load_balancer::balancing_algorithms::RoundRobinAlgorithm;
+use load_balancer::{ForwardingConfig, LoadBalancer, LoadBalancerConfig};
+
+use crate::support::{get_with_headers, spawn_balancer, spawn_header_echo_upstream};
+
+/// Starts a balancer whose worker echoes `header` back.
+async fn start_balancer(header: &'static str, forwarding: ForwardingConfig) -> SocketAddr {
+    let config = LoadBalancerConfig {
+        forwarding,
+        ..LoadBalancerConfig::default()
+    };
+    let load_balancer = Arc::new(
+        LoadBalancer::with_config(
+            vec![spawn_header_echo_upstream(header).await],
+            Box::new(RoundRobinAlgorithm::new()),
+            config,
+        )
+        .expect("Failed to create load balancer"),
+    );
+    spawn_balancer(load_balancer).await
+}
+
+/// The value of `header` as the worker received it.
+async fn forwarded(
+    header: &'static str,
+    forwarding: ForwardingConfig,
+    headers: &[(&str, &str)],
+) -> String {
+    let addr = start_balancer(header, forwarding).await;
+    let response = get_with_headers(addr, "/", headers).await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    String::from_utf8_lossy(&body).into_owned()
+}
+
+fn trusting_localhost() -> ForwardingConfig {
+    ForwardingConfig {
+        trusted_proxies: vec!["127.0.0.0/8".parse().unwrap()],
+        ..ForwardingConfig::default()
+    }
+}
+
+const SPOOFED: [(&str, &str); 5] = [
+    ("x-forwarded-for", "203.0.113.7"),
+    ("x-forwarded-proto", "https"),
+    ("x-forwarded-host", "spoofed.example"),
+    ("forwarded", "for=203.0.113.7;proto=https"),
+    ("via", "1.1 edge"),
+];
+
+#[tokio::test]
+async fn test_untrusted_client_headers_are_replaced() {
+    let config = ForwardingConfig::default;
+    assert_eq!(
+        forwarded("x-forwarded-for", config(), &SPOOFED).await,
+        "127.0.0.1"
+    );
+    assert_eq!(
+        forwarded("x-forwarded-proto", config(), &SPOOFED).await,
+        "http"
+    );
+    assert_eq!(
+        forwarded("x-forwarded-host", config(), &[("host", "shop.example")]).await,
+        "shop.example"
+    );
+    assert_eq!(
+        forwarded("forwarded", config(), &[("host", "shop.example:8080")]).await,
+        "for=127.0.0.1;host=\"shop.example:8080\";proto=http"
+    );
+    // Via records every hop, trusted or not
+    assert_eq!(
+        forwarded("via", config(), &SPOOFED).await,
+        "1.1 edge, 1.1 load-balancer"
+    );
+}
+
+#[tokio::test]
+async fn test_trusted_proxy_headers_are_appended_to() {
+    assert_eq!(
+        forwarded("x-forwarded-for", trusting_localhost(), &SPOOFED).await,
+        "203.0.113.7, 127.0.0.1"
+    );
+    assert_eq!(
+        forwarded("x-forwarded-proto", trusting_localhost(), &SPOOFED).await,
+        "https"
+    );
+    assert_eq!(
+        forwarded("x-forwarded-host", trusting_localhost(), &SPOOFED).await,
+        "spoofed.example"
+    );
+    let headers = [SPOOFED[3], ("host", "shop.example")];
+    assert_eq!(
+        forwarded("forwarded", trusting_localhost(), &headers).await,
+        "for=203.0.113.7;proto=https, for=127.0.0.1;host=shop.example;proto=http"
+    );
+}
+
+#[tokio::test]
+async fn test_via_can_be_disabled() {
+    let config = ForwardingConfig {
+        via_pseudonym: None,
+        ..ForwardingConfig::default()
+    };
+    assert_eq!(forwarded("via", config, &[]).await, "-");
+}
@@ -0,0 +1,167 @@
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, atomic::Ordering},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use http_body_util::BodyExt;
+use hyper::{
+    Request,
+    body::{Body, Bytes, Frame},
+    client::conn::http2::{self, SendRequest},
+};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use load_balancer::balancing_algorithms::RoundRobinAlgorithm;
+use load_balancer::{GrpcConfig, GrpcHealthCheckConfig, LoadBalancer, LoadBalancerConfig, Worker};
+use tokio::net::TcpStream;
+
+use crate::support::{grpc_frame, spawn_balancer, spawn_grpc_upstream};
+
+/// A request body without a known length, as most gRPC clients send.
+struct UnsizedBody(Option<Bytes>);
+
+impl Body for UnsizedBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        Poll::Ready(self.0.take().map(|data| Ok(Frame::data(data))))
+    }
+}
+
+fn grpc_balancer(workers: Vec<Worker>, grpc: GrpcConfig) -> Arc<LoadBalancer> {
+    let config = LoadBalancerConfig {
+        grpc,
+        circuit_breaker: None,
+        ..LoadBalancerConfig::default()
+    };
+    Arc::new(
+        LoadBalancer::with_config(workers, Box::new(RoundRobinAlgorithm::new()), config)
+            .expect("Failed to create load balancer"),
+    )
+}
+
+async fn connect(addr: SocketAddr) -> SendRequest<UnsizedBody> {
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (sender, connection) = http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+        .await
+        .unwrap();
+    tokio::spawn(connection);
+    sender
+}
+
+/// Makes a unary call, returning the response message and `grpc-status`.
+async fn call(sender: &mut SendRequest<UnsizedBody>) -> (Bytes, String) {
+    let request = Request::post("http://localhost/test.Echo/Say")
+        .header("content-type", "application/grpc")
+        .header("te", "trailers")
+        .body(UnsizedBody(Some(grpc_frame(b"hello"))))
+        .unwrap();
+    let response = sender.send_request(request).await.unwrap();
+    let trailers_only = response.headers().get("grpc-status").cloned();
+    let body = response.into_body().collect().await.unwrap();
+    let status = trailers_only
+        .or_else(|| body.trailers().and_then(|t| t.get("grpc-status").cloned()))
+        .unwrap();
+    let body = body.to_bytes();
+    let message = body.slice(body.len().min(5)..);
+    (message, status.to_str().unwrap().to_string())
+}
+
+#[tokio::test]
+async fn test_grpc_calls_are_balanced_per_call_with_trailers() {
+    let (a, _) = spawn_grpc_upstream("a").await;
+    let (b, _) = spawn_grpc_upstream("b").await;
+    let load_balancer = grpc_balancer(vec![a, b], GrpcConfig::default());
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    // Both calls share one client connection but reach different workers
+    let mut sender = connect(addr).await;
+    let first = call(&mut sender).await;
+    let second = call(&mut sender).await;
+    assert_eq!(first, (Bytes::from("a"), "0".to_string()));
+    assert_eq!(second, (Bytes::from("b"), "0".to_string()));
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(
+        load_balancer
+            .metrics_report()
+            .await
+            .contains("grpc_responses_total{status=\"OK\"} 2")
+    );
+}
+
+#[tokio::test]
+async fn test_unavailable_grpc_call_is_retried_on_another_worker() {
+    let (a, a_serving) = spawn_grpc_upstream("a").await;
+    let (b, _) = spawn_grpc_upstream("b").await;
+    a_serving.store(false, Ordering::SeqCst);
+    let load_balancer = grpc_balancer(vec![a, b], GrpcConfig::default());
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    let mut sender = connect(addr).await;
+    assert_eq!(call(&mut sender).await, (Bytes::from("b"), "0".to_string()));
+    assert!(
+        load_balancer
+            .metrics_report()
+            .await
+            .contains("retries_total 1")
+    );
+
+    // Without retries the status reaches the client and is counted
+    let (c, c_serving) = spawn_grpc_upstream("c").await;
+    c_serving.store(false, Ordering::SeqCst);
+    let load_balancer = grpc_balancer(
+        vec![c],
+        GrpcConfig {
+            retry_on: Vec::new(),
+            ..GrpcConfig::default()
+        },
+    );
+    let addr = spawn_balancer(load_balancer.clone()).await;
+    let mut sender = connect(addr).await;
+    assert_eq!(call(&mut sender).await.1, "14");
+    assert!(
+        load_balancer
+            .metrics_report()
+            .await
+            .contains("grpc_responses_total{status=\"UNAVAILABLE\"} 1")
+    );
+}
+
+#[tokio::test]
+async fn test_unhealthy_grpc_worker_is_skipped_until_serving_again() {
+    let (a, a_serving) = spawn_grpc_upstream("a").await;
+    let (b, _) = spawn_grpc_upstream("b").await;
+    let a_host = a.host.clone();
+    let load_balancer = grpc_balancer(
+        vec![a, b],
+        GrpcConfig {
+            health_check: Some(GrpcHealthCheckConfig {
+                interval: Duration::from_millis(50),
+                ..GrpcHealthCheckConfig::default()
+            }),
+            ..GrpcConfig::default()
+        },
+    );
+    let addr = spawn_balancer(load_balancer.clone()).await;
+    let unhealthy = format!("worker_healthy{{worker=\"{}\"}} 0", a_host);
+
+    a_serving.store(false, Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(load_balancer.metrics_report().await.contains(&unhealthy));
+    let mut sender = connect(addr).await;
+    for _ in 0..2 {
+        assert_eq!(call(&mut sender).await.0, Bytes::from("b"));
+    }
+
+    a_serving.store(true, Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!load_balancer.metrics_report().await.contains(&unhealthy));
+}
@@ -0,0 +1,76 @@
+use std::{
+    env,
+    os::fd::AsRawFd,
+    process::Stdio,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use hyper::StatusCode;
+use load_balancer::handoff;
+use tokio::process::Command;
+
+use crate::support::get;
+
+/// `LISTEN_FDS` and `LISTEN_PID` are process-wide, so tests that set them
+/// take turns.
+static LISTEN_ENV: Mutex<()> = Mutex::new(());
+
+fn set_listen_env(fds: Option<&str>, pid: Option<&str>) -> MutexGuard<'static, ()> {
+    let guard = LISTEN_ENV.lock().unwrap_or_else(|e| e.into_inner());
+    // SAFETY: no other test reads or writes these variables while the lock
+    // is held
+    unsafe {
+        match fds {
+            Some(fds) => env::set_var("LISTEN_FDS", fds),
+            None => env::remove_var("LISTEN_FDS"),
+        }
+        match pid {
+            Some(pid) => env::set_var("LISTEN_PID", pid),
+            None => env::remove_var("LISTEN_PID"),
+        }
+    }
+    guard
+}
+
+#[test]
+fn test_no_listen_fds_inherits_nothing() {
+    let _env = set_listen_env(None, None);
+
+    assert!(handoff::inherited_listener().unwrap().is_none());
+}
+
+#[test]
+fn test_listen_pid_for_another_process_is_ignored() {
+    let other_pid = (std::process::id() + 1).to_string();
+    let _env = set_listen_env(Some("1"), Some(&other_pid));
+
+    assert!(handoff::inherited_listener().unwrap().is_none());
+}
+
+#[test]
+fn test_more_than_one_listen_fd_is_rejected() {
+    let _env = set_listen_env(Some("2"), None);
+
+    assert!(handoff::inherited_listener().is_err());
+}
+
+#[tokio::test]
+async fn test_child_accepts_on_passed_listener() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let mut command = Command::new(env!("CARGO_BIN_EXE_load-balancer"));
+    command
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true);
+    handoff::pass_listener(command.as_std_mut(), listener.as_raw_fd());
+    let _child = command.spawn().expect("failed to start load balancer");
+
+    // Only the child accepts, so a response means it took over the socket
+    let (status, _) = tokio::time::timeout(Duration::from_secs(10), get(addr, "/admin/metrics"))
+        .await
+        .expect("child never answered on the passed listener");
+    assert_eq!(status, StatusCode::OK);
+}
@@ -0,0 +1,86 @@
+use std::{sync::Arc, time::Duration};
+
+use hyper::StatusCode;
+use load_balancer::balancing_algorithms::RoundRobinAlgorithm;
+use load_balancer::{HedgingConfig, LoadBalancer, LoadBalancerConfig};
+
+use crate::support::{get, post, spawn_balancer, spawn_delayed_upstream, spawn_upstream};
+
+async fn hedged_load_balancer(hedging: HedgingConfig) -> Arc<LoadBalancer> {
+    // Round robin sends the first request to the slow worker
+    let workers = vec![
+        spawn_delayed_upstream("slow", Duration::from_millis(500)).await,
+        spawn_upstream("fast").await,
+    ];
+    let config = LoadBalancerConfig {
+        hedging: Some(hedging),
+        ..LoadBalancerConfig::default()
+    };
+    Arc::new(
+        LoadBalancer::with_config(workers, Box::new(RoundRobinAlgorithm::new()), config)
+            .expect("Failed to create load balancer"),
+    )
+}
+
+#[tokio::test]
+async fn test_hedged_request_uses_first_response() {
+    let load_balancer = hedged_load_balancer(HedgingConfig {
+        routes: vec!["/search".to_string()],
+        delay: Duration::from_millis(50),
+        max_hedge_ratio: 1.0,
+        ..HedgingConfig::default()
+    })
+    .await;
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    let (status, body) = get(addr, "/search").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "fast");
+
+    let report = load_balancer.metrics_report().await;
+    assert!(report.contains("hedged_requests_total 1"));
+    assert!(report.contains("hedge_wins_total 1"));
+}
+
+#[tokio::test]
+async fn test_only_gets_on_hedged_routes_are_hedged() {
+    let load_balancer = hedged_load_balancer(HedgingConfig {
+        routes: vec!["/search".to_string()],
+        delay: Duration::from_millis(50),
+        max_hedge_ratio: 1.0,
+        ..HedgingConfig::default()
+    })
+    .await;
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    assert_eq!(get(addr, "/other").await.1, "slow");
+    get(addr, "/other").await;
+    assert_eq!(post(addr, "/search", "payload").await.1, "slow");
+
+    assert!(
+        load_balancer
+            .metrics_report()
+            .await
+            .contains("hedged_requests_total 0")
+    );
+}
+
+#[tokio::test]
+async fn test_hedge_ratio_caps_hedged_requests() {
+    let load_balancer = hedged_load_balancer(HedgingConfig {
+        routes: vec!["/".to_string()],
+        delay: Duration::from_millis(50),
+        max_hedge_ratio: 0.0,
+        ..HedgingConfig::default()
+    })
+    .await;
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    assert_eq!(get(addr, "/").await.1, "slow");
+    assert!(
+        load_balancer
+            .metrics_report()
+            .await
+            .contains("hedged_requests_total 0")
+    );
+}
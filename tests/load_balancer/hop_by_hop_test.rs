@@ -0,0 +1,97 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use load_balancer::balancing_algorithms::RoundRobinAlgorithm;
+use load_balancer::{HostHeader, LoadBalancer, Worker};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::support::{spawn_balancer, spawn_worker_binary};
+
+async fn start_balancer(worker: Worker) -> SocketAddr {
+    let load_balancer = Arc::new(
+        LoadBalancer::new(vec![worker], Box::new(RoundRobinAlgorithm::new()))
+            .expect("Failed to create load balancer"),
+    );
+    spawn_balancer(load_balancer).await
+}
+
+/// Sends a raw `GET /headers` with `headers` and returns the lowercased
+/// response head and the body, which lists the headers the worker received.
+async fn get_headers(addr: SocketAddr, headers: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!("GET /headers HTTP/1.1\r\n{}\r\n", headers);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.to_ascii_lowercase(), body.to_string())
+}
+
+#[tokio::test]
+async fn test_hop_by_hop_headers_are_stripped_both_ways() {
+    let (worker, _child) = spawn_worker_binary().await;
+    let addr = start_balancer(worker).await;
+
+    let (head, received) = get_headers(
+        addr,
+        "host: shop.example\r\n\
+         connection: close, x-client-hop\r\n\
+         x-client-hop: secret\r\n\
+         keep-alive: timeout=30\r\n\
+         proxy-authorization: Basic Zm9vOmJhcg==\r\n\
+         proxy-connection: keep-alive\r\n\
+         te: trailers, deflate\r\n\
+         upgrade: websocket\r\n\
+         x-end-to-end: kept\r\n",
+    )
+    .await;
+
+    assert!(head.starts_with("http/1.1 200"), "{}", head);
+    for line in ["host: shop.example", "te: trailers", "x-end-to-end: kept"] {
+        assert!(received.lines().any(|l| l == line), "{}", received);
+    }
+    for name in [
+        "connection",
+        "x-client-hop",
+        "keep-alive",
+        "proxy-authorization",
+        "proxy-connection",
+        "upgrade",
+    ] {
+        assert!(
+            !received
+                .lines()
+                .any(|l| l.starts_with(&format!("{}:", name))),
+            "{} was forwarded:\n{}",
+            name,
+            received
+        );
+    }
+
+    for name in ["x-worker-hop", "keep-alive", "proxy-authenticate"] {
+        assert!(
+            !head.lines().any(|l| l.starts_with(&format!("{}:", name))),
+            "{} was returned:\n{}",
+            name,
+            head
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_host_is_rewritten_for_rewriting_workers() {
+    let (worker, _child) = spawn_worker_binary().await;
+    let authority = worker.host.trim_start_matches("http://").to_string();
+    let addr = start_balancer(worker.with_host_header(HostHeader::Rewrite)).await;
+
+    let (_, received) = get_headers(addr, "host: shop.example\r\nconnection: close\r\n").await;
+    assert!(
+        received
+            .lines()
+            .any(|l| l == format!("host: {}", authority)),
+        "{}",
+        received
+    );
+}
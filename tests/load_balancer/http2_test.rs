@@ -0,0 +1,129 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use http_body_util::{BodyExt, Empty};
+use hyper::{Request, StatusCode, Version, body::Bytes};
+use hyper_util::{
+    client::legacy::{Client, connect::HttpConnector},
+    rt::TokioExecutor,
+};
+use load_balancer::balancing_algorithms::RoundRobinAlgorithm;
+use load_balancer::{Http2Config, LoadBalancer, ServerConfig};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::support::{spawn_balancer_with_config, spawn_upstream};
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+const EMPTY_SETTINGS: [u8; 9] = [0, 0, 0, 0x4, 0, 0, 0, 0, 0];
+const UPGRADE_REQUEST: &str = "GET / HTTP/1.1\r\n\
+    Host: localhost\r\n\
+    Connection: Upgrade, HTTP2-Settings\r\n\
+    Upgrade: h2c\r\n\
+    HTTP2-Settings: AAMAAABkAAQAAP__\r\n\r\n";
+
+async fn start_balancer(http2: Http2Config) -> SocketAddr {
+    let workers = vec![spawn_upstream("a").await];
+    let load_balancer = Arc::new(
+        LoadBalancer::new(workers, Box::new(RoundRobinAlgorithm::new()))
+            .expect("Failed to create load balancer"),
+    );
+    spawn_balancer_with_config(
+        load_balancer,
+        ServerConfig {
+            http2,
+            ..ServerConfig::default()
+        },
+    )
+    .await
+}
+
+/// Reads the HTTP/1.1 response head, returning it as text.
+async fn read_response_head(stream: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(stream.read_u8().await.unwrap());
+    }
+    String::from_utf8(head).unwrap()
+}
+
+#[tokio::test]
+async fn test_prior_knowledge_requests_use_http2() {
+    let addr = start_balancer(Http2Config::default()).await;
+    let client: Client<HttpConnector, Empty<Bytes>> = Client::builder(TokioExecutor::new())
+        .http2_only(true)
+        .build(HttpConnector::new());
+
+    let requests: Vec<_> = (0..5)
+        .map(|_| {
+            let request = Request::get(format!("http://{}/", addr))
+                .body(Empty::new())
+                .unwrap();
+            tokio::spawn(client.request(request))
+        })
+        .collect();
+    for request in requests {
+        let response = request.await.unwrap().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.version(), Version::HTTP_2);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"a");
+    }
+}
+
+#[tokio::test]
+async fn test_h2c_upgrade_answers_request_on_stream_one() {
+    let addr = start_balancer(Http2Config::default()).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    stream.write_all(UPGRADE_REQUEST.as_bytes()).await.unwrap();
+    let head = read_response_head(&mut stream).await;
+    assert!(head.starts_with("HTTP/1.1 101"), "{}", head);
+
+    stream.write_all(PREFACE).await.unwrap();
+    stream.write_all(&EMPTY_SETTINGS).await.unwrap();
+
+    let mut saw_headers = false;
+    let mut body = Vec::new();
+    loop {
+        let mut header = [0; 9];
+        stream.read_exact(&mut header).await.unwrap();
+        let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        let (frame_type, flags) = (header[3], header[4]);
+        let stream_id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]);
+        let mut payload = vec![0; len];
+        stream.read_exact(&mut payload).await.unwrap();
+
+        if stream_id != 1 {
+            continue;
+        }
+        match frame_type {
+            0x1 => saw_headers = true,
+            0x0 => {
+                body.extend_from_slice(&payload);
+                if flags & 0x1 != 0 {
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    assert!(saw_headers);
+    assert_eq!(body, b"a");
+}
+
+#[tokio::test]
+async fn test_h2c_upgrade_can_be_disabled() {
+    let addr = start_balancer(Http2Config {
+        allow_upgrade: false,
+        ..Http2Config::default()
+    })
+    .await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    stream.write_all(UPGRADE_REQUEST.as_bytes()).await.unwrap();
+    let head = read_response_head(&mut stream).await;
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+}
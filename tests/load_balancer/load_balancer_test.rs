@@ -5,12 +5,8 @@ use std::sync::Arc;
 #[tokio::test]
 async fn test_load_balancer_new_with_valid_workers() {
     let workers = vec![
-        Worker {
-            host: "http://localhost:3000".to_string(),
-        },
-        Worker {
-            host: "http://localhost:3001".to_string(),
-        },
+        Worker::new("http://localhost:3000"),
+        Worker::new("http://localhost:3001"),
     ];
     let algorithm = Box::new(RoundRobinAlgorithm::new());
 
@@ -30,12 +26,8 @@ async fn test_load_balancer_new_with_empty_workers() {
 #[tokio::test]
 async fn test_load_balancer_thread_safety() {
     let workers = vec![
-        Worker {
-            host: "http://localhost:3000".to_string(),
-        },
-        Worker {
-            host: "http://localhost:3001".to_string(),
-        },
+        Worker::new("http://localhost:3000"),
+        Worker::new("http://localhost:3001"),
     ];
     let algorithm = Box::new(RoundRobinAlgorithm::new());
 
@@ -0,0 +1,90 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use hyper::{StatusCode, header::HeaderName};
+use load_balancer::balancing_algorithms::RoundRobinAlgorithm;
+use load_balancer::{
+    LoadBalancer, LoadBalancerConfig, LoadSheddingConfig, OverloadDetectorConfig, PriorityMatch,
+    PriorityRule,
+};
+
+use crate::support::{get, get_with_headers, spawn_balancer, spawn_upstream};
+
+/// Priority 0 is shed once one request is in flight; priority 255 only once
+/// two are.
+async fn overloadable_balancer() -> (Arc<LoadBalancer>, SocketAddr) {
+    let workers = vec![spawn_upstream("a").await];
+    let config = LoadBalancerConfig {
+        load_shedding: Some(LoadSheddingConfig {
+            rules: vec![
+                PriorityRule {
+                    matches: PriorityMatch::Header(
+                        HeaderName::from_static("x-client-class"),
+                        "premium".to_string(),
+                    ),
+                    priority: 255,
+                },
+                PriorityRule {
+                    matches: PriorityMatch::Route("/checkout".to_string()),
+                    priority: 255,
+                },
+            ],
+            shed_start: 0.5,
+            detector: OverloadDetectorConfig {
+                max_in_flight: Some(2),
+                ..OverloadDetectorConfig::default()
+            },
+            ..LoadSheddingConfig::default()
+        }),
+        ..LoadBalancerConfig::default()
+    };
+    let load_balancer = Arc::new(
+        LoadBalancer::with_config(workers, Box::new(RoundRobinAlgorithm::new()), config)
+            .expect("Failed to create load balancer"),
+    );
+    let addr = spawn_balancer(load_balancer.clone()).await;
+    (load_balancer, addr)
+}
+
+#[tokio::test]
+async fn test_low_priority_requests_are_shed_first() {
+    let (load_balancer, addr) = overloadable_balancer().await;
+
+    let slow = tokio::spawn(async move { get(addr, "/slow").await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    assert_eq!(get(addr, "/").await.0, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(get(addr, "/checkout").await.0, StatusCode::OK);
+    let premium = get_with_headers(addr, "/", &[("x-client-class", "premium")]).await;
+    assert_eq!(premium.status(), StatusCode::OK);
+
+    assert_eq!(slow.await.unwrap().0, StatusCode::OK);
+    assert_eq!(get(addr, "/").await.0, StatusCode::OK);
+
+    let report = load_balancer.metrics_report().await;
+    assert!(report.contains("shed_requests_total{priority=\"0\"} 1"));
+    assert!(report.contains("rejected_requests_total{reason=\"Overloaded\"} 1"));
+}
+
+#[tokio::test]
+async fn test_health_checks_are_never_shed() {
+    let (_load_balancer, addr) = overloadable_balancer().await;
+
+    let slow = [
+        tokio::spawn(async move { get(addr, "/slow").await.0 }),
+        tokio::spawn(async move {
+            get_with_headers(addr, "/slow", &[("x-client-class", "premium")])
+                .await
+                .status()
+        }),
+    ];
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let premium = get_with_headers(addr, "/", &[("x-client-class", "premium")]).await;
+    assert_eq!(premium.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(get(addr, "/health").await.0, StatusCode::OK);
+    assert_eq!(get(addr, "/admin/metrics").await.0, StatusCode::OK);
+
+    for request in slow {
+        assert_eq!(request.await.unwrap(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,14 @@
+use load_balancer::{LogConfig, LogFormat};
+
+#[test]
+fn test_invalid_filter_is_rejected() {
+    let config = LogConfig {
+        filter: "load_balancer=loud".to_string(),
+        format: LogFormat::Json,
+    };
+
+    // Rejected while parsing, before anything is installed
+    let error = config.init().unwrap_err();
+
+    assert!(error.contains("invalid log filter"), "{}", error);
+}
@@ -1,2 +1,28 @@
+mod adaptive_concurrency_test;
 mod algorithms_test;
+mod circuit_breaker_test;
+mod draining_test;
+mod forwarding_test;
+mod grpc_test;
+#[cfg(unix)]
+mod handoff_test;
+mod hedging_test;
+mod hop_by_hop_test;
+mod http2_test;
 mod load_balancer_test;
+mod load_shedding_test;
+mod logging_test;
+mod proxy_protocol_test;
+mod queue_test;
+mod rate_limit_test;
+mod request_id_test;
+mod retries_test;
+mod server_test;
+mod support;
+mod tcp_proxy_test;
+mod timeouts_test;
+mod tls_test;
+mod udp_proxy_test;
+mod upgrade_test;
+mod upstream_test;
+mod upstream_tls_test;
@@ -0,0 +1,218 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use load_balancer::balancing_algorithms::RoundRobinAlgorithm;
+use load_balancer::{
+    LoadBalancer, LoadBalancerConfig, ProxyProtocolVersion, RateLimitConfig, RateLimitKey,
+    RateLimitRule, Server, ServerConfig, TcpProxy, TcpProxyConfig, Worker,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::support::spawn_upstream;
+
+/// A v2 header for a TCP connection from 203.0.113.7:4000 to
+/// 198.51.100.1:443.
+fn v2_header() -> Vec<u8> {
+    let mut header = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+    header.extend_from_slice(&[0x21, 0x11, 0, 12]);
+    header.extend_from_slice(&[203, 0, 113, 7, 198, 51, 100, 1]);
+    header.extend_from_slice(&4000u16.to_be_bytes());
+    header.extend_from_slice(&443u16.to_be_bytes());
+    header
+}
+
+/// Starts a balancer that accepts PROXY protocol and allows each client
+/// address a single request.
+async fn start_balancer() -> SocketAddr {
+    let config = LoadBalancerConfig {
+        rate_limit: RateLimitConfig {
+            rules: vec![RateLimitRule {
+                path_prefix: "/".to_string(),
+                key: RateLimitKey::ClientIp,
+                burst: 1,
+                refill_per_sec: 0.01,
+            }],
+        },
+        ..LoadBalancerConfig::default()
+    };
+    let load_balancer = Arc::new(
+        LoadBalancer::with_config(
+            vec![spawn_upstream("a").await],
+            Box::new(RoundRobinAlgorithm::new()),
+            config,
+        )
+        .expect("Failed to create load balancer"),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_config = ServerConfig {
+        accept_proxy_protocol: true,
+        ..ServerConfig::default()
+    };
+    tokio::spawn(async move {
+        Server::new(load_balancer, server_config)
+            .serve(listener, std::future::pending())
+            .await
+    });
+    addr
+}
+
+/// Sends `header` and then a GET request, returning the response's status
+/// line, or `None` if the connection is closed without one.
+async fn get_after_header(addr: SocketAddr, header: &[u8]) -> Option<String> {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(header).await.unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response).await;
+    let response = String::from_utf8(response).unwrap();
+    response.lines().next().map(str::to_string)
+}
+
+#[tokio::test]
+async fn test_client_address_is_taken_from_the_header() {
+    let addr = start_balancer().await;
+    let v1 = b"PROXY TCP4 192.0.2.1 198.51.100.1 5000 80\r\n";
+
+    // Each reported client gets its own rate limit bucket, although every
+    // connection comes from the same socket address
+    let status = get_after_header(addr, v1).await;
+    assert_eq!(status.as_deref(), Some("HTTP/1.1 200 OK"));
+    let status = get_after_header(addr, &v2_header()).await;
+    assert_eq!(status.as_deref(), Some("HTTP/1.1 200 OK"));
+    let status = get_after_header(addr, v1).await;
+    assert_eq!(status.as_deref(), Some("HTTP/1.1 429 Too Many Requests"));
+}
+
+#[tokio::test]
+async fn test_local_header_keeps_the_socket_address() {
+    let addr = start_balancer().await;
+    let unknown = b"PROXY UNKNOWN\r\n";
+    let mut local = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+    local.extend_from_slice(&[0x20, 0x00, 0, 0]);
+
+    let status = get_after_header(addr, unknown).await;
+    assert_eq!(status.as_deref(), Some("HTTP/1.1 200 OK"));
+    let status = get_after_header(addr, &local).await;
+    assert_eq!(status.as_deref(), Some("HTTP/1.1 429 Too Many Requests"));
+}
+
+#[tokio::test]
+async fn test_connection_without_header_is_closed() {
+    let addr = start_balancer().await;
+    assert_eq!(get_after_header(addr, b"").await, None);
+}
+
+/// Starts a TCP proxy in front of a listener the test accepts from itself.
+async fn start_tcp_proxy(config: TcpProxyConfig) -> (SocketAddr, TcpListener) {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let worker = Worker::new(upstream.local_addr().unwrap().to_string());
+    let proxy = Arc::new(
+        TcpProxy::new(vec![worker], Box::new(RoundRobinAlgorithm::new()), config).unwrap(),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(proxy.serve(listener, std::future::pending()));
+    (addr, upstream)
+}
+
+#[tokio::test]
+async fn test_tcp_proxy_sends_v1_header_to_workers() {
+    let (addr, upstream) = start_tcp_proxy(TcpProxyConfig {
+        send_proxy_protocol: Some(ProxyProtocolVersion::V1),
+        ..TcpProxyConfig::default()
+    })
+    .await;
+
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    client.write_all(b"hello").await.unwrap();
+    let (mut worker, _) = upstream.accept().await.unwrap();
+
+    let expected = format!(
+        "PROXY TCP4 127.0.0.1 127.0.0.1 {} {}\r\nhello",
+        client.local_addr().unwrap().port(),
+        addr.port()
+    );
+    let mut received = vec![0; expected.len()];
+    worker.read_exact(&mut received).await.unwrap();
+    assert_eq!(String::from_utf8(received).unwrap(), expected);
+}
+
+#[tokio::test]
+async fn test_tcp_proxy_passes_inbound_client_on_as_v2() {
+    let (addr, upstream) = start_tcp_proxy(TcpProxyConfig {
+        accept_proxy_protocol: true,
+        send_proxy_protocol: Some(ProxyProtocolVersion::V2),
+        ..TcpProxyConfig::default()
+    })
+    .await;
+
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    client
+        .write_all(b"PROXY TCP4 203.0.113.7 198.51.100.1 4000 443\r\nhello")
+        .await
+        .unwrap();
+    let (mut worker, _) = upstream.accept().await.unwrap();
+
+    let mut expected = v2_header();
+    expected.extend_from_slice(b"hello");
+    let mut received = vec![0; expected.len()];
+    worker.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, expected);
+
+    // The rest of the connection is piped as usual
+    worker.write_all(b"world").await.unwrap();
+    let mut reply = [0; 5];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"world");
+}
+
+#[tokio::test]
+async fn test_tcp_proxy_passes_socket_address_on_for_local_header() {
+    let (addr, upstream) = start_tcp_proxy(TcpProxyConfig {
+        accept_proxy_protocol: true,
+        send_proxy_protocol: Some(ProxyProtocolVersion::V1),
+        ..TcpProxyConfig::default()
+    })
+    .await;
+
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    let mut local = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+    local.extend_from_slice(&[0x20, 0x00, 0, 0]);
+    local.extend_from_slice(b"hello");
+    client.write_all(&local).await.unwrap();
+    let (mut worker, _) = upstream.accept().await.unwrap();
+
+    let expected = format!(
+        "PROXY TCP4 127.0.0.1 127.0.0.1 {} {}\r\nhello",
+        client.local_addr().unwrap().port(),
+        addr.port()
+    );
+    let mut received = vec![0; expected.len()];
+    worker.read_exact(&mut received).await.unwrap();
+    assert_eq!(String::from_utf8(received).unwrap(), expected);
+}
+
+#[tokio::test]
+async fn test_tcp_proxy_drops_connection_without_header() {
+    let (addr, upstream) = start_tcp_proxy(TcpProxyConfig {
+        accept_proxy_protocol: true,
+        ..TcpProxyConfig::default()
+    })
+    .await;
+
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    client.write_all(b"hello, no header here").await.unwrap();
+    let mut buf = [0; 16];
+    assert!(matches!(client.read(&mut buf).await, Ok(0) | Err(_)));
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), upstream.accept())
+            .await
+            .is_err()
+    );
+}
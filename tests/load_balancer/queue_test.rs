@@ -0,0 +1,145 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use hyper::StatusCode;
+use hyper::header::HeaderName;
+use load_balancer::balancing_algorithms::RoundRobinAlgorithm;
+use load_balancer::{LoadBalancer, LoadBalancerConfig, QueueConfig, QueueOrdering};
+
+use crate::support::{get, get_with_headers, spawn_balancer, spawn_upstream};
+
+async fn single_slot_balancer(queue: Option<QueueConfig>) -> Arc<LoadBalancer> {
+    let workers = vec![spawn_upstream("a").await.with_max_connections(1)];
+    let config = LoadBalancerConfig {
+        queue,
+        ..LoadBalancerConfig::default()
+    };
+    Arc::new(
+        LoadBalancer::with_config(workers, Box::new(RoundRobinAlgorithm::new()), config)
+            .expect("Failed to create load balancer"),
+    )
+}
+
+#[tokio::test]
+async fn test_request_waits_for_worker_at_capacity() {
+    let addr = spawn_balancer(single_slot_balancer(Some(QueueConfig::default())).await).await;
+
+    let started = Instant::now();
+    let first = tokio::spawn(async move { get(addr, "/slow").await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let (status, _) = get(addr, "/slow").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(first.await.unwrap().0, StatusCode::OK);
+    // The second request only started once the first had finished
+    assert!(started.elapsed() >= Duration::from_millis(1000));
+}
+
+#[tokio::test]
+async fn test_full_queue_rejects_with_service_unavailable() {
+    let load_balancer = single_slot_balancer(Some(QueueConfig {
+        max_size: 0,
+        ..QueueConfig::default()
+    }))
+    .await;
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    let first = tokio::spawn(async move { get(addr, "/slow").await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    assert_eq!(get(addr, "/").await.0, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(first.await.unwrap().0, StatusCode::OK);
+    assert!(
+        load_balancer
+            .metrics_report()
+            .await
+            .contains("rejected_requests_total{reason=\"QueueFull\"} 1")
+    );
+}
+
+#[tokio::test]
+async fn test_queued_request_times_out() {
+    let load_balancer = single_slot_balancer(Some(QueueConfig {
+        timeout: Duration::from_millis(100),
+        ..QueueConfig::default()
+    }))
+    .await;
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    let _first = tokio::spawn(async move { get(addr, "/slow").await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    assert_eq!(get(addr, "/").await.0, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(
+        load_balancer
+            .metrics_report()
+            .await
+            .contains("rejected_requests_total{reason=\"QueueTimeout\"} 1")
+    );
+}
+
+#[tokio::test]
+async fn test_priority_queue_serves_higher_priority_first() {
+    let addr = spawn_balancer(
+        single_slot_balancer(Some(QueueConfig {
+            ordering: QueueOrdering::Priority(HeaderName::from_static("x-priority")),
+            ..QueueConfig::default()
+        }))
+        .await,
+    )
+    .await;
+
+    let queued = |priority: &'static str| async move {
+        get_with_headers(addr, "/slow", &[("x-priority", priority)]).await;
+        Instant::now()
+    };
+
+    let _first = tokio::spawn(async move { get(addr, "/slow").await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let low = tokio::spawn(queued("1"));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let high = tokio::spawn(queued("9"));
+
+    assert!(high.await.unwrap() < low.await.unwrap());
+}
+
+#[tokio::test]
+async fn test_timed_out_request_leaves_the_queue() {
+    let load_balancer = single_slot_balancer(Some(QueueConfig {
+        timeout: Duration::from_millis(100),
+        ..QueueConfig::default()
+    }))
+    .await;
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    let _first = tokio::spawn(async move { get(addr, "/slow").await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(get(addr, "/").await.0, StatusCode::SERVICE_UNAVAILABLE);
+
+    assert!(
+        load_balancer
+            .metrics_report()
+            .await
+            .contains("request_queue_length 0")
+    );
+}
+
+#[tokio::test]
+async fn test_cancelled_waiter_passes_its_turn_on() {
+    let addr = spawn_balancer(single_slot_balancer(Some(QueueConfig::default())).await).await;
+
+    let first = tokio::spawn(async move { get(addr, "/slow").await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    // Queued first, then its client gives up
+    let cancelled = tokio::time::timeout(Duration::from_millis(100), get(addr, "/")).await;
+    assert!(cancelled.is_err());
+
+    let started = Instant::now();
+    let (status, _) = get(addr, "/").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(first.await.unwrap().0, StatusCode::OK);
+    // Served as soon as the first request finished, not at the queue timeout
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
@@ -0,0 +1,148 @@
+use std::{sync::Arc, time::Duration};
+
+use hyper::StatusCode;
+use hyper::header::HeaderName;
+use load_balancer::balancing_algorithms::RoundRobinAlgorithm;
+use load_balancer::{
+    LoadBalancer, LoadBalancerConfig, RateLimitConfig, RateLimitKey, RateLimitRule,
+};
+
+use crate::support::{get_response, get_with_headers, spawn_balancer, spawn_upstream};
+
+async fn rate_limited_balancer(rules: Vec<RateLimitRule>) -> Arc<LoadBalancer> {
+    let workers = vec![spawn_upstream("a").await];
+    let config = LoadBalancerConfig {
+        rate_limit: RateLimitConfig { rules },
+        ..LoadBalancerConfig::default()
+    };
+    Arc::new(
+        LoadBalancer::with_config(workers, Box::new(RoundRobinAlgorithm::new()), config)
+            .expect("Failed to create load balancer"),
+    )
+}
+
+#[tokio::test]
+async fn test_client_ip_limit_rejects_after_burst() {
+    let load_balancer = rate_limited_balancer(vec![RateLimitRule {
+        path_prefix: "/".to_string(),
+        key: RateLimitKey::ClientIp,
+        burst: 2,
+        refill_per_sec: 1.0,
+    }])
+    .await;
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    let response = get_response(addr, "/").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["ratelimit-limit"], "2");
+    assert_eq!(response.headers()["ratelimit-remaining"], "1");
+    assert_eq!(get_response(addr, "/").await.status(), StatusCode::OK);
+
+    let response = get_response(addr, "/").await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["retry-after"], "1");
+    assert_eq!(response.headers()["ratelimit-remaining"], "0");
+
+    assert!(
+        load_balancer
+            .metrics_report()
+            .await
+            .contains("rate_limited_total{route=\"/\",key=\"client_ip\"} 1")
+    );
+}
+
+#[tokio::test]
+async fn test_header_limit_is_per_header_value() {
+    let load_balancer = rate_limited_balancer(vec![RateLimitRule {
+        path_prefix: "/".to_string(),
+        key: RateLimitKey::Header(HeaderName::from_static("x-api-key")),
+        burst: 1,
+        refill_per_sec: 0.1,
+    }])
+    .await;
+    let addr = spawn_balancer(load_balancer).await;
+
+    let status = |key| async move {
+        get_with_headers(addr, "/", &[("x-api-key", key)])
+            .await
+            .status()
+    };
+    assert_eq!(status("one").await, StatusCode::OK);
+    assert_eq!(status("one").await, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(status("two").await, StatusCode::OK);
+
+    // Requests without the header aren't limited by the rule
+    assert_eq!(get_response(addr, "/").await.status(), StatusCode::OK);
+    assert_eq!(get_response(addr, "/").await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_route_limit_only_applies_to_route_and_refills() {
+    let load_balancer = rate_limited_balancer(vec![RateLimitRule {
+        path_prefix: "/limited".to_string(),
+        key: RateLimitKey::Route,
+        burst: 1,
+        refill_per_sec: 10.0,
+    }])
+    .await;
+    let addr = spawn_balancer(load_balancer).await;
+
+    assert_eq!(
+        get_response(addr, "/limited").await.status(),
+        StatusCode::OK
+    );
+    assert_eq!(
+        get_response(addr, "/limited").await.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(get_response(addr, "/other").await.status(), StatusCode::OK);
+
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert_eq!(
+        get_response(addr, "/limited").await.status(),
+        StatusCode::OK
+    );
+}
+
+#[tokio::test]
+async fn test_non_positive_refill_rate_is_rejected() {
+    for refill_per_sec in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+        let config = LoadBalancerConfig {
+            rate_limit: RateLimitConfig {
+                rules: vec![RateLimitRule {
+                    path_prefix: "/".to_string(),
+                    key: RateLimitKey::Route,
+                    burst: 1,
+                    refill_per_sec,
+                }],
+            },
+            ..LoadBalancerConfig::default()
+        };
+        let load_balancer = LoadBalancer::with_config(
+            vec![spawn_upstream("a").await],
+            Box::new(RoundRobinAlgorithm::new()),
+            config,
+        );
+        assert!(load_balancer.is_err(), "accepted {}", refill_per_sec);
+    }
+}
+
+#[tokio::test]
+async fn test_tiny_refill_rate_saturates_reset_headers() {
+    let load_balancer = rate_limited_balancer(vec![RateLimitRule {
+        path_prefix: "/".to_string(),
+        key: RateLimitKey::Route,
+        burst: 1,
+        refill_per_sec: 1e-300,
+    }])
+    .await;
+    let addr = spawn_balancer(load_balancer).await;
+
+    let response = get_response(addr, "/").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["ratelimit-reset"], u64::MAX.to_string());
+
+    let response = get_response(addr, "/").await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["retry-after"], u64::MAX.to_string());
+}
@@ -0,0 +1,125 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use http_body_util::BodyExt;
+use hyper::{StatusCode, header::HeaderName};
+use load_balancer::balancing_algorithms::RoundRobinAlgorithm;
+use load_balancer::{
+    ForwardingConfig, LoadBalancer, LoadBalancerConfig, RequestIdConfig, TrustIncomingId, Worker,
+};
+
+use crate::support::{get_with_headers, spawn_balancer, spawn_header_echo_upstream};
+
+async fn start_balancer(worker: Worker, config: LoadBalancerConfig) -> SocketAddr {
+    let load_balancer = Arc::new(
+        LoadBalancer::with_config(vec![worker], Box::new(RoundRobinAlgorithm::new()), config)
+            .expect("Failed to create load balancer"),
+    );
+    spawn_balancer(load_balancer).await
+}
+
+/// Returns the ID the client got back and the one the worker received.
+async fn request_ids(
+    config: LoadBalancerConfig,
+    header: &'static str,
+    headers: &[(&str, &str)],
+) -> (String, String) {
+    let addr = start_balancer(spawn_header_echo_upstream(header).await, config).await;
+    let response = get_with_headers(addr, "/", headers).await;
+    let returned = response.headers()[header].to_str().unwrap().to_string();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (returned, String::from_utf8_lossy(&body).into_owned())
+}
+
+fn is_uuid_v7(id: &str) -> bool {
+    id.len() == 36 && id.as_bytes()[14] == b'7'
+}
+
+#[tokio::test]
+async fn test_generated_id_is_forwarded_and_returned() {
+    let (returned, received) =
+        request_ids(LoadBalancerConfig::default(), "x-request-id", &[]).await;
+    assert!(is_uuid_v7(&returned), "{}", returned);
+    assert_eq!(received, returned);
+
+    let (other, _) = request_ids(LoadBalancerConfig::default(), "x-request-id", &[]).await;
+    assert_ne!(other, returned);
+}
+
+#[tokio::test]
+async fn test_untrusted_client_id_is_replaced() {
+    let (returned, received) = request_ids(
+        LoadBalancerConfig::default(),
+        "x-request-id",
+        &[("x-request-id", "client-chosen")],
+    )
+    .await;
+    assert!(is_uuid_v7(&returned), "{}", returned);
+    assert_eq!(received, returned);
+}
+
+#[tokio::test]
+async fn test_trusted_proxy_id_is_reused() {
+    let config = LoadBalancerConfig {
+        forwarding: ForwardingConfig {
+            trusted_proxies: vec!["127.0.0.1/32".parse().unwrap()],
+            ..ForwardingConfig::default()
+        },
+        ..LoadBalancerConfig::default()
+    };
+    let (returned, received) =
+        request_ids(config, "x-request-id", &[("x-request-id", "edge-1234")]).await;
+    assert_eq!(returned, "edge-1234");
+    assert_eq!(received, "edge-1234");
+}
+
+#[tokio::test]
+async fn test_custom_header_and_invalid_ids() {
+    let config = || LoadBalancerConfig {
+        request_id: RequestIdConfig {
+            header: HeaderName::from_static("x-correlation-id"),
+            trust_incoming: TrustIncomingId::Always,
+        },
+        ..LoadBalancerConfig::default()
+    };
+    let (returned, received) = request_ids(
+        config(),
+        "x-correlation-id",
+        &[("x-correlation-id", "abc-123")],
+    )
+    .await;
+    assert_eq!(
+        (returned.as_str(), received.as_str()),
+        ("abc-123", "abc-123")
+    );
+
+    let too_long = "a".repeat(201);
+    for invalid in ["has spaces", too_long.as_str()] {
+        let (returned, _) = request_ids(
+            config(),
+            "x-correlation-id",
+            &[("x-correlation-id", invalid)],
+        )
+        .await;
+        assert!(is_uuid_v7(&returned), "{}", returned);
+    }
+}
+
+#[tokio::test]
+async fn test_rejections_carry_the_id() {
+    let worker = spawn_header_echo_upstream("x-request-id").await;
+    let host = worker.host.clone();
+    let load_balancer = Arc::new(
+        LoadBalancer::new(vec![worker], Box::new(RoundRobinAlgorithm::new()))
+            .expect("Failed to create load balancer"),
+    );
+    load_balancer
+        .drain_worker(&host, Duration::from_secs(1))
+        .unwrap();
+    let addr = spawn_balancer(load_balancer).await;
+
+    let response = get_with_headers(addr, "/", &[]).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(is_uuid_v7(
+        response.headers()["x-request-id"].to_str().unwrap()
+    ));
+}
@@ -0,0 +1,121 @@
+use std::sync::Arc;
+
+use hyper::StatusCode;
+use load_balancer::balancing_algorithms::RoundRobinAlgorithm;
+use load_balancer::{LoadBalancer, LoadBalancerConfig, RetryConfig, Worker};
+
+use crate::support::{
+    get, get_response, post, spawn_balancer, spawn_failing_upstream, spawn_upstream,
+    unreachable_worker,
+};
+
+fn load_balancer(workers: Vec<Worker>, retries: RetryConfig) -> Arc<LoadBalancer> {
+    let config = LoadBalancerConfig {
+        retries,
+        circuit_breaker: None,
+        ..LoadBalancerConfig::default()
+    };
+    Arc::new(
+        LoadBalancer::with_config(workers, Box::new(RoundRobinAlgorithm::new()), config)
+            .expect("Failed to create load balancer"),
+    )
+}
+
+#[tokio::test]
+async fn test_connection_failure_is_retried_on_another_worker() {
+    let workers = vec![unreachable_worker().await, spawn_upstream("b").await];
+    let load_balancer = load_balancer(workers, RetryConfig::default());
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    let (status, body) = get(addr, "/").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "b");
+    assert!(
+        load_balancer
+            .metrics_report()
+            .await
+            .contains("retries_total 1")
+    );
+}
+
+#[tokio::test]
+async fn test_non_idempotent_request_is_retried_when_never_sent() {
+    let workers = vec![unreachable_worker().await, spawn_upstream("b").await];
+    let addr = spawn_balancer(load_balancer(workers, RetryConfig::default())).await;
+
+    let (status, body) = post(addr, "/", "payload").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "b");
+}
+
+#[tokio::test]
+async fn test_retryable_status_is_retried_for_idempotent_methods_only() {
+    let workers = vec![
+        spawn_failing_upstream(StatusCode::SERVICE_UNAVAILABLE).await,
+        spawn_upstream("b").await,
+    ];
+    let addr = spawn_balancer(load_balancer(workers, RetryConfig::default())).await;
+
+    // First attempt of each request lands on the failing worker
+    let (status, body) = get(addr, "/").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "b");
+
+    let (status, _) = post(addr, "/", "payload").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn test_retries_disabled_returns_first_response() {
+    let workers = vec![
+        spawn_failing_upstream(StatusCode::SERVICE_UNAVAILABLE).await,
+        spawn_upstream("b").await,
+    ];
+    let retries = RetryConfig {
+        max_retries: 0,
+        ..RetryConfig::default()
+    };
+    let addr = spawn_balancer(load_balancer(workers, retries)).await;
+
+    assert_eq!(get(addr, "/").await.0, StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn test_retry_budget_limits_retries() {
+    let workers = vec![
+        spawn_failing_upstream(StatusCode::SERVICE_UNAVAILABLE).await,
+        spawn_failing_upstream(StatusCode::SERVICE_UNAVAILABLE).await,
+    ];
+    let retries = RetryConfig {
+        budget_ratio: 0.0,
+        budget_min_per_sec: 0,
+        ..RetryConfig::default()
+    };
+    let load_balancer = load_balancer(workers, retries);
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    for _ in 0..12 {
+        assert_eq!(get(addr, "/").await.0, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    let report = load_balancer.metrics_report().await;
+    assert!(report.contains("retries_total 10"));
+    assert!(report.contains("retry_budget_exhausted_total 2"));
+}
+
+#[tokio::test]
+async fn test_unreachable_workers_answer_bad_gateway() {
+    let workers = vec![unreachable_worker().await];
+    let load_balancer = load_balancer(workers, RetryConfig::default());
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    let response = get_response(addr, "/").await;
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    assert!(response.headers().contains_key("x-request-id"));
+    assert!(
+        load_balancer
+            .metrics_report()
+            .await
+            .contains("upstream_failures_total 1")
+    );
+}
@@ -0,0 +1,79 @@
+use std::{sync::Arc, time::Duration};
+
+use hyper::StatusCode;
+use load_balancer::balancing_algorithms::RoundRobinAlgorithm;
+use load_balancer::{LoadBalancer, Server, ServerConfig, ShutdownOutcome};
+use tokio::{net::TcpListener, sync::oneshot};
+
+use crate::support::{get, spawn_upstream};
+
+async fn start_server(
+    grace_period: Duration,
+) -> (
+    std::net::SocketAddr,
+    oneshot::Sender<()>,
+    tokio::task::JoinHandle<ShutdownOutcome>,
+) {
+    let workers = vec![spawn_upstream("a").await];
+    let load_balancer = Arc::new(
+        LoadBalancer::new(workers, Box::new(RoundRobinAlgorithm::new()))
+            .expect("Failed to create load balancer"),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+
+    let server = Server::new(
+        load_balancer,
+        ServerConfig {
+            shutdown_grace_period: grace_period,
+            ..ServerConfig::default()
+        },
+    );
+    let handle = tokio::spawn(async move {
+        server
+            .serve(listener, async {
+                let _ = shutdown_rx.await;
+            })
+            .await
+    });
+
+    (addr, shutdown_tx, handle)
+}
+
+#[tokio::test]
+async fn test_shutdown_finishes_in_flight_requests() {
+    let (addr, shutdown_tx, handle) = start_server(Duration::from_secs(5)).await;
+
+    let slow_request = tokio::spawn(async move { get(addr, "/slow").await });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    shutdown_tx.send(()).unwrap();
+
+    let (status, body) = slow_request.await.unwrap();
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "a");
+    assert_eq!(handle.await.unwrap(), ShutdownOutcome::Graceful);
+
+    // The listener is closed once shutdown starts
+    assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn test_shutdown_reports_elapsed_grace_period() {
+    let (addr, shutdown_tx, handle) = start_server(Duration::from_millis(50)).await;
+
+    let _slow_request = tokio::spawn(async move { get(addr, "/slow").await });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    shutdown_tx.send(()).unwrap();
+
+    assert_eq!(handle.await.unwrap(), ShutdownOutcome::GracePeriodElapsed);
+}
+
+#[tokio::test]
+async fn test_shutdown_with_no_connections_is_graceful() {
+    let (_addr, shutdown_tx, handle) = start_server(Duration::from_secs(5)).await;
+
+    shutdown_tx.send(()).unwrap();
+
+    assert_eq!(handle.await.unwrap(), ShutdownOutcome::Graceful);
+}
@@ -0,0 +1,543 @@
+use std::{
+    convert::Infallible,
+    future::Future,
+    net::SocketAddr,
+    path::PathBuf,
+    process::Stdio,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use http_body_util::{BodyExt, Empty, Full};
+use hyper::{
+    Request, Response, StatusCode,
+    body::{Body, Bytes, Incoming},
+    server::conn::{http1, http2},
+    service::service_fn,
+};
+use hyper_util::{
+    client::legacy::{Client, connect::HttpConnector},
+    rt::{TokioExecutor, TokioIo},
+};
+use load_balancer::{LoadBalancer, Server, ServerConfig, TlsCertificate, UpstreamProtocol, Worker};
+use rustls::{
+    ClientConfig, RootCertStore,
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName, pem::PemObject},
+    server::WebPkiClientVerifier,
+};
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+    process::{Child, Command},
+};
+use tokio_rustls::{TlsAcceptor, TlsConnector, client::TlsStream};
+
+/// Starts an upstream that answers every request with `name`.
+/// `/slow` responds after 500ms.
+pub async fn spawn_upstream(name: &'static str) -> Worker {
+    spawn_delayed_upstream(name, Duration::ZERO).await
+}
+
+/// Like [`spawn_upstream`], but every response is delayed by `delay`.
+pub async fn spawn_delayed_upstream(name: &'static str, delay: Duration) -> Worker {
+    spawn_upstream_with(move |req| async move {
+        tokio::time::sleep(delay).await;
+        if req.uri().path() == "/slow" {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        Response::new(Full::new(Bytes::from(name)))
+    })
+    .await
+}
+
+/// Starts an HTTP/1.1 upstream that answers requests with `handler`.
+/// Connections may be upgraded.
+pub async fn spawn_upstream_with<F, Fut, B>(handler: F) -> Worker
+where
+    F: Fn(Request<Incoming>) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Response<B>> + Send + 'static,
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req| {
+                    let response = handler(req);
+                    async move { Ok::<_, Infallible>(response.await) }
+                });
+                let _ = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .with_upgrades()
+                    .await;
+            });
+        }
+    });
+
+    Worker::new(format!("http://{}", addr))
+}
+
+/// Starts an HTTP/2-only upstream that answers every request with `name`
+/// and the request's HTTP version. `/slow` responds after 500ms.
+pub async fn spawn_http2_upstream(name: &'static str) -> Worker {
+    spawn_http2_upstream_with(move |req| async move {
+        if req.uri().path() == "/slow" {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        let body = format!("{} {:?}", name, req.version());
+        Response::new(Full::new(Bytes::from(body)))
+    })
+    .await
+}
+
+/// Like [`spawn_upstream_with`], but speaking HTTP/2 only.
+pub async fn spawn_http2_upstream_with<F, Fut, B>(handler: F) -> Worker
+where
+    F: Fn(Request<Incoming>) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Response<B>> + Send + 'static,
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req| {
+                    let response = handler(req);
+                    async move { Ok::<_, Infallible>(response.await) }
+                });
+                let _ = http2::Builder::new(TokioExecutor::new())
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+
+    Worker::new(format!("http://{}", addr)).with_protocol(UpstreamProtocol::Http2)
+}
+
+/// Starts an HTTPS upstream serving `certificate` that answers every
+/// request with `name` and the SNI the client sent. With `client_ca`, only
+/// clients presenting a certificate issued by it may connect.
+pub async fn spawn_https_upstream(
+    name: &'static str,
+    certificate: &TestCertificate,
+    client_ca: Option<&CertificateDer<'static>>,
+) -> SocketAddr {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .unwrap();
+    let builder = match client_ca {
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+            roots.add(ca.clone()).unwrap();
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .unwrap();
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder
+        .with_single_cert(
+            vec![CertificateDer::from_pem_file(&certificate.cert_path).unwrap()],
+            PrivateKeyDer::from_pem_file(&certificate.key_path).unwrap(),
+        )
+        .unwrap();
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let Ok(stream) = acceptor.accept(stream).await else {
+                    return;
+                };
+                let sni = stream.get_ref().1.server_name().unwrap_or("-").to_string();
+                let service = service_fn(move |_req: Request<Incoming>| {
+                    let body = format!("{} {}", name, sni);
+                    async move { Ok::<_, Infallible>(Response::new(Full::new(Bytes::from(body)))) }
+                });
+                let _ = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+
+    addr
+}
+
+/// Starts an upstream that answers every request with the value of its
+/// `header` header, or `-` without one.
+pub async fn spawn_header_echo_upstream(header: &'static str) -> Worker {
+    spawn_upstream_with(move |req| async move {
+        let value = req.headers().get(header).map_or("-".to_string(), |value| {
+            String::from_utf8_lossy(value.as_bytes()).into_owned()
+        });
+        Response::new(Full::new(Bytes::from(value)))
+    })
+    .await
+}
+
+/// Starts an upstream that switches `Upgrade: echo` requests to a protocol
+/// echoing back every byte it receives. Other requests get 426.
+pub async fn spawn_upgrade_echo_upstream() -> Worker {
+    spawn_upstream_with(|mut req: Request<Incoming>| async move {
+        let mut response = Response::new(Empty::<Bytes>::new());
+        if req
+            .headers()
+            .get("upgrade")
+            .is_none_or(|value| value != "echo")
+        {
+            *response.status_mut() = StatusCode::UPGRADE_REQUIRED;
+            return response;
+        }
+        let on_upgrade = hyper::upgrade::on(&mut req);
+        tokio::spawn(async move {
+            let mut upgraded = TokioIo::new(on_upgrade.await.unwrap());
+            let mut buf = [0; 1024];
+            while let Ok(n) = upgraded.read(&mut buf).await
+                && n > 0
+            {
+                upgraded.write_all(&buf[..n]).await.unwrap();
+            }
+        });
+        *response.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
+        response
+            .headers_mut()
+            .insert("connection", "upgrade".parse().unwrap());
+        response
+            .headers_mut()
+            .insert("upgrade", "echo".parse().unwrap());
+        response
+    })
+    .await
+}
+
+/// Starts a raw TCP upstream that greets each connection with `name` and a
+/// newline, then echoes back whatever it receives.
+pub async fn spawn_tcp_echo_upstream(name: &'static str) -> Worker {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                if stream
+                    .write_all(format!("{}\n", name).as_bytes())
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+                let mut buf = [0; 1024];
+                while let Ok(n) = stream.read(&mut buf).await
+                    && n > 0
+                {
+                    if stream.write_all(&buf[..n]).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    Worker::new(addr.to_string())
+}
+
+/// Starts a UDP upstream that answers every datagram with `name`, a colon
+/// and the datagram itself.
+pub async fn spawn_udp_echo_upstream(name: &'static str) -> Worker {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut buf = [0; 1024];
+        while let Ok((n, peer)) = socket.recv_from(&mut buf).await {
+            let mut reply = format!("{}:", name).into_bytes();
+            reply.extend_from_slice(&buf[..n]);
+            let _ = socket.send_to(&reply, peer).await;
+        }
+    });
+
+    Worker::new(addr.to_string())
+}
+
+/// Starts an HTTP/2 gRPC upstream. Calls get a message holding `name`,
+/// and `grpc.health.v1.Health/Check` reports `SERVING`. Once the returned
+/// flag is cleared, calls fail with a trailers-only `UNAVAILABLE` and the
+/// health check reports `NOT_SERVING`.
+pub async fn spawn_grpc_upstream(name: &'static str) -> (Worker, Arc<AtomicBool>) {
+    let serving = Arc::new(AtomicBool::new(true));
+    let flag = serving.clone();
+    let worker = spawn_http2_upstream_with(move |req| {
+        let serving = flag.load(Ordering::SeqCst);
+        async move {
+            let health_check = req.uri().path() == "/grpc.health.v1.Health/Check";
+            req.into_body().collect().await.unwrap();
+            let message = match (health_check, serving) {
+                // HealthCheckResponse { status: SERVING or NOT_SERVING }
+                (true, serving) => vec![0x08, if serving { 1 } else { 2 }],
+                (false, true) => name.as_bytes().to_vec(),
+                (false, false) => {
+                    return Response::builder()
+                        .header("content-type", "application/grpc")
+                        .header("grpc-status", "14")
+                        .body(Empty::new().boxed())
+                        .unwrap();
+                }
+            };
+            let mut trailers = hyper::HeaderMap::new();
+            trailers.insert("grpc-status", "0".parse().unwrap());
+            let body = Full::new(grpc_frame(&message))
+                .with_trailers(async move { Some(Ok(trailers)) })
+                .boxed();
+            Response::builder()
+                .header("content-type", "application/grpc")
+                .body(body)
+                .unwrap()
+        }
+    })
+    .await;
+    (worker, serving)
+}
+
+/// Prefixes a gRPC message with its compression flag and length.
+pub fn grpc_frame(message: &[u8]) -> Bytes {
+    let mut frame = vec![0];
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(message);
+    Bytes::from(frame)
+}
+
+/// Starts an upstream that answers every request with `status`.
+pub async fn spawn_failing_upstream(status: StatusCode) -> Worker {
+    spawn_upstream_with(move |_req| async move {
+        let mut response = Response::new(Full::new(Bytes::from("failed")));
+        *response.status_mut() = status;
+        response
+    })
+    .await
+}
+
+/// Returns a worker whose address refuses connections.
+pub async fn unreachable_worker() -> Worker {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+
+    Worker::new(format!("http://{}", addr))
+}
+
+/// Starts an upstream that sends half of its response body and then stalls.
+pub async fn spawn_stalling_upstream() -> Worker {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf).await;
+                let _ = stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nhello")
+                    .await;
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            });
+        }
+    });
+
+    Worker::new(format!("http://{}", addr))
+}
+
+/// Runs the `worker` binary on a free port, killing it once the returned
+/// child is dropped.
+pub async fn spawn_worker_binary() -> (Worker, Child) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+
+    let child = Command::new(env!("CARGO_BIN_EXE_worker"))
+        .arg(addr.port().to_string())
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("failed to start worker binary");
+    for _ in 0..100 {
+        if TcpStream::connect(addr).await.is_ok() {
+            return (Worker::new(format!("http://{}", addr)), child);
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("worker binary never started listening on {}", addr);
+}
+
+pub async fn spawn_balancer(load_balancer: Arc<LoadBalancer>) -> SocketAddr {
+    spawn_balancer_with_config(load_balancer, ServerConfig::default()).await
+}
+
+pub async fn spawn_balancer_with_config(
+    load_balancer: Arc<LoadBalancer>,
+    config: ServerConfig,
+) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        Server::new(load_balancer, config)
+            .serve(listener, std::future::pending())
+            .await
+    });
+
+    addr
+}
+
+pub async fn get(addr: SocketAddr, path: &str) -> (StatusCode, String) {
+    let response = get_response(addr, path).await;
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, String::from_utf8_lossy(&body).into_owned())
+}
+
+pub async fn post(addr: SocketAddr, path: &str, body: &'static str) -> (StatusCode, String) {
+    let client: Client<HttpConnector, Full<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+    let request = Request::post(format!("http://{}{}", addr, path))
+        .body(Full::new(Bytes::from(body)))
+        .unwrap();
+    let response = client.request(request).await.unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, String::from_utf8_lossy(&body).into_owned())
+}
+
+pub async fn get_response(addr: SocketAddr, path: &str) -> Response<Incoming> {
+    get_with_headers(addr, path, &[]).await
+}
+
+pub async fn get_with_headers(
+    addr: SocketAddr,
+    path: &str,
+    headers: &[(&str, &str)],
+) -> Response<Incoming> {
+    let client: Client<HttpConnector, Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+    let mut request = Request::get(format!("http://{}{}", addr, path));
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    client
+        .request(request.body(Empty::new()).unwrap())
+        .await
+        .unwrap()
+}
+
+/// A self-signed certificate for `server_names`, written as PEM files to a
+/// fresh temporary directory that is removed on drop.
+pub struct TestCertificate {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    pub der: CertificateDer<'static>,
+    _dir: TempDir,
+}
+
+impl TestCertificate {
+    pub fn generate(server_names: &[&str]) -> Self {
+        let dir = TempDir::with_prefix("load-balancer-test-").unwrap();
+        let certificate = Self {
+            cert_path: dir.path().join("cert.pem"),
+            key_path: dir.path().join("key.pem"),
+            der: CertificateDer::from(Vec::new()),
+            _dir: dir,
+        };
+        certificate.regenerate(server_names)
+    }
+
+    /// Overwrites the files with a new certificate for `server_names`.
+    pub fn regenerate(self, server_names: &[&str]) -> Self {
+        let names: Vec<String> = server_names.iter().map(|name| name.to_string()).collect();
+        let generated = rcgen::generate_simple_self_signed(names).unwrap();
+        std::fs::write(&self.cert_path, generated.cert.pem()).unwrap();
+        std::fs::write(&self.key_path, generated.signing_key.serialize_pem()).unwrap();
+        Self {
+            der: generated.cert.der().clone(),
+            ..self
+        }
+    }
+
+    pub fn tls_certificate(&self, server_names: &[&str]) -> TlsCertificate {
+        TlsCertificate {
+            cert_path: self.cert_path.clone(),
+            key_path: self.key_path.clone(),
+            server_names: server_names.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+}
+
+/// Opens a TLS connection to `addr` trusting only `roots`.
+pub async fn tls_connect(
+    addr: SocketAddr,
+    server_name: &str,
+    roots: &[&CertificateDer<'static>],
+    alpn: &[&[u8]],
+) -> std::io::Result<TlsStream<TcpStream>> {
+    tls_connect_as(addr, server_name, roots, alpn, None).await
+}
+
+/// Like [`tls_connect`], presenting `client` as the client certificate.
+pub async fn tls_connect_as(
+    addr: SocketAddr,
+    server_name: &str,
+    roots: &[&CertificateDer<'static>],
+    alpn: &[&[u8]],
+    client: Option<&TestCertificate>,
+) -> std::io::Result<TlsStream<TcpStream>> {
+    let mut root_store = RootCertStore::empty();
+    for root in roots {
+        root_store.add((*root).clone()).unwrap();
+    }
+    let builder =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(root_store);
+    let mut config = match client {
+        Some(client) => builder
+            .with_client_auth_cert(
+                vec![client.der.clone()],
+                PrivateKeyDer::from_pem_file(&client.key_path).unwrap(),
+            )
+            .unwrap(),
+        None => builder.with_no_client_auth(),
+    };
+    config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+
+    let stream = TcpStream::connect(addr).await?;
+    let server_name = ServerName::try_from(server_name.to_string()).unwrap();
+    TlsConnector::from(Arc::new(config))
+        .connect(server_name, stream)
+        .await
+}
@@ -0,0 +1,251 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use load_balancer::balancing_algorithms::{LeastConnectionsAlgorithm, RoundRobinAlgorithm};
+use load_balancer::{TcpProxy, TcpProxyConfig, Worker};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::support::spawn_tcp_echo_upstream;
+
+async fn spawn_proxy(proxy: TcpProxy) -> (Arc<TcpProxy>, SocketAddr) {
+    let proxy = Arc::new(proxy);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(proxy.clone().serve(listener, std::future::pending()));
+    (proxy, addr)
+}
+
+/// Connects through the proxy and returns the stream with the name of the
+/// worker that greeted it.
+async fn connect(addr: SocketAddr) -> (TcpStream, String) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut greeting = Vec::new();
+    loop {
+        let mut byte = [0];
+        stream.read_exact(&mut byte).await.unwrap();
+        if byte[0] == b'\n' {
+            break;
+        }
+        greeting.push(byte[0]);
+    }
+    (stream, String::from_utf8(greeting).unwrap())
+}
+
+async fn echo(stream: &mut TcpStream, message: &[u8]) {
+    stream.write_all(message).await.unwrap();
+    let mut echoed = vec![0; message.len()];
+    stream.read_exact(&mut echoed).await.unwrap();
+    assert_eq!(echoed, message);
+}
+
+async fn refused_worker() -> Worker {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+    Worker::new(addr.to_string())
+}
+
+#[tokio::test]
+async fn test_connections_are_balanced_across_workers() {
+    let workers = vec![
+        spawn_tcp_echo_upstream("a").await,
+        spawn_tcp_echo_upstream("b").await,
+    ];
+    let (_proxy, addr) = spawn_proxy(
+        TcpProxy::new(
+            workers,
+            Box::new(RoundRobinAlgorithm::new()),
+            TcpProxyConfig::default(),
+        )
+        .unwrap(),
+    )
+    .await;
+
+    let mut names = Vec::new();
+    for _ in 0..4 {
+        let (mut stream, name) = connect(addr).await;
+        echo(&mut stream, b"ping").await;
+        names.push(name);
+    }
+    assert_eq!(names, ["a", "b", "a", "b"]);
+}
+
+#[tokio::test]
+async fn test_byte_counters_track_each_connection() {
+    let worker = spawn_tcp_echo_upstream("a").await;
+    let host = worker.host.clone();
+    let (proxy, addr) = spawn_proxy(
+        TcpProxy::new(
+            vec![worker],
+            Box::new(RoundRobinAlgorithm::new()),
+            TcpProxyConfig::default(),
+        )
+        .unwrap(),
+    )
+    .await;
+
+    let (mut stream, _) = connect(addr).await;
+    echo(&mut stream, b"hello world").await;
+
+    let connections = proxy.connections();
+    assert_eq!(connections.len(), 1);
+    assert_eq!(connections[0].worker, host);
+    assert_eq!(connections[0].client_addr, stream.local_addr().unwrap());
+    assert_eq!(connections[0].bytes_to_worker, 11);
+    // The greeting "a\n" plus the echo
+    assert_eq!(connections[0].bytes_to_client, 13);
+
+    drop(stream);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(proxy.connections().is_empty());
+    let report = proxy.metrics_report();
+    assert!(report.contains("tcp_connections_total 1"), "{}", report);
+    assert!(report.contains("tcp_active_connections 0"), "{}", report);
+    assert!(report.contains("tcp_bytes_total{direction=\"to_worker\"} 11"));
+    assert!(report.contains("tcp_bytes_total{direction=\"to_client\"} 13"));
+}
+
+#[tokio::test]
+async fn test_idle_connections_are_closed() {
+    let (proxy, addr) = spawn_proxy(
+        TcpProxy::new(
+            vec![spawn_tcp_echo_upstream("a").await],
+            Box::new(RoundRobinAlgorithm::new()),
+            TcpProxyConfig {
+                idle_timeout: Some(Duration::from_millis(200)),
+                ..TcpProxyConfig::default()
+            },
+        )
+        .unwrap(),
+    )
+    .await;
+
+    let (mut stream, _) = connect(addr).await;
+    // Activity keeps the connection open past the timeout
+    for _ in 0..3 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        echo(&mut stream, b"ping").await;
+    }
+
+    let mut buf = [0; 16];
+    let read = tokio::time::timeout(Duration::from_secs(2), stream.read(&mut buf))
+        .await
+        .expect("idle connection was not closed");
+    assert!(matches!(read, Ok(0) | Err(_)));
+    assert!(proxy.metrics_report().contains("tcp_idle_timeouts_total 1"));
+    assert_eq!(proxy.active_connections(), 0);
+}
+
+#[tokio::test]
+async fn test_unreachable_worker_falls_back_to_the_next() {
+    let (proxy, addr) = spawn_proxy(
+        TcpProxy::new(
+            vec![refused_worker().await, spawn_tcp_echo_upstream("b").await],
+            Box::new(RoundRobinAlgorithm::new()),
+            TcpProxyConfig::default(),
+        )
+        .unwrap(),
+    )
+    .await;
+
+    for _ in 0..2 {
+        let (mut stream, name) = connect(addr).await;
+        assert_eq!(name, "b");
+        echo(&mut stream, b"ping").await;
+    }
+    // Each connection tried the refusing worker first
+    assert!(
+        proxy
+            .metrics_report()
+            .contains("tcp_connect_failures_total 2")
+    );
+}
+
+#[tokio::test]
+async fn test_connection_is_dropped_when_no_worker_is_reachable() {
+    let (proxy, addr) = spawn_proxy(
+        TcpProxy::new(
+            vec![refused_worker().await],
+            Box::new(RoundRobinAlgorithm::new()),
+            TcpProxyConfig::default(),
+        )
+        .unwrap(),
+    )
+    .await;
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut buf = [0; 16];
+    assert!(matches!(stream.read(&mut buf).await, Ok(0) | Err(_)));
+    assert_eq!(proxy.active_connections(), 0);
+}
+
+#[tokio::test]
+async fn test_least_connections_counts_open_sockets() {
+    let workers = vec![
+        spawn_tcp_echo_upstream("a").await,
+        spawn_tcp_echo_upstream("b").await,
+    ];
+    let algorithm = Box::new(LeastConnectionsAlgorithm::new(&workers));
+    let (_proxy, addr) =
+        spawn_proxy(TcpProxy::new(workers, algorithm, TcpProxyConfig::default()).unwrap()).await;
+
+    let (first, first_name) = connect(addr).await;
+    let (_second, second_name) = connect(addr).await;
+    assert_ne!(first_name, second_name);
+
+    // Closing the first socket frees its worker for the next connection
+    drop(first);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let (_third, third_name) = connect(addr).await;
+    assert_eq!(third_name, first_name);
+}
+
+#[tokio::test]
+async fn test_empty_worker_list_is_rejected() {
+    assert!(
+        TcpProxy::new(
+            Vec::new(),
+            Box::new(RoundRobinAlgorithm::new()),
+            TcpProxyConfig::default(),
+        )
+        .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_worker_that_stops_reading_hits_idle_timeout() {
+    // Accepts connections but never reads from them
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let worker = Worker::new(listener.local_addr().unwrap().to_string());
+    tokio::spawn(async move {
+        let mut held = Vec::new();
+        loop {
+            held.push(listener.accept().await.unwrap().0);
+        }
+    });
+    let (proxy, addr) = spawn_proxy(
+        TcpProxy::new(
+            vec![worker],
+            Box::new(RoundRobinAlgorithm::new()),
+            TcpProxyConfig {
+                idle_timeout: Some(Duration::from_millis(200)),
+                ..TcpProxyConfig::default()
+            },
+        )
+        .unwrap(),
+    )
+    .await;
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let chunk = vec![0; 64 * 1024];
+    // Fills the socket buffers until the proxy gives up on the connection
+    let written = tokio::time::timeout(Duration::from_secs(5), async {
+        while stream.write_all(&chunk).await.is_ok() {}
+    })
+    .await;
+
+    assert!(written.is_ok(), "connection outlived the idle timeout");
+    assert!(proxy.metrics_report().contains("tcp_idle_timeouts_total 1"));
+}
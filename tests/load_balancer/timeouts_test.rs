@@ -0,0 +1,107 @@
+use std::{sync::Arc, time::Duration};
+
+use http_body_util::BodyExt;
+use hyper::StatusCode;
+use load_balancer::balancing_algorithms::RoundRobinAlgorithm;
+use load_balancer::{LoadBalancer, LoadBalancerConfig, RouteTimeouts, TimeoutConfig, Worker};
+
+use crate::support::{get, get_response, spawn_balancer, spawn_stalling_upstream, spawn_upstream};
+
+fn load_balancer(workers: Vec<Worker>, config: LoadBalancerConfig) -> Arc<LoadBalancer> {
+    Arc::new(
+        LoadBalancer::with_config(workers, Box::new(RoundRobinAlgorithm::new()), config)
+            .expect("Failed to create load balancer"),
+    )
+}
+
+#[tokio::test]
+async fn test_first_byte_timeout_returns_gateway_timeout() {
+    let workers = vec![spawn_upstream("a").await];
+    let config = LoadBalancerConfig {
+        timeouts: TimeoutConfig {
+            first_byte: Some(Duration::from_millis(100)),
+            ..TimeoutConfig::default()
+        },
+        ..LoadBalancerConfig::default()
+    };
+    let load_balancer = load_balancer(workers, config);
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    let (status, _) = get(addr, "/slow").await;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    assert!(
+        load_balancer
+            .metrics_report()
+            .await
+            .contains("timeouts_total{kind=\"FirstByte\"} 1")
+    );
+}
+
+#[tokio::test]
+async fn test_total_timeout_returns_gateway_timeout() {
+    let workers = vec![spawn_upstream("a").await];
+    let config = LoadBalancerConfig {
+        timeouts: TimeoutConfig {
+            total: Some(Duration::from_millis(100)),
+            ..TimeoutConfig::default()
+        },
+        ..LoadBalancerConfig::default()
+    };
+    let load_balancer = load_balancer(workers, config);
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    let (status, _) = get(addr, "/slow").await;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    assert!(
+        load_balancer
+            .metrics_report()
+            .await
+            .contains("timeouts_total{kind=\"Total\"} 1")
+    );
+}
+
+#[tokio::test]
+async fn test_route_timeouts_override_defaults() {
+    let workers = vec![spawn_upstream("a").await];
+    let config = LoadBalancerConfig {
+        route_timeouts: vec![RouteTimeouts {
+            path_prefix: "/slow".to_string(),
+            timeouts: TimeoutConfig {
+                first_byte: Some(Duration::from_millis(100)),
+                idle_body: None,
+                total: None,
+            },
+        }],
+        ..LoadBalancerConfig::default()
+    };
+    let addr = spawn_balancer(load_balancer(workers, config)).await;
+
+    assert_eq!(get(addr, "/").await.0, StatusCode::OK);
+    assert_eq!(get(addr, "/slow").await.0, StatusCode::GATEWAY_TIMEOUT);
+}
+
+#[tokio::test]
+async fn test_idle_body_timeout_aborts_stalled_response() {
+    let workers = vec![spawn_stalling_upstream().await];
+    let config = LoadBalancerConfig {
+        timeouts: TimeoutConfig {
+            idle_body: Some(Duration::from_millis(100)),
+            ..TimeoutConfig::default()
+        },
+        ..LoadBalancerConfig::default()
+    };
+    let load_balancer = load_balancer(workers, config);
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    let response = get_response(addr, "/").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.into_body().collect().await.is_err());
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(
+        load_balancer
+            .metrics_report()
+            .await
+            .contains("timeouts_total{kind=\"IdleBody\"} 1")
+    );
+}
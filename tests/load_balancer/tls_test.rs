@@ -0,0 +1,390 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use http_body_util::{BodyExt, Empty};
+use hyper::{Request, StatusCode, body::Bytes, client::conn};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use load_balancer::balancing_algorithms::RoundRobinAlgorithm;
+use load_balancer::{
+    ClientAuthConfig, ClientIdentity, LoadBalancer, LoadBalancerConfig, RateLimitConfig,
+    RateLimitKey, RateLimitRule, ServerConfig, TlsConfig, TlsTermination, TlsVersion,
+};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+
+use crate::support::{
+    TestCertificate, spawn_balancer_with_config, spawn_header_echo_upstream, spawn_upstream,
+    tls_connect, tls_connect_as,
+};
+
+async fn start_tls_balancer(tls: TlsTermination) -> SocketAddr {
+    let load_balancer = Arc::new(
+        LoadBalancer::new(
+            vec![spawn_upstream("a").await],
+            Box::new(RoundRobinAlgorithm::new()),
+        )
+        .expect("Failed to create load balancer"),
+    );
+    start_balancer_with_tls(load_balancer, tls).await
+}
+
+async fn start_balancer_with_tls(
+    load_balancer: Arc<LoadBalancer>,
+    tls: TlsTermination,
+) -> SocketAddr {
+    spawn_balancer_with_config(
+        load_balancer,
+        ServerConfig {
+            tls: Some(tls),
+            ..ServerConfig::default()
+        },
+    )
+    .await
+}
+
+fn peer_certificate(stream: &TlsStream<TcpStream>) -> Vec<u8> {
+    let (_, connection) = stream.get_ref();
+    connection.peer_certificates().unwrap()[0].to_vec()
+}
+
+#[tokio::test]
+async fn test_https_requests_over_http1_and_h2() {
+    let certificate = TestCertificate::generate(&["localhost"]);
+    let tls = TlsTermination::new(TlsConfig {
+        certificates: vec![certificate.tls_certificate(&["localhost"])],
+        ..TlsConfig::default()
+    })
+    .unwrap();
+    let addr = start_tls_balancer(tls).await;
+
+    let stream = tls_connect(addr, "localhost", &[&certificate.der], &[b"http/1.1"])
+        .await
+        .unwrap();
+    let (mut sender, connection) = conn::http1::handshake(TokioIo::new(stream)).await.unwrap();
+    tokio::spawn(connection);
+    let request = Request::get("/")
+        .header("host", "localhost")
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+    let response = sender.send_request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"a");
+
+    let stream = tls_connect(
+        addr,
+        "localhost",
+        &[&certificate.der],
+        &[b"h2", b"http/1.1"],
+    )
+    .await
+    .unwrap();
+    assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+    let (mut sender, connection) =
+        conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+            .await
+            .unwrap();
+    tokio::spawn(connection);
+    let request = Request::get("https://localhost/")
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+    let response = sender.send_request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_sni_selects_certificate() {
+    let first = TestCertificate::generate(&["a.test"]);
+    let second = TestCertificate::generate(&["b.test", "x.b.test"]);
+    let tls = TlsTermination::new(TlsConfig {
+        certificates: vec![
+            first.tls_certificate(&["a.test"]),
+            second.tls_certificate(&["b.test", "*.b.test"]),
+        ],
+        ..TlsConfig::default()
+    })
+    .unwrap();
+    let addr = start_tls_balancer(tls).await;
+    let roots = [&first.der, &second.der];
+
+    let stream = tls_connect(addr, "b.test", &roots, &[]).await.unwrap();
+    assert_eq!(peer_certificate(&stream), second.der.to_vec());
+    let stream = tls_connect(addr, "x.b.test", &roots, &[]).await.unwrap();
+    assert_eq!(peer_certificate(&stream), second.der.to_vec());
+    let stream = tls_connect(addr, "a.test", &roots, &[]).await.unwrap();
+    assert_eq!(peer_certificate(&stream), first.der.to_vec());
+}
+
+#[tokio::test]
+async fn test_reload_serves_new_certificate() {
+    let certificate = TestCertificate::generate(&["localhost"]);
+    let tls = TlsTermination::new(TlsConfig {
+        certificates: vec![certificate.tls_certificate(&["localhost"])],
+        ..TlsConfig::default()
+    })
+    .unwrap();
+    let addr = start_tls_balancer(tls.clone()).await;
+    let old_der = certificate.der.clone();
+
+    let certificate = certificate.regenerate(&["localhost"]);
+    // Still the old certificate until reloaded
+    let stream = tls_connect(addr, "localhost", &[&old_der], &[])
+        .await
+        .unwrap();
+    assert_eq!(peer_certificate(&stream), old_der.to_vec());
+
+    tls.reload().unwrap();
+    let stream = tls_connect(addr, "localhost", &[&certificate.der], &[])
+        .await
+        .unwrap();
+    assert_eq!(peer_certificate(&stream), certificate.der.to_vec());
+}
+
+#[tokio::test]
+async fn test_versions_and_cipher_suites_are_restricted() {
+    let certificate = TestCertificate::generate(&["localhost"]);
+    let tls = TlsTermination::new(TlsConfig {
+        certificates: vec![certificate.tls_certificate(&["localhost"])],
+        versions: vec![TlsVersion::Tls13],
+        cipher_suites: vec!["TLS13_CHACHA20_POLY1305_SHA256".to_string()],
+        ..TlsConfig::default()
+    })
+    .unwrap();
+    let addr = start_tls_balancer(tls).await;
+
+    let stream = tls_connect(addr, "localhost", &[&certificate.der], &[])
+        .await
+        .unwrap();
+    let (_, connection) = stream.get_ref();
+    assert_eq!(
+        connection.protocol_version(),
+        Some(rustls::ProtocolVersion::TLSv1_3)
+    );
+    assert_eq!(
+        format!(
+            "{:?}",
+            connection.negotiated_cipher_suite().unwrap().suite()
+        ),
+        "TLS13_CHACHA20_POLY1305_SHA256"
+    );
+
+    let invalid = TlsTermination::new(TlsConfig {
+        certificates: vec![certificate.tls_certificate(&["localhost"])],
+        cipher_suites: vec!["TLS_NOT_A_SUITE".to_string()],
+        ..TlsConfig::default()
+    });
+    assert!(invalid.is_err());
+}
+
+/// Terminates TLS with a `localhost` certificate, trusting client
+/// certificates issued by `client_ca`.
+fn mtls_termination(
+    certificate: &TestCertificate,
+    client_ca: &TestCertificate,
+    required: bool,
+) -> TlsTermination {
+    TlsTermination::new(TlsConfig {
+        certificates: vec![certificate.tls_certificate(&["localhost"])],
+        client_auth: Some(ClientAuthConfig {
+            required,
+            ..ClientAuthConfig::new(&client_ca.cert_path)
+        }),
+        ..TlsConfig::default()
+    })
+    .unwrap()
+}
+
+async fn https_get(
+    stream: TlsStream<TcpStream>,
+    headers: &[(&str, &str)],
+) -> Result<(StatusCode, String), hyper::Error> {
+    let (mut sender, connection) = conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(connection);
+    let mut request = Request::get("/").header("host", "localhost");
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let response = sender
+        .send_request(request.body(Empty::<Bytes>::new()).unwrap())
+        .await?;
+    let status = response.status();
+    let body = response.into_body().collect().await?.to_bytes();
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+}
+
+#[tokio::test]
+async fn test_required_client_certificate_is_verified_and_forwarded() {
+    let certificate = TestCertificate::generate(&["localhost"]);
+    let client = TestCertificate::generate(&["billing.internal"]);
+    let stranger = TestCertificate::generate(&["stranger.internal"]);
+    let load_balancer = Arc::new(
+        LoadBalancer::new(
+            vec![spawn_header_echo_upstream("x-forwarded-client-cert").await],
+            Box::new(RoundRobinAlgorithm::new()),
+        )
+        .expect("Failed to create load balancer"),
+    );
+    let addr =
+        start_balancer_with_tls(load_balancer, mtls_termination(&certificate, &client, true)).await;
+    let roots = [&certificate.der];
+
+    let stream = tls_connect_as(addr, "localhost", &roots, &[], Some(&client))
+        .await
+        .unwrap();
+    let (status, body) = https_get(stream, &[]).await.unwrap();
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.starts_with("Subject=\""), "{}", body);
+    assert!(body.ends_with(";DNS=billing.internal"), "{}", body);
+
+    // With TLS 1.3 the client only learns of the rejection on its first read
+    for client in [None, Some(&stranger)] {
+        let rejected = match tls_connect_as(addr, "localhost", &roots, &[], client).await {
+            Ok(stream) => https_get(stream, &[]).await.is_err(),
+            Err(_) => true,
+        };
+        assert!(rejected);
+    }
+}
+
+#[tokio::test]
+async fn test_optional_client_certificate_header_cannot_be_spoofed() {
+    let certificate = TestCertificate::generate(&["localhost"]);
+    let client = TestCertificate::generate(&["billing.internal"]);
+    let load_balancer = Arc::new(
+        LoadBalancer::new(
+            vec![spawn_header_echo_upstream("x-forwarded-client-cert").await],
+            Box::new(RoundRobinAlgorithm::new()),
+        )
+        .expect("Failed to create load balancer"),
+    );
+    let addr = start_balancer_with_tls(
+        load_balancer,
+        mtls_termination(&certificate, &client, false),
+    )
+    .await;
+
+    let stream = tls_connect(addr, "localhost", &[&certificate.der], &[])
+        .await
+        .unwrap();
+    let spoofed = [("x-forwarded-client-cert", "Subject=\"CN=admin\"")];
+    assert_eq!(
+        https_get(stream, &spoofed).await.unwrap(),
+        (StatusCode::OK, "-".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_reload_picks_up_new_client_ca() {
+    let certificate = TestCertificate::generate(&["localhost"]);
+    let old_client = TestCertificate::generate(&["billing.internal"]);
+    let new_client = TestCertificate::generate(&["payments.internal"]);
+    let load_balancer = Arc::new(
+        LoadBalancer::new(
+            vec![spawn_upstream("a").await],
+            Box::new(RoundRobinAlgorithm::new()),
+        )
+        .expect("Failed to create load balancer"),
+    );
+    let tls = mtls_termination(&certificate, &old_client, true);
+    let addr = start_balancer_with_tls(load_balancer, tls.clone()).await;
+    let roots = [&certificate.der];
+    let accepted = |client| async move {
+        match tls_connect_as(addr, "localhost", &roots, &[], Some(client)).await {
+            Ok(stream) => https_get(stream, &[]).await.is_ok(),
+            Err(_) => false,
+        }
+    };
+
+    // The bundle is only read again on reload
+    std::fs::copy(&new_client.cert_path, &old_client.cert_path).unwrap();
+    assert!(accepted(&old_client).await);
+    assert!(!accepted(&new_client).await);
+
+    tls.reload().unwrap();
+    assert!(!accepted(&old_client).await);
+    assert!(accepted(&new_client).await);
+}
+
+#[test]
+fn test_client_identity_quotes_names_that_would_split_the_header() {
+    let identity = ClientIdentity {
+        subject: "CN=a \"b\"".to_string(),
+        dns_names: vec!["plain.internal".to_string(), "a;b,c".to_string()],
+        uris: vec!["spiffe://example/\"x\"".to_string()],
+    };
+    assert_eq!(
+        identity.to_string(),
+        r#"Subject="CN=a \"b\"";DNS=plain.internal;DNS="a;b,c";URI="spiffe://example/\"x\"""#
+    );
+}
+
+#[tokio::test]
+async fn test_client_identity_is_available_to_rate_limits() {
+    let certificate = TestCertificate::generate(&["localhost"]);
+    let client = TestCertificate::generate(&["billing.internal"]);
+    let config = LoadBalancerConfig {
+        rate_limit: RateLimitConfig {
+            rules: vec![RateLimitRule {
+                path_prefix: "/".to_string(),
+                key: RateLimitKey::ClientIdentity,
+                burst: 1,
+                refill_per_sec: 0.001,
+            }],
+        },
+        ..LoadBalancerConfig::default()
+    };
+    let load_balancer = Arc::new(
+        LoadBalancer::with_config(
+            vec![spawn_upstream("a").await],
+            Box::new(RoundRobinAlgorithm::new()),
+            config,
+        )
+        .expect("Failed to create load balancer"),
+    );
+    let addr = start_balancer_with_tls(
+        load_balancer,
+        mtls_termination(&certificate, &client, false),
+    )
+    .await;
+    let roots = [&certificate.der];
+
+    let mut statuses = Vec::new();
+    for _ in 0..2 {
+        let stream = tls_connect_as(addr, "localhost", &roots, &[], Some(&client))
+            .await
+            .unwrap();
+        statuses.push(https_get(stream, &[]).await.unwrap().0);
+    }
+    assert_eq!(statuses, [StatusCode::OK, StatusCode::TOO_MANY_REQUESTS]);
+
+    // Anonymous clients aren't limited by the rule
+    for _ in 0..2 {
+        let stream = tls_connect(addr, "localhost", &roots, &[]).await.unwrap();
+        assert_eq!(https_get(stream, &[]).await.unwrap().0, StatusCode::OK);
+    }
+}
+
+#[tokio::test]
+async fn test_forwarded_proto_is_https() {
+    let certificate = TestCertificate::generate(&["localhost"]);
+    let load_balancer = Arc::new(
+        LoadBalancer::new(
+            vec![spawn_header_echo_upstream("x-forwarded-proto").await],
+            Box::new(RoundRobinAlgorithm::new()),
+        )
+        .expect("Failed to create load balancer"),
+    );
+    let tls = TlsTermination::new(TlsConfig {
+        certificates: vec![certificate.tls_certificate(&["localhost"])],
+        ..TlsConfig::default()
+    })
+    .unwrap();
+    let addr = start_balancer_with_tls(load_balancer, tls).await;
+
+    let stream = tls_connect(addr, "localhost", &[&certificate.der], &[])
+        .await
+        .unwrap();
+    assert_eq!(
+        https_get(stream, &[]).await.unwrap(),
+        (StatusCode::OK, "https".to_string())
+    );
+}
@@ -0,0 +1,175 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use load_balancer::balancing_algorithms::{LeastConnectionsAlgorithm, RoundRobinAlgorithm};
+use load_balancer::{UdpProxy, UdpProxyConfig, Worker};
+use tokio::net::UdpSocket;
+
+use crate::support::spawn_udp_echo_upstream;
+
+async fn spawn_proxy(proxy: UdpProxy) -> (Arc<UdpProxy>, SocketAddr) {
+    let proxy = Arc::new(proxy);
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(proxy.clone().serve(socket, std::future::pending()));
+    (proxy, addr)
+}
+
+async fn client(proxy_addr: SocketAddr) -> UdpSocket {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.connect(proxy_addr).await.unwrap();
+    socket
+}
+
+/// Sends `message` and returns the reply.
+async fn exchange(socket: &UdpSocket, message: &str) -> String {
+    socket.send(message.as_bytes()).await.unwrap();
+    let mut buf = [0; 1024];
+    let n = tokio::time::timeout(Duration::from_secs(2), socket.recv(&mut buf))
+        .await
+        .expect("no reply")
+        .unwrap();
+    String::from_utf8(buf[..n].to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn test_client_sticks_to_its_worker() {
+    let workers = vec![
+        spawn_udp_echo_upstream("a").await,
+        spawn_udp_echo_upstream("b").await,
+    ];
+    let (proxy, addr) = spawn_proxy(
+        UdpProxy::new(
+            workers,
+            Box::new(RoundRobinAlgorithm::new()),
+            UdpProxyConfig::default(),
+        )
+        .await
+        .unwrap(),
+    )
+    .await;
+
+    let first = client(addr).await;
+    let second = client(addr).await;
+    assert_eq!(exchange(&first, "one").await, "a:one");
+    assert_eq!(exchange(&second, "two").await, "b:two");
+    assert_eq!(exchange(&first, "three").await, "a:three");
+    assert_eq!(exchange(&second, "four").await, "b:four");
+
+    assert_eq!(proxy.active_sessions(), 2);
+    let mut sessions = proxy.sessions();
+    sessions.sort_by_key(|session| session.client_addr == second.local_addr().unwrap());
+    assert_eq!(sessions[0].client_addr, first.local_addr().unwrap());
+    assert_eq!(sessions[0].bytes_to_worker, 8);
+    assert_eq!(sessions[0].bytes_to_client, 12);
+    let report = proxy.metrics_report();
+    assert!(report.contains("udp_sessions_total 2"), "{}", report);
+    assert!(report.contains("udp_bytes_total{direction=\"to_worker\"} 15"));
+}
+
+#[tokio::test]
+async fn test_idle_sessions_expire_and_rebalance() {
+    let workers = vec![
+        spawn_udp_echo_upstream("a").await,
+        spawn_udp_echo_upstream("b").await,
+    ];
+    let algorithm = Box::new(LeastConnectionsAlgorithm::new(&workers));
+    let (proxy, addr) = spawn_proxy(
+        UdpProxy::new(
+            workers,
+            algorithm,
+            UdpProxyConfig {
+                session_timeout: Duration::from_millis(200),
+                ..UdpProxyConfig::default()
+            },
+        )
+        .await
+        .unwrap(),
+    )
+    .await;
+
+    let first = client(addr).await;
+    let second = client(addr).await;
+    assert_eq!(exchange(&first, "x").await, "a:x");
+    assert_eq!(exchange(&second, "x").await, "b:x");
+
+    // Traffic keeps the first session alive while the second goes idle
+    for _ in 0..4 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(exchange(&first, "x").await, "a:x");
+    }
+    assert_eq!(proxy.active_sessions(), 1);
+    assert!(
+        proxy
+            .metrics_report()
+            .contains("udp_expired_sessions_total 1")
+    );
+
+    // The expired session's worker is free again for a new client
+    let third = client(addr).await;
+    assert_eq!(exchange(&third, "x").await, "b:x");
+}
+
+#[tokio::test]
+async fn test_empty_worker_list_is_rejected() {
+    assert!(
+        UdpProxy::new(
+            Vec::new(),
+            Box::new(RoundRobinAlgorithm::new()),
+            UdpProxyConfig::default(),
+        )
+        .await
+        .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_unresolvable_worker_is_rejected() {
+    assert!(
+        UdpProxy::new(
+            vec![Worker::new("not a host")],
+            Box::new(RoundRobinAlgorithm::new()),
+            UdpProxyConfig::default(),
+        )
+        .await
+        .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_new_clients_are_dropped_at_the_session_limit() {
+    let workers = vec![spawn_udp_echo_upstream("a").await];
+    let (proxy, addr) = spawn_proxy(
+        UdpProxy::new(
+            workers,
+            Box::new(RoundRobinAlgorithm::new()),
+            UdpProxyConfig {
+                max_sessions: 1,
+                ..UdpProxyConfig::default()
+            },
+        )
+        .await
+        .unwrap(),
+    )
+    .await;
+
+    let first = client(addr).await;
+    let second = client(addr).await;
+    assert_eq!(exchange(&first, "one").await, "a:one");
+    second.send(b"two").await.unwrap();
+    let mut buf = [0; 1024];
+    assert!(
+        tokio::time::timeout(Duration::from_millis(200), second.recv(&mut buf))
+            .await
+            .is_err()
+    );
+    // The existing session keeps working
+    assert_eq!(exchange(&first, "three").await, "a:three");
+
+    assert_eq!(proxy.active_sessions(), 1);
+    let report = proxy.metrics_report();
+    assert!(
+        report.contains("udp_dropped_datagrams_total 1"),
+        "{}",
+        report
+    );
+}
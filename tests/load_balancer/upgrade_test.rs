@@ -0,0 +1,79 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use hyper::StatusCode;
+use load_balancer::LoadBalancer;
+use load_balancer::balancing_algorithms::RoundRobinAlgorithm;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::support::{get, spawn_balancer, spawn_upgrade_echo_upstream};
+
+async fn start_balancer() -> (Arc<LoadBalancer>, SocketAddr, String) {
+    let worker = spawn_upgrade_echo_upstream().await;
+    let host = worker.host.clone();
+    let load_balancer = Arc::new(
+        LoadBalancer::new(vec![worker], Box::new(RoundRobinAlgorithm::new()))
+            .expect("Failed to create load balancer"),
+    );
+    let addr = spawn_balancer(load_balancer.clone()).await;
+    (load_balancer, addr, host)
+}
+
+/// Sends an upgrade request and returns the connection with the response
+/// head, once it has been read in full.
+async fn upgrade(addr: SocketAddr, protocol: &str) -> (TcpStream, String) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!(
+        "GET /ws HTTP/1.1\r\nhost: localhost\r\nconnection: upgrade\r\nupgrade: {}\r\n\r\n",
+        protocol
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        stream.read_exact(&mut byte).await.unwrap();
+        head.push(byte[0]);
+    }
+    (stream, String::from_utf8(head).unwrap())
+}
+
+#[tokio::test]
+async fn test_upgraded_connection_is_spliced_to_the_worker() {
+    let (load_balancer, addr, host) = start_balancer().await;
+
+    let (mut stream, head) = upgrade(addr, "echo").await;
+    assert!(head.starts_with("HTTP/1.1 101"), "{}", head);
+    assert!(head.to_ascii_lowercase().contains("upgrade: echo"));
+
+    for message in [&b"ping"[..], b"pong"] {
+        stream.write_all(message).await.unwrap();
+        let mut echoed = vec![0; message.len()];
+        stream.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(echoed, message);
+    }
+    assert!(
+        load_balancer
+            .metrics_report()
+            .await
+            .contains("upgraded_connections_total 1")
+    );
+
+    // The tunnel counts as active on its worker until it closes
+    assert_eq!(load_balancer.drain_status(&host).unwrap().in_flight, 1);
+    drop(stream);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(load_balancer.drain_status(&host).unwrap().in_flight, 0);
+}
+
+#[tokio::test]
+async fn test_refused_upgrade_is_an_ordinary_response() {
+    let (load_balancer, addr, host) = start_balancer().await;
+
+    let (_stream, head) = upgrade(addr, "websocket").await;
+    assert!(head.starts_with("HTTP/1.1 426"), "{}", head);
+    assert_eq!(get(addr, "/").await.0, StatusCode::UPGRADE_REQUIRED);
+    assert_eq!(load_balancer.drain_status(&host).unwrap().in_flight, 0);
+}
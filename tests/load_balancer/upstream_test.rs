@@ -0,0 +1,101 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use hyper::StatusCode;
+use load_balancer::balancing_algorithms::{LeastConnectionsAlgorithm, RoundRobinAlgorithm};
+use load_balancer::{LoadBalancer, LoadBalancerConfig, UpstreamPoolConfig};
+
+use crate::support::{
+    get, get_response, spawn_balancer, spawn_http2_upstream, spawn_stalling_upstream,
+    spawn_upstream,
+};
+
+async fn http2_balancer(upstream_pool: UpstreamPoolConfig) -> (Arc<LoadBalancer>, SocketAddr) {
+    let workers = vec![spawn_http2_upstream("a").await];
+    let config = LoadBalancerConfig {
+        upstream_pool,
+        queue: None,
+        ..LoadBalancerConfig::default()
+    };
+    let load_balancer = Arc::new(
+        LoadBalancer::with_config(workers, Box::new(RoundRobinAlgorithm::new()), config)
+            .expect("Failed to create load balancer"),
+    );
+    let addr = spawn_balancer(load_balancer.clone()).await;
+    (load_balancer, addr)
+}
+
+#[tokio::test]
+async fn test_http2_workers_are_reached_over_http2() {
+    let (_load_balancer, addr) = http2_balancer(UpstreamPoolConfig::default()).await;
+
+    assert_eq!(
+        get(addr, "/").await,
+        (StatusCode::OK, "a HTTP/2.0".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_http2_streams_are_multiplexed_up_to_the_pool_limit() {
+    let (load_balancer, addr) = http2_balancer(UpstreamPoolConfig {
+        http2_connections_per_worker: 1,
+        max_streams_per_connection: 2,
+        ..UpstreamPoolConfig::default()
+    })
+    .await;
+
+    let slow: Vec<_> = (0..2)
+        .map(|_| tokio::spawn(async move { get(addr, "/slow").await.0 }))
+        .collect();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert!(
+        load_balancer
+            .metrics_report()
+            .await
+            .contains("upstream_http2_streams{worker=")
+    );
+    assert_eq!(get(addr, "/").await.0, StatusCode::SERVICE_UNAVAILABLE);
+    for request in slow {
+        assert_eq!(request.await.unwrap(), StatusCode::OK);
+    }
+    assert_eq!(get(addr, "/").await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_least_connections_counts_requests_until_body_finishes() {
+    let stalling = spawn_stalling_upstream().await;
+    let stalling_host = stalling.host.clone();
+    let workers = vec![stalling, spawn_upstream("a").await];
+    let load_balancer = Arc::new(
+        LoadBalancer::new(
+            workers.clone(),
+            Box::new(LeastConnectionsAlgorithm::new(&workers)),
+        )
+        .expect("Failed to create load balancer"),
+    );
+    let addr = spawn_balancer(load_balancer.clone()).await;
+
+    // The stalling worker sends its headers and then half of its body
+    let streaming = get_response(addr, "/").await;
+    assert_eq!(streaming.status(), StatusCode::OK);
+    assert_eq!(
+        load_balancer
+            .drain_status(&stalling_host)
+            .unwrap()
+            .in_flight,
+        1
+    );
+
+    assert_eq!(get(addr, "/").await.1, "a");
+    assert_eq!(get(addr, "/").await.1, "a");
+
+    drop(streaming);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(
+        load_balancer
+            .drain_status(&stalling_host)
+            .unwrap()
+            .in_flight,
+        0
+    );
+}
@@ -0,0 +1,121 @@
+use std::sync::Arc;
+
+use hyper::StatusCode;
+use load_balancer::balancing_algorithms::RoundRobinAlgorithm;
+use load_balancer::{
+    ClientCertificate, LoadBalancer, LoadBalancerConfig, RetryConfig, UpstreamTlsConfig, Worker,
+};
+
+use crate::support::{TestCertificate, get, spawn_balancer, spawn_https_upstream, spawn_upstream};
+
+fn load_balancer(workers: Vec<Worker>) -> Arc<LoadBalancer> {
+    let config = LoadBalancerConfig {
+        retries: RetryConfig::default(),
+        circuit_breaker: None,
+        ..LoadBalancerConfig::default()
+    };
+    Arc::new(
+        LoadBalancer::with_config(workers, Box::new(RoundRobinAlgorithm::new()), config)
+            .expect("Failed to create load balancer"),
+    )
+}
+
+fn trusting(certificate: &TestCertificate) -> UpstreamTlsConfig {
+    UpstreamTlsConfig {
+        ca_bundle: Some(certificate.cert_path.clone()),
+        ..UpstreamTlsConfig::default()
+    }
+}
+
+#[tokio::test]
+async fn test_https_worker_trusted_through_ca_bundle() {
+    let certificate = TestCertificate::generate(&["localhost"]);
+    let addr = spawn_https_upstream("a", &certificate, None).await;
+    let worker =
+        Worker::new(format!("https://localhost:{}", addr.port())).with_tls(trusting(&certificate));
+    let addr = spawn_balancer(load_balancer(vec![worker])).await;
+
+    assert_eq!(
+        get(addr, "/").await,
+        (StatusCode::OK, "a localhost".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_untrusted_worker_certificate_fails_unless_verification_is_off() {
+    let certificate = TestCertificate::generate(&["localhost"]);
+    let upstream = spawn_https_upstream("a", &certificate, None).await;
+    let host = format!("https://localhost:{}", upstream.port());
+
+    // The certificate isn't in the Mozilla roots, so the request falls
+    // through to the plain worker
+    let workers = vec![Worker::new(host.clone()), spawn_upstream("b").await];
+    let balancer = load_balancer(workers);
+    let addr = spawn_balancer(balancer.clone()).await;
+    assert_eq!(get(addr, "/").await, (StatusCode::OK, "b".to_string()));
+    assert!(balancer.metrics_report().await.contains("retries_total 1"));
+
+    let worker = Worker::new(host).with_tls(UpstreamTlsConfig {
+        verify: false,
+        ..UpstreamTlsConfig::default()
+    });
+    let addr = spawn_balancer(load_balancer(vec![worker])).await;
+    assert_eq!(
+        get(addr, "/").await,
+        (StatusCode::OK, "a localhost".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_server_name_overrides_sni_and_verification() {
+    let certificate = TestCertificate::generate(&["backend.internal"]);
+    let addr = spawn_https_upstream("a", &certificate, None).await;
+    let worker = Worker::new(format!("https://{}", addr)).with_tls(UpstreamTlsConfig {
+        server_name: Some("backend.internal".to_string()),
+        ..trusting(&certificate)
+    });
+    let addr = spawn_balancer(load_balancer(vec![worker])).await;
+
+    assert_eq!(
+        get(addr, "/").await,
+        (StatusCode::OK, "a backend.internal".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_client_certificate_is_presented_to_workers_requiring_one() {
+    let certificate = TestCertificate::generate(&["localhost"]);
+    let client = TestCertificate::generate(&["load-balancer"]);
+    let upstream = spawn_https_upstream("a", &certificate, Some(&client.der)).await;
+    let host = format!("https://localhost:{}", upstream.port());
+
+    let workers = vec![
+        Worker::new(host.clone()).with_tls(trusting(&certificate)),
+        spawn_upstream("b").await,
+    ];
+    let addr = spawn_balancer(load_balancer(workers)).await;
+    assert_eq!(get(addr, "/").await, (StatusCode::OK, "b".to_string()));
+
+    let worker = Worker::new(host).with_tls(UpstreamTlsConfig {
+        client_certificate: Some(ClientCertificate {
+            cert_path: client.cert_path.clone(),
+            key_path: client.key_path.clone(),
+        }),
+        ..trusting(&certificate)
+    });
+    let addr = spawn_balancer(load_balancer(vec![worker])).await;
+    assert_eq!(
+        get(addr, "/").await,
+        (StatusCode::OK, "a localhost".to_string())
+    );
+}
+
+#[test]
+fn test_unreadable_ca_bundle_is_rejected() {
+    let worker = Worker::new("https://localhost:1").with_tls(UpstreamTlsConfig {
+        ca_bundle: Some("/nonexistent/ca.pem".into()),
+        ..UpstreamTlsConfig::default()
+    });
+
+    assert!(LoadBalancer::new(vec![worker], Box::new(RoundRobinAlgorithm::new())).is_err());
+}
@@ -0,0 +1,15 @@
+//! Installs the global logger, so it runs in its own test binary rather than
+//! alongside the other tests.
+
+use load_balancer::{LogConfig, LogFormat};
+
+#[test]
+fn test_logger_is_installed_once() {
+    let config = LogConfig {
+        filter: "warn,load_balancer::tcp_proxy=debug".to_string(),
+        format: LogFormat::Text,
+    };
+
+    assert!(config.init().is_ok());
+    assert!(config.init().is_err());
+}